@@ -0,0 +1,18 @@
+fn main() {
+    // OpenSBI starts the next stage 2 MiB into RAM, in S-mode.
+    if std::env::var_os("CARGO_FEATURE_OPENSBI").is_some() {
+        println!("cargo:rustc-link-arg=--defsym=__kernel_base=0x80200000");
+        println!("cargo:rustc-link-arg=--entry=_supervisor_entry");
+    }
+    println!("cargo:rerun-if-changed=misc/lds/kernel.ld");
+
+    // A cpio archive to build in as the initramfs, see `fs::initramfs`.
+    println!("cargo:rustc-check-cfg=cfg(initramfs)");
+    println!("cargo:rerun-if-env-changed=WALNUT_INITRAMFS");
+    if let Some(path) = std::env::var_os("WALNUT_INITRAMFS") {
+        let path = std::fs::canonicalize(&path).expect("WALNUT_INITRAMFS has to name an existing file");
+        println!("cargo:rustc-cfg=initramfs");
+        println!("cargo:rustc-env=WALNUT_INITRAMFS={}", path.display());
+        println!("cargo:rerun-if-changed={}", path.display());
+    }
+}
@@ -36,11 +36,30 @@ fi
 
 
 
-# Build common QEMU command components
-QEMU_BASE="qemu-system-riscv64 -machine virt -cpu rv64 -smp 4 -m 128M -nographic -serial mon:stdio -bios none -device virtio-keyboard-device -kernel"
-
 EXPECTED_TARGET_PATH=$SCRIPT_DIR/../../target/riscv64gc-unknown-none-elf/debug/walnut
 
+# Kernels built with the `opensbi` feature are linked at 0x80200000 and
+# expect to be started in S-mode by QEMU's bundled OpenSBI, everything
+# else brings its own M-mode firmware and runs with `-bios none`.
+function bios_for() {
+    local entry
+    entry=$(od -An -t x8 -j 24 -N 8 "$1" | tr -d ' ')
+    if [[ $entry == "0000000080200000" ]]; then
+        echo "default"
+    else
+        echo "none"
+    fi
+}
+
+if [[ $MODE == "run" ]]; then
+    BIOS=$(bios_for "$1")
+else
+    BIOS=$(bios_for "$EXPECTED_TARGET_PATH")
+fi
+
+# Build common QEMU command components
+QEMU_BASE="qemu-system-riscv64 -machine virt -cpu rv64 -smp 4 -m 128M -nographic -serial mon:stdio -bios $BIOS -device virtio-keyboard-device -kernel"
+
 if [[ $MODE == "run" ]]; then
     COMMAND="$QEMU_BASE $@"  # Pass additional arguments for run mode
 elif [[ $MODE == "debug" ]]; then
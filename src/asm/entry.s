@@ -1,25 +1,37 @@
-.section .bss
-	.equ CPU_CNT, 4
-.align 16
-	stack0: .space 4096 * CPU_CNT
+.section .bss.firmware
+	.equ CPU_CNT, {MAX_HARTS}
+	.equ FIRMWARE_STACK_SIZE, 4096 * 2
+.balign 16
+	firmware_stacks: .space FIRMWARE_STACK_SIZE * CPU_CNT
 
-.section .text.init
+# This has to be the very first thing at 0x80000000,
+# see `kernel.ld`
+.section .text.init.reset
 .global _entry
 
-# Entry point of the operating system
-# All this does is load the stack pointer from
-# where its calculated to be at compile-time (see kernel.ld)
+# Entry point of the operating system, in M-mode.
+# QEMU's reset vector leaves the hart id in a0 and
+# the address of the device tree blob in a1.
 # Each hart will run here.
 _entry:
-	# Index into the STACK0 byte array as
-	# defined in `init/mod.rs`
-	la sp, __kernel_stack_end 
-        li a0, 1024*4
-        csrr a1, mhartid
-        addi a1, a1, 1
-        mul a0, a0, a1
-        sub sp, sp, a0
+	# There is no firmware stack nor hart slot for harts past
+	# `cpu::MAX_HARTS`, they stay parked here for good
+	csrr t0, mhartid
+	li t1, CPU_CNT
+	bgeu t0, t1, spin
+
+	# Each hart gets a slice of `firmware_stacks`, whose top
+	# is also kept in mscratch for the machine trap vector.
+	addi t0, t0, 1
+	li t1, FIRMWARE_STACK_SIZE
+	mul t0, t0, t1
+	la sp, firmware_stacks
+	add sp, sp, t0
+	csrw mscratch, sp
+
+	csrr a0, mhartid
 	call kinit
 
 spin:
+	wfi
 	j spin
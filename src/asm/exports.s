@@ -20,9 +20,25 @@ KERNEL_STACK_END:
 KERNEL_STACK_SIZE:
     .dword __kernel_stack_size
 
+    .global FIRMWARE_START
+FIRMWARE_START:
+    .dword __firmware_start
+
+    .global FIRMWARE_END
+FIRMWARE_END:
+    .dword __firmware_end
+
     .global TEXT_START
 TEXT_START:
     .dword stext
     .global TEXT_END
 TEXT_END:
     .dword etext
+
+    .global RODATA_START
+RODATA_START:
+    .dword srodata
+
+    .global DATA_START
+DATA_START:
+    .dword sdata
@@ -1,5 +1,29 @@
-use core::arch::global_asm;
+use core::{arch::global_asm, mem::{offset_of, size_of}};
 
-global_asm!(include_str!("entry.s"));
-global_asm!(include_str!("trap.s"));
+use crate::cpu::{hart::Hart, trap::TrapFrame};
+
+#[cfg(not(feature = "opensbi"))]
+global_asm!(include_str!("entry.s"), MAX_HARTS = const crate::cpu::MAX_HARTS);
+global_asm!(
+    include_str!("supervisor.s"),
+    MAX_HARTS = const crate::cpu::MAX_HARTS,
+    EID_HSM = const crate::sbi::EID_HSM,
+);
+global_asm!(
+    include_str!("trap.s"),
+    HART_SCRATCH = const offset_of!(Hart, scratch),
+    HART_KERNEL_SP = const offset_of!(Hart, kernel_sp),
+    HART_ID = const offset_of!(Hart, id),
+    HART_STACK_BOTTOM = const offset_of!(Hart, stack_bottom),
+    HART_EMERGENCY_SP = const offset_of!(Hart, emergency_sp),
+    FRAME_SIZE = const size_of::<TrapFrame>(),
+    FRAME_PC = const offset_of!(TrapFrame, pc),
+    FRAME_SSTATUS = const offset_of!(TrapFrame, sstatus),
+    FRAME_STVAL = const offset_of!(TrapFrame, stval),
+    FRAME_SCAUSE = const offset_of!(TrapFrame, scause),
+);
+#[cfg(not(feature = "opensbi"))]
+global_asm!(include_str!("mtrap.s"));
+global_asm!(include_str!("switch.s"));
+global_asm!(include_str!("uaccess.s"));
 global_asm!(include_str!("exports.s"));
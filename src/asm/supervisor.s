@@ -0,0 +1,69 @@
+.section .text.init
+.global _supervisor_entry
+
+# Entry point of the kernel, in S-mode.
+# The SBI implementation (our own firmware, or OpenSBI) hands us
+# the hart id in a0, and an opaque value (the device tree for the
+# boot hart) in a1. Paging is disabled.
+_supervisor_entry:
+	# There is no stack nor per-hart state for harts past
+	# `cpu::MAX_HARTS`, hand them back before touching either
+	li t0, {MAX_HARTS}
+	bgeu a0, t0, unsupported_hart
+
+	# Keep the hart id around in tp, see `cpu::util::my_hart`
+	mv tp, a0
+
+	# Index into the kernel stack region
+	# as defined in `kernel.ld`
+	la sp, __kernel_stack_end
+        li t0, 1024*4
+        addi t1, a0, 1
+        mul t0, t0, t1
+        sub sp, sp, t0
+	call kmain
+
+unsupported_hart:
+	# Secondary harts are only ever started below `cpu::MAX_HARTS`,
+	# so this is the boot hart, and nothing would boot if it just
+	# stopped: say why, then hand the device tree over to the first
+	# hart we support that the SBI implementation agrees to start
+	mv s1, a1
+	la s0, unsupported_hart_msg
+print_msg:
+	lbu a0, 0(s0)
+	beqz a0, start_other
+	# Legacy `sbi_console_putchar`
+	li a7, 0x01
+	ecall
+	addi s0, s0, 1
+	j print_msg
+
+start_other:
+	li s0, 0
+start_next:
+	# `sbi_hart_start(hartid, _supervisor_entry, dtb)`
+	mv a0, s0
+	la a1, _supervisor_entry
+	mv a2, s1
+	li a7, {EID_HSM}
+	li a6, 0
+	ecall
+	beqz a0, stop
+	addi s0, s0, 1
+	li t0, {MAX_HARTS}
+	bltu s0, t0, start_next
+
+stop:
+	# `sbi_hart_stop`, which only returns if it failed
+	li a7, {EID_HSM}
+	li a6, 1
+	ecall
+
+halt:
+	wfi
+	j halt
+
+.section .rodata
+unsupported_hart_msg:
+	.asciz "walnut: boot hart id is past MAX_HARTS, handing over to another hart\n"
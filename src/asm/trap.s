@@ -1,80 +1,144 @@
 .section .text
 .global kernelvec
+.global trap_return
 .align 4
+# Supervisor trap vector, for traps from both U-mode and S-mode.
+#
+# `sscratch` always holds this hart's `cpu::hart::Hart`, which gives us
+# somewhere to stash registers before we have a stack, the kernel stack
+# to switch to when coming from U-mode, and the hart id to put in `tp`.
+# The registers are saved in a `cpu::trap::TrapFrame` on the kernel stack.
 kernelvec:
+        csrrw tp, sscratch, tp
+        sd t0, {HART_SCRATCH}(tp)
+        sd sp, {HART_SCRATCH}+8(tp)
+
+        # SPP tells us whether we came from U-mode (0) or S-mode (1),
+        # only in the former do we need to find a kernel stack.
+        csrr t0, sstatus
+        andi t0, t0, 1 << 8
+        bnez t0, 1f
+        ld sp, {HART_KERNEL_SP}(tp)
+        j 2f
+1:
+        # From S-mode we keep the current stack, unless a frame would not
+        # fit on it. Then it has overflowed or is about to, and we move to
+        # the emergency stack so the overflow can at least be reported.
+        ld t0, {HART_STACK_BOTTOM}(tp)
+        beqz t0, 2f
+        addi t0, t0, {FRAME_SIZE}
+        bgeu sp, t0, 2f
+        ld sp, {HART_EMERGENCY_SP}(tp)
+2:
         # make room to save registers.
-        addi sp, sp, -256
+        addi sp, sp, -{FRAME_SIZE}
+
+        # save the registers, indexed by register number.
+        sd x1, 8(sp)
+        sd x3, 24(sp)
+        sd x6, 48(sp)
+        sd x7, 56(sp)
+        sd x8, 64(sp)
+        sd x9, 72(sp)
+        sd x10, 80(sp)
+        sd x11, 88(sp)
+        sd x12, 96(sp)
+        sd x13, 104(sp)
+        sd x14, 112(sp)
+        sd x15, 120(sp)
+        sd x16, 128(sp)
+        sd x17, 136(sp)
+        sd x18, 144(sp)
+        sd x19, 152(sp)
+        sd x20, 160(sp)
+        sd x21, 168(sp)
+        sd x22, 176(sp)
+        sd x23, 184(sp)
+        sd x24, 192(sp)
+        sd x25, 200(sp)
+        sd x26, 208(sp)
+        sd x27, 216(sp)
+        sd x28, 224(sp)
+        sd x29, 232(sp)
+        sd x30, 240(sp)
+        sd x31, 248(sp)
 
-        # save the registers.
-        sd ra, 0(sp)
-        sd sp, 8(sp)
-        sd gp, 16(sp)
-        sd tp, 24(sp)
+        # and the ones we had to move out of the way.
+        ld t0, {HART_SCRATCH}(tp)
+        sd t0, 40(sp)
+        ld t0, {HART_SCRATCH}+8(tp)
+        sd t0, 16(sp)
+        csrr t0, sscratch
         sd t0, 32(sp)
-        sd t1, 40(sp)
-        sd t2, 48(sp)
-        sd s0, 56(sp)
-        sd s1, 64(sp)
-        sd a0, 72(sp)
-        sd a1, 80(sp)
-        sd a2, 88(sp)
-        sd a3, 96(sp)
-        sd a4, 104(sp)
-        sd a5, 112(sp)
-        sd a6, 120(sp)
-        sd a7, 128(sp)
-        sd s2, 136(sp)
-        sd s3, 144(sp)
-        sd s4, 152(sp)
-        sd s5, 160(sp)
-        sd s6, 168(sp)
-        sd s7, 176(sp)
-        sd s8, 184(sp)
-        sd s9, 192(sp)
-        sd s10, 200(sp)
-        sd s11, 208(sp)
-        sd t3, 216(sp)
-        sd t4, 224(sp)
-        sd t5, 232(sp)
-        sd t6, 240(sp)
 
+        # give sscratch its Hart back, and the kernel its hart id.
+        csrw sscratch, tp
+        ld tp, {HART_ID}(tp)
+
+        csrr t0, sepc
+        sd t0, {FRAME_PC}(sp)
+        csrr t0, sstatus
+        sd t0, {FRAME_SSTATUS}(sp)
+        csrr t0, stval
+        sd t0, {FRAME_STVAL}(sp)
+        csrr t0, scause
+        sd t0, {FRAME_SCAUSE}(sp)
+
+        mv a0, sp
         call handle_trap
 
-        # restore registers.
-        ld ra, 0(sp)
-        ld sp, 8(sp)
-        ld gp, 16(sp)
-        # not tp (contains hartid), in case we moved CPUs
-        ld t0, 32(sp)
-        ld t1, 40(sp)
-        ld t2, 48(sp)
-        ld s0, 56(sp)
-        ld s1, 64(sp)
-        ld a0, 72(sp)
-        ld a1, 80(sp)
-        ld a2, 88(sp)
-        ld a3, 96(sp)
-        ld a4, 104(sp)
-        ld a5, 112(sp)
-        ld a6, 120(sp)
-        ld a7, 128(sp)
-        ld s2, 136(sp)
-        ld s3, 144(sp)
-        ld s4, 152(sp)
-        ld s5, 160(sp)
-        ld s6, 168(sp)
-        ld s7, 176(sp)
-        ld s8, 184(sp)
-        ld s9, 192(sp)
-        ld s10, 200(sp)
-        ld s11, 208(sp)
-        ld t3, 216(sp)
-        ld t4, 224(sp)
-        ld t5, 232(sp)
-        ld t6, 240(sp)
+# Returns from a trap into the context saved in the frame at `sp`.
+# This is also how we enter U-mode for the first time, see `cpu::transition`.
+trap_return:
+        ld t0, {FRAME_PC}(sp)
+        csrw sepc, t0
+        ld t0, {FRAME_SSTATUS}(sp)
+        csrw sstatus, t0
 
-        addi sp, sp, 256
+        andi t0, t0, 1 << 8
+        bnez t0, 1f
 
-        # return to whatever we were doing in the kernel.
-        sret
+        # Returning to U-mode: the next trap from there will reuse this
+        # frame's memory at the top of the kernel stack, and user code
+        # gets its own tp back.
+        # When returning to S-mode we leave tp alone (it contains
+        # the hartid), in case we moved CPUs.
+        csrr t1, sscratch
+        addi t2, sp, {FRAME_SIZE}
+        sd t2, {HART_KERNEL_SP}(t1)
+        ld tp, 32(sp)
+1:
+        # restore registers.
+        ld x1, 8(sp)
+        ld x3, 24(sp)
+        ld x5, 40(sp)
+        ld x6, 48(sp)
+        ld x7, 56(sp)
+        ld x8, 64(sp)
+        ld x9, 72(sp)
+        ld x10, 80(sp)
+        ld x11, 88(sp)
+        ld x12, 96(sp)
+        ld x13, 104(sp)
+        ld x14, 112(sp)
+        ld x15, 120(sp)
+        ld x16, 128(sp)
+        ld x17, 136(sp)
+        ld x18, 144(sp)
+        ld x19, 152(sp)
+        ld x20, 160(sp)
+        ld x21, 168(sp)
+        ld x22, 176(sp)
+        ld x23, 184(sp)
+        ld x24, 192(sp)
+        ld x25, 200(sp)
+        ld x26, 208(sp)
+        ld x27, 216(sp)
+        ld x28, 224(sp)
+        ld x29, 232(sp)
+        ld x30, 240(sp)
+        ld x31, 248(sp)
+        ld sp, 16(sp)
 
+        # return to whatever we were doing.
+        sret
@@ -0,0 +1,53 @@
+.section .text
+.global copy_user
+.align 4
+# copy_user(dst: *mut u8, src: *const u8, len: usize) -> usize
+#
+# Copies `len` bytes between kernel and user memory, in either direction,
+# and returns how many of them were left uncopied. Every access that may
+# touch user memory has an entry in `__ex_table`, so when one faults the
+# trap handler resumes at `.Lcopy_user_fault` instead of panicking, and
+# the caller sees a short copy. The caller must have set `sstatus.SUM`.
+copy_user:
+        # Whole doublewords while both sides are aligned to them.
+        or t0, a0, a1
+        andi t0, t0, 7
+        bnez t0, .Lcopy_user_bytes
+.Lcopy_user_words:
+        li t0, 8
+        bltu a2, t0, .Lcopy_user_bytes
+.Lcopy_user_ld:
+        ld t1, 0(a1)
+.Lcopy_user_sd:
+        sd t1, 0(a0)
+        addi a0, a0, 8
+        addi a1, a1, 8
+        addi a2, a2, -8
+        j .Lcopy_user_words
+
+.Lcopy_user_bytes:
+        beqz a2, .Lcopy_user_done
+.Lcopy_user_lb:
+        lb t1, 0(a1)
+.Lcopy_user_sb:
+        sb t1, 0(a0)
+        addi a0, a0, 1
+        addi a1, a1, 1
+        addi a2, a2, -1
+        j .Lcopy_user_bytes
+
+.Lcopy_user_done:
+        li a0, 0
+        ret
+
+.Lcopy_user_fault:
+        mv a0, a2
+        ret
+
+# (faulting instruction, where to resume) pairs, see `syscall::uaccess`.
+.section __ex_table, "a"
+.balign 8
+        .dword .Lcopy_user_ld, .Lcopy_user_fault
+        .dword .Lcopy_user_sd, .Lcopy_user_fault
+        .dword .Lcopy_user_lb, .Lcopy_user_fault
+        .dword .Lcopy_user_sb, .Lcopy_user_fault
@@ -13,6 +13,42 @@ pub enum ControlStatusRegister {
     /// Machine Exception Program Counter
     Mepc,
 
+    /// Machine Status
+    Mstatus,
+
+    /// Machine Trap Vector Base Address
+    Mtvec,
+
+    /// Machine Scratch
+    Mscratch,
+
+    /// Machine Cause
+    Mcause,
+
+    /// Machine Trap Value
+    Mtval,
+
+    /// Machine Interrupt Enable
+    Mie,
+
+    /// Machine Interrupt Pending
+    Mip,
+
+    /// Machine Counter Enable
+    Mcounteren,
+
+    /// Machine Vendor ID
+    Mvendorid,
+
+    /// Machine Architecture ID
+    Marchid,
+
+    /// Machine Implementation ID
+    Mimpid,
+
+    /// Machine ISA
+    Misa,
+
     /// Supervisor Exception Program Counter
     Sepc,
 
@@ -31,6 +67,16 @@ pub enum ControlStatusRegister {
     /// Supervisor Interrupt Enable
     Sie,
 
+    /// Supervisor Interrupt Pending
+    Sip,
+
+    /// Supervisor Scratch
+    Sscratch,
+
+    /// Real-time clock, readable from S-mode when
+    /// the firmware allows it in `mcounteren`.
+    Time,
+
     /// Physical Memory Protection address
     Pmpaddr0,
 
@@ -63,11 +109,26 @@ impl ControlStatusRegister {
         unsafe {
             match self {
                 Self::Mepc => core::arch::asm!("csrr {0}, mepc", out(reg) result),
+                Self::Mstatus => core::arch::asm!("csrr {0}, mstatus", out(reg) result),
+                Self::Mtvec => core::arch::asm!("csrr {0}, mtvec", out(reg) result),
+                Self::Mscratch => core::arch::asm!("csrr {0}, mscratch", out(reg) result),
+                Self::Mcause => core::arch::asm!("csrr {0}, mcause", out(reg) result),
+                Self::Mtval => core::arch::asm!("csrr {0}, mtval", out(reg) result),
+                Self::Mie => core::arch::asm!("csrr {0}, mie", out(reg) result),
+                Self::Mip => core::arch::asm!("csrr {0}, mip", out(reg) result),
+                Self::Mcounteren => core::arch::asm!("csrr {0}, mcounteren", out(reg) result),
+                Self::Mvendorid => core::arch::asm!("csrr {0}, mvendorid", out(reg) result),
+                Self::Marchid => core::arch::asm!("csrr {0}, marchid", out(reg) result),
+                Self::Mimpid => core::arch::asm!("csrr {0}, mimpid", out(reg) result),
+                Self::Misa => core::arch::asm!("csrr {0}, misa", out(reg) result),
                 Self::Sepc => core::arch::asm!("csrr {0}, sepc", out(reg) result),
                 Self::Satp => core::arch::asm!("csrr {0}, satp", out(reg) result),
                 Self::Medeleg => core::arch::asm!("csrr {0}, medeleg", out(reg) result),
                 Self::Mideleg => core::arch::asm!("csrr {0}, mideleg", out(reg) result),
                 Self::Sie => core::arch::asm!("csrr {0}, sie", out(reg) result),
+                Self::Sip => core::arch::asm!("csrr {0}, sip", out(reg) result),
+                Self::Sscratch => core::arch::asm!("csrr {0}, sscratch", out(reg) result),
+                Self::Time => core::arch::asm!("csrr {0}, time", out(reg) result),
                 Self::Pmpaddr0 => core::arch::asm!("csrr {0}, pmpaddr0", out(reg) result),
                 Self::Pmpcfg0 => core::arch::asm!("csrr {0}, pmpcfg0", out(reg) result),
                 Self::Stvec => core::arch::asm!("csrr {0}, stvec", out(reg) result),
@@ -86,11 +147,25 @@ impl ControlStatusRegister {
         unsafe {
             match self {
                 Self::Mepc => core::arch::asm!("csrw mepc, {}", in(reg) v),
+                Self::Mstatus => core::arch::asm!("csrw mstatus, {}", in(reg) v),
+                Self::Mtvec => core::arch::asm!("csrw mtvec, {}", in(reg) v),
+                Self::Mscratch => core::arch::asm!("csrw mscratch, {}", in(reg) v),
+                Self::Mcause => core::arch::asm!("csrw mcause, {}", in(reg) v),
+                Self::Mtval => core::arch::asm!("csrw mtval, {}", in(reg) v),
+                Self::Mie => core::arch::asm!("csrw mie, {}", in(reg) v),
+                Self::Mip => core::arch::asm!("csrw mip, {}", in(reg) v),
+                Self::Mcounteren => core::arch::asm!("csrw mcounteren, {}", in(reg) v),
+                // read-only registers
+                Self::Mvendorid | Self::Marchid | Self::Mimpid | Self::Misa | Self::Time => {
+                    unreachable!("Attempted to write a read-only CSR")
+                }
                 Self::Sepc => core::arch::asm!("csrw sepc, {}", in(reg) v),
                 Self::Satp => core::arch::asm!("csrw satp, {}", in(reg) v),
                 Self::Medeleg => core::arch::asm!("csrw medeleg, {}", in(reg) v),
                 Self::Mideleg => core::arch::asm!("csrw mideleg, {}", in(reg) v),
                 Self::Sie => core::arch::asm!("csrw sie, {}", in(reg) v),
+                Self::Sip => core::arch::asm!("csrw sip, {}", in(reg) v),
+                Self::Sscratch => core::arch::asm!("csrw sscratch, {}", in(reg) v),
                 Self::Pmpaddr0 => core::arch::asm!("csrw  pmpaddr0, {}", in(reg) v),
                 Self::Pmpcfg0 => core::arch::asm!("csrw  pmpcfg0, {}", in(reg) v),
                 Self::Stvec => core::arch::asm!("csrw  stvec, {}", in(reg) v),
@@ -103,6 +178,16 @@ impl ControlStatusRegister {
         }
     }
 
+    /// Sets every bit in `mask`, leaving the rest of the register untouched.
+    pub fn set_bits(&self, mask: usize) {
+        self.write(self.read() | mask);
+    }
+
+    /// Clears every bit in `mask`, leaving the rest of the register untouched.
+    pub fn clear_bits(&self, mask: usize) {
+        self.write(self.read() & !mask);
+    }
+
     pub fn write_fn_addr(&self, f: fn()) {
         self.write(f as *const () as usize);
     }
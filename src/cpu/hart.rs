@@ -0,0 +1,131 @@
+//! Per-hart state.
+//!
+//! Each hart owns one [`Hart`], whose address lives in `sscratch` for as
+//! long as the hart runs the kernel. The trap vector (see `asm/trap.s`)
+//! relies on it to find a kernel stack and restore `tp` when trapping
+//! from U-mode, so the layout of the first few fields is fixed.
+
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+use super::{csr::ControlStatusRegister, mode::Mode, util::my_hart, MAX_HARTS};
+
+#[repr(C)]
+pub struct Hart {
+    /// Somewhere for the trap vector to stash `t0` and `sp`
+    /// before it has a stack to work with.
+    pub(crate) scratch: [AtomicUsize; 2],
+
+    /// Top of the kernel stack to switch to when trapping from U-mode.
+    pub(crate) kernel_sp: AtomicUsize,
+
+    /// The hart id, which is what the kernel keeps in `tp`.
+    pub(crate) id: AtomicUsize,
+
+    /// Lowest usable address of the stack the hart is running on.
+    /// Traps from S-mode that would push a frame below it go to
+    /// the emergency stack instead.
+    pub(crate) stack_bottom: AtomicUsize,
+
+    /// Top of a stack kept aside to report kernel stack overflows.
+    pub(crate) emergency_sp: AtomicUsize,
+
+    /// The privilege mode this hart is running in.
+    mode: AtomicU8,
+
+    /// The privilege mode this hart was running in when it last trapped.
+    trapped_from: AtomicU8,
+}
+
+/// When booted by our own firmware every hart starts in M-mode,
+/// OpenSBI hands them to us in S-mode.
+const INITIAL_MODE: Mode = if cfg!(feature = "opensbi") {
+    Mode::Supervisor
+} else {
+    Mode::Machine
+};
+
+static HARTS: [Hart; MAX_HARTS] = [const { Hart::new() }; MAX_HARTS];
+
+impl Hart {
+    const fn new() -> Self {
+        Self {
+            scratch: [AtomicUsize::new(0), AtomicUsize::new(0)],
+            kernel_sp: AtomicUsize::new(0),
+            id: AtomicUsize::new(0),
+            stack_bottom: AtomicUsize::new(0),
+            emergency_sp: AtomicUsize::new(0),
+            mode: AtomicU8::new(INITIAL_MODE as u8),
+            trapped_from: AtomicU8::new(INITIAL_MODE as u8),
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id.load(Ordering::Relaxed)
+    }
+
+    pub fn mode(&self) -> Mode {
+        Mode::from_bits(self.mode.load(Ordering::Relaxed))
+    }
+
+    pub fn set_mode(&self, m: Mode) {
+        self.mode.store(m as u8, Ordering::Relaxed);
+    }
+
+    /// The privilege mode this hart was in when it last trapped
+    /// into the kernel, e.g. `User` while handling a system call.
+    pub fn trapped_from(&self) -> Mode {
+        Mode::from_bits(self.trapped_from.load(Ordering::Relaxed))
+    }
+
+    /// Records that a trap from `from` was taken, we are now in S-mode.
+    pub fn enter_trap(&self, from: Mode) {
+        self.trapped_from.store(from as u8, Ordering::Relaxed);
+        self.set_mode(Mode::Supervisor);
+    }
+
+    /// Records that we are about to return from a trap into `to`.
+    pub fn exit_trap(&self, to: Mode) {
+        self.set_mode(to);
+    }
+
+    /// Top of the kernel stack used for traps from U-mode.
+    pub fn kernel_sp(&self) -> usize {
+        self.kernel_sp.load(Ordering::Relaxed)
+    }
+
+    pub fn set_kernel_sp(&self, sp: usize) {
+        self.kernel_sp.store(sp, Ordering::Relaxed);
+    }
+
+    pub fn stack_bottom(&self) -> usize {
+        self.stack_bottom.load(Ordering::Relaxed)
+    }
+
+    /// Records the stack this hart now runs on, for overflow detection.
+    pub fn set_stack_bottom(&self, bottom: usize) {
+        self.stack_bottom.store(bottom, Ordering::Relaxed);
+    }
+
+    pub fn set_emergency_sp(&self, sp: usize) {
+        self.emergency_sp.store(sp, Ordering::Relaxed);
+    }
+}
+
+/// Gets the state of the calling hart.
+pub fn current() -> &'static Hart {
+    &HARTS[unsafe { my_hart() }]
+}
+
+/// Gets the state of `hartid`, if we support that many harts.
+pub fn get(hartid: usize) -> Option<&'static Hart> {
+    HARTS.get(hartid)
+}
+
+/// Sets up the calling hart's [`Hart`] once it runs the kernel in S-mode,
+/// and hands it to the trap vector through `sscratch`.
+pub fn init(hartid: usize) {
+    let hart = &HARTS[hartid];
+    hart.id.store(hartid, Ordering::Relaxed);
+    hart.set_mode(Mode::Supervisor);
+    ControlStatusRegister::Sscratch.write(hart as *const Hart as usize);
+}
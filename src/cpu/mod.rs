@@ -1,56 +1,186 @@
-use core::arch::asm;
+use core::{
+    arch::asm,
+    convert::Infallible,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
 
 use crate::cpu::csr::ControlStatusRegister;
 
-use self::mode::Mode;
+use self::{mode::Mode, trap::TrapFrame};
+use crate::util::error::WalnutError;
 
 pub mod csr;
+pub mod hart;
 pub mod mode;
+pub mod pmp;
 pub mod port;
 pub mod trap;
 pub mod util;
 
-/// Delete exceptions and interrupts to Supervisor mode
+/// The maximum number of harts Walnut supports.
+///
+/// This must match `CPU_CNT` in `asm/entry.s`.
+pub const MAX_HARTS: usize = 4;
+
+pub const SSTATUS_SIE: usize = 1 << 1;
+pub const SSTATUS_SPIE: usize = 1 << 5;
+pub const SSTATUS_SPP: usize = 1 << 8;
+pub const SSTATUS_SUM: usize = 1 << 18;
+
+/// The hart that booted the kernel, see [`claim_boot_hart`].
+static BOOT_HART: AtomicUsize = AtomicUsize::new(0);
+static BOOT_HART_CLAIMED: AtomicBool = AtomicBool::new(false);
+
+/// Gets the hart that booted the kernel and performs
+/// the one-time initialization.
+///
+/// This is hart 0 with our own firmware, but OpenSBI
+/// may pick any hart to start the kernel on.
+pub fn boot_hart() -> usize {
+    BOOT_HART.load(Ordering::Acquire)
+}
+
+/// Records `hartid` as the boot hart if no other hart got there first,
+/// returning whether it did.
+pub fn claim_boot_hart(hartid: usize) -> bool {
+    let first = !BOOT_HART_CLAIMED.swap(true, Ordering::AcqRel);
+    if first {
+        BOOT_HART.store(hartid, Ordering::Release);
+    }
+    first
+}
+
+/// Delegate exceptions and interrupts to Supervisor mode
+///
+/// Everything is delegated except for `ecall`s from S-mode,
+/// which is how the kernel calls into the firmware.
 pub fn delegate_traps() {
-    ControlStatusRegister::Medeleg.write(0xffff);
-    ControlStatusRegister::Mideleg.write(0xffff);
-    ControlStatusRegister::Sie.write(
-        ControlStatusRegister::Sie.read() |
+    ControlStatusRegister::Medeleg.write(0xffff & !(1 << 9));
+    ControlStatusRegister::Mideleg.write(
           1 << 9 // external interrupts
         | 1 << 5 // timer    interrupts
         | 1 << 1, // software interrupts
     );
 }
 
-/// Transitions into a given mode using the `xRET` instructions.
+/// Transitions into a less privileged mode using the `xRET` instructions,
+/// resuming the context described by `frame`.
 ///
-/// For example, transitioning from `Machine` to `Supervisor`
-/// like we do in `kinit`, we perform an `mret` instruction.
+/// - `Machine` to `Supervisor` is how the firmware enters the kernel, with
+///   an `mret`. Only `pc`, `a0` and `a1` of `frame` are used.
+/// - `Supervisor` to `User` enters user code with an `sret`, with every
+///   register taken from `frame` and interrupts enabled.
+///
+/// Going the other way only ever happens through a trap (an `ecall`,
+/// an exception or an interrupt, see `asm/trap.s`), so this returns
+/// an error for any other pair of modes.
 ///
 /// # Safety
 ///
-/// If we attempt this with incorrect Modes
-/// it WILL panic.
+/// `frame.pc` must point to code that can run in `dest_mode`.
 ///
-/// If certain elements and CSRs are not set up
-/// correctly, it WILL panic.
-pub unsafe fn transition(dest_mode: Mode) -> ! {
+/// When entering U-mode, `frame` must sit at the very top of the current
+/// kernel stack, as traps from U-mode will reuse the memory below it.
+pub unsafe fn transition(dest_mode: Mode, frame: &mut TrapFrame) -> crate::Result<Infallible> {
     match (mode::Mode::current(), dest_mode) {
         (Mode::Machine, Mode::Supervisor) => {
-            // Adjust the mode so we transition into S-mode
+            // Adjust the mode so we transition into S-mode,
+            // with supervisor interrupts disabled until the kernel is ready.
             mode::set_prev_privilege_mode(Mode::Supervisor);
+            ControlStatusRegister::Mstatus.clear_bits(SSTATUS_SIE);
 
             // Set the MEPC so that after we `mret`
-            // we will be in `kmain`
-            ControlStatusRegister::Mepc.write_fn_addr(crate::kmain);
+            // we will be wherever the frame says
+            ControlStatusRegister::Mepc.write(frame.pc);
+
+            Mode::set_current(Mode::Supervisor);
+            asm!(
+                "mret",
+                in("a0") frame.arg(0),
+                in("a1") frame.arg(1),
+                options(noreturn)
+            );
+        }
+        (Mode::Supervisor, Mode::User) => {
+            // Nothing may trap onto this stack until we are gone.
+            ControlStatusRegister::SStatus.clear_bits(SSTATUS_SIE);
+
+            // `sret` into U-mode, turning interrupts on as it does so,
+            // and without access to user memory from the kernel.
+            frame.sstatus = (ControlStatusRegister::SStatus.read()
+                & !(SSTATUS_SPP | SSTATUS_SIE | SSTATUS_SUM))
+                | Mode::User.spp_val()
+                | SSTATUS_SPIE;
 
-            asm!("mret");
-            unreachable!();
+            Mode::set_current(Mode::User);
+            asm!(
+                "mv sp, {frame}",
+                "la t0, trap_return",
+                "jr t0",
+                frame = in(reg) frame as *mut TrapFrame,
+                options(noreturn)
+            );
         }
-        _ => unreachable!(),
+        _ => Err(WalnutError::new("Invalid privilege mode transition")),
     }
 }
 
 pub fn save_hartid() {
     ControlStatusRegister::ThreadPointer.write(ControlStatusRegister::Mhartid.read());
 }
+
+/// Enables supervisor interrupts on the calling hart.
+pub fn enable_interrupts() {
+    unsafe { core::arch::asm!("csrsi sstatus, {}", const SSTATUS_SIE) };
+}
+
+/// Disables supervisor interrupts on the calling hart,
+/// returning whether they were enabled.
+pub fn disable_interrupts() -> bool {
+    let prev: usize;
+    unsafe { core::arch::asm!("csrrci {}, sstatus, {}", out(reg) prev, const SSTATUS_SIE) };
+    prev & SSTATUS_SIE != 0
+}
+
+/// Re-enables interrupts if `enabled`, as returned by [`disable_interrupts`].
+pub fn restore_interrupts(enabled: bool) {
+    if enabled {
+        enable_interrupts();
+    }
+}
+
+/// Waits for an interrupt.
+pub fn wait_for_interrupt() {
+    unsafe { core::arch::asm!("wfi") };
+}
+
+/// `misa` of the harts we run on, as found by the firmware.
+static ISA: AtomicUsize = AtomicUsize::new(0);
+
+/// Records the extensions the harts support, from `misa`.
+pub fn set_isa(misa: usize) {
+    ISA.store(misa, Ordering::Relaxed);
+}
+
+/// Whether the harts support the single letter extension `ext`, e.g. `'c'`.
+///
+/// Under OpenSBI we can not read `misa`, and assume the extensions
+/// the kernel itself is built for.
+pub fn has_extension(ext: char) -> bool {
+    let bit = self::ext(ext.to_ascii_lowercase() as u8);
+    match ISA.load(Ordering::Relaxed) {
+        0 => KERNEL_ISA & bit != 0,
+        misa => misa & bit != 0,
+    }
+}
+
+const fn ext(e: u8) -> usize {
+    1 << (e - b'a')
+}
+
+const KERNEL_ISA: usize = ext(b'i')
+    | if cfg!(target_feature = "m") { ext(b'm') } else { 0 }
+    | if cfg!(target_feature = "a") { ext(b'a') } else { 0 }
+    | if cfg!(target_feature = "f") { ext(b'f') } else { 0 }
+    | if cfg!(target_feature = "d") { ext(b'd') } else { 0 }
+    | if cfg!(target_feature = "c") { ext(b'c') } else { 0 };
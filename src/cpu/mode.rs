@@ -1,14 +1,10 @@
 use core::arch::asm;
 
-/// The the current privilege mode this
-/// CPU/HART is running in.
-///
-/// We know that we enter rust in machine mode (M-mode)
-/// so it starts as such
-static mut CURRENT_MODE: Mode = Mode::Machine;
+use super::hart;
 
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
 pub enum Mode {
     /// When running in a hypervisor context
     /// Unused in Walnut, and disregarded in documentation etc.
@@ -25,6 +21,9 @@ pub enum Mode {
     User,
 }
 
+const MPP_MASK: usize = 3 << 11;
+const SPP_BIT: usize = 1 << 8;
+
 impl Mode {
     /// Get the current privilege mode this
     /// CPU/HART is running in.
@@ -35,21 +34,45 @@ impl Mode {
     /// it because this could be used for processes to discover they running from
     /// within a hypvervisor
     ///
-    /// We could implement this by doing some trickery with
-    /// checking previous privilige mode and perform some logic
-    /// to retreive this, however this may become error-prone.
-    /// So, we just track it in a global static mutable variable,
-    /// changed upon mode-switch.
-    ///
-    /// This function's primary purpose is to provide a 'safe'
-    /// API to grab the current mode.
+    /// So, we track it per hart in `cpu::hart::Hart`, updated
+    /// on every mode switch we perform and every trap we take.
     pub fn current() -> Mode {
-        unsafe { CURRENT_MODE }
+        hart::current().mode()
     }
 
     pub fn set_current(m: Mode) {
-        unsafe {
-            CURRENT_MODE = m;
+        hart::current().set_mode(m)
+    }
+
+    /// The mode a trap was taken from, according to
+    /// the `SPP` bit of the `sstatus` saved at that time.
+    pub fn from_sstatus(sstatus: usize) -> Mode {
+        if sstatus & SPP_BIT != 0 {
+            Self::Supervisor
+        } else {
+            Self::User
+        }
+    }
+
+    /// Decodes the `MPP` field of `mstatus`.
+    ///
+    /// The encoding `2` is reserved (it was once hypervisor mode),
+    /// which is why this is fallible.
+    pub fn from_mpp(mstatus: usize) -> Option<Mode> {
+        match (mstatus & MPP_MASK) >> 11 {
+            3 => Some(Self::Machine),
+            1 => Some(Self::Supervisor),
+            0 => Some(Self::User),
+            _ => None,
+        }
+    }
+
+    pub(super) fn from_bits(bits: u8) -> Mode {
+        match bits {
+            0 => Self::Hypervisor,
+            1 => Self::Machine,
+            2 => Self::Supervisor,
+            _ => Self::User,
         }
     }
 
@@ -58,27 +81,21 @@ impl Mode {
             Self::Machine => 3 << 11,
             Self::Supervisor => 1 << 11,
             Self::User => 0 << 11,
-            Self::Hypervisor => unreachable!(),
+            Self::Hypervisor => unreachable!("Walnut does not support hypervisor mode"),
         }
     }
-}
 
-impl From<usize> for Mode {
-    fn from(value: usize) -> Self {
-        const M: usize = 3 << 11;
-        const S: usize = 1 << 11;
-        const U: usize = 0 << 11;
-        match value {
-            M => Self::Machine,
-            S => Self::Supervisor,
-            U => Self::User,
-            _ => unreachable!("Invalid value found for Mode!"),
+    /// The value of the `SPP` bit of `sstatus` that makes `sret` return into this mode.
+    pub fn spp_val(&self) -> usize {
+        match self {
+            Self::Supervisor => SPP_BIT,
+            Self::User => 0,
+            _ => unreachable!("`sret` can only return to S-mode or U-mode"),
         }
     }
 }
 
 pub fn set_prev_privilege_mode(m: Mode) {
-    const MPP_MASK: usize = 3 << 11;
     let mut csr_data: usize;
 
     unsafe {
@@ -95,16 +112,15 @@ pub fn set_prev_privilege_mode(m: Mode) {
     }
 
     // Sanity check!
-    assert!(m == get_prev_privilege_mode());
+    assert!(Some(m) == get_prev_privilege_mode());
 }
 
-pub fn get_prev_privilege_mode() -> Mode {
-    const MPP_MASK: usize = 3 << 11;
-    let mut csr_data: usize;
+pub fn get_prev_privilege_mode() -> Option<Mode> {
+    let csr_data: usize;
 
     unsafe {
         asm!("csrr {}, mstatus", out(reg) csr_data);
     }
 
-    Mode::from(csr_data & MPP_MASK)
+    Mode::from_mpp(csr_data)
 }
@@ -0,0 +1,315 @@
+//! Physical Memory Protection.
+//!
+//! PMP entries restrict which physical addresses S-mode and U-mode may
+//! access, and how. Entries are checked in order and the lowest numbered
+//! entry that matches an address decides the permissions, so the usual
+//! way to use them is to carve out the most specific regions first and
+//! finish with a catch-all entry.
+//!
+//! Unless an entry is locked it does not apply to M-mode at all, which is
+//! how the firmware keeps access to its own memory while hiding it from
+//! the kernel. This must all be done from M-mode.
+//!
+//! # Usage Example:
+//! ```
+//! let mut pmp = Pmp::new();
+//! unsafe {
+//!     pmp.add_tor(TEXT_START, TEXT_END, Permissions::READ | Permissions::EXEC, false)?;
+//! }
+//! pmp.add_napot(0, 1 << 56, Permissions::ALL, false)?;
+//! Pmp::dump();
+//! ```
+
+use core::arch::asm;
+
+use mycelium_bitfield::{bitfield, enum_from_bits};
+
+use crate::{info, util::error::WalnutError};
+
+/// The number of PMP entries we manage, QEMU implements (at least) 16.
+pub const PMP_ENTRIES: usize = 16;
+
+/// The smallest region a PMP entry can describe.
+const PMP_GRAIN: usize = 4;
+
+enum_from_bits! {
+    #[derive(Debug, PartialEq, Eq)]
+    pub enum AddressMatching<u8> {
+        /// The entry is disabled.
+        Off = 0b00,
+        /// Top Of Range, from the previous entry's address up to this one's.
+        Tor = 0b01,
+        /// Naturally aligned four-byte region.
+        Na4 = 0b10,
+        /// Naturally aligned power-of-two region, of at least 8 bytes.
+        Napot = 0b11,
+    }
+}
+
+bitfield! {
+    /// Per-entry configuration byte, as found in `pmpcfgN`.
+    pub struct PmpConfig<u8> {
+        pub const READ: bool;
+        pub const WRITE: bool;
+        pub const EXEC: bool;
+        pub const MATCHING: AddressMatching;
+        const _RESERVED = 2;
+        /// Locked entries also apply to M-mode, and can
+        /// not be changed again until reset.
+        pub const LOCKED: bool;
+    }
+}
+
+/// Access permissions granted to a region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Permissions(u8);
+
+impl Permissions {
+    pub const NONE: Self = Self(0);
+    pub const READ: Self = Self(1 << 0);
+    pub const WRITE: Self = Self(1 << 1);
+    pub const EXEC: Self = Self(1 << 2);
+    pub const ALL: Self = Self(0b111);
+
+    fn config(self, matching: AddressMatching, locked: bool) -> PmpConfig {
+        PmpConfig::new()
+            .with(PmpConfig::READ, self.0 & Self::READ.0 != 0)
+            .with(PmpConfig::WRITE, self.0 & Self::WRITE.0 != 0)
+            .with(PmpConfig::EXEC, self.0 & Self::EXEC.0 != 0)
+            .with(PmpConfig::MATCHING, matching)
+            .with(PmpConfig::LOCKED, locked)
+    }
+}
+
+impl core::ops::BitOr for Permissions {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Programs PMP entries in order, starting from entry 0.
+///
+/// Any entry that was not handed out is left as is, so this
+/// should be used once per hart with the full set of regions.
+pub struct Pmp {
+    next: usize,
+}
+
+impl Pmp {
+    pub fn new() -> Self {
+        Self { next: 0 }
+    }
+
+    /// Protects `[start, end)` using Top Of Range matching.
+    ///
+    /// This needs a second, disabled, entry to hold `start` unless
+    /// the region starts at 0 or right where the previous TOR region ended.
+    pub fn add_tor(
+        &mut self,
+        start: usize,
+        end: usize,
+        perms: Permissions,
+        locked: bool,
+    ) -> Result<(), PmpError> {
+        if !start.is_multiple_of(PMP_GRAIN) || !end.is_multiple_of(PMP_GRAIN) {
+            return Err(PmpError::new("TOR region bounds must be 4-byte aligned"));
+        }
+        if start >= end {
+            return Err(PmpError::new("TOR region is empty"));
+        }
+
+        let shares_bound = match self.next {
+            0 => start == 0,
+            n => read_addr(n - 1) == start >> 2,
+        };
+
+        if !shares_bound {
+            self.reserve(2)?;
+            self.write(start >> 2, Permissions::NONE.config(AddressMatching::Off, locked))?;
+        } else {
+            self.reserve(1)?;
+        }
+        self.write(end >> 2, perms.config(AddressMatching::Tor, locked))
+    }
+
+    /// Protects the naturally aligned power-of-two region of
+    /// `size` bytes at `base`.
+    pub fn add_napot(
+        &mut self,
+        base: usize,
+        size: usize,
+        perms: Permissions,
+        locked: bool,
+    ) -> Result<(), PmpError> {
+        if !size.is_power_of_two() || size < PMP_GRAIN {
+            return Err(PmpError::new("NAPOT region size must be a power of two of at least 4 bytes"));
+        }
+        if !base.is_multiple_of(size) {
+            return Err(PmpError::new("NAPOT region base must be aligned to its size"));
+        }
+
+        self.reserve(1)?;
+        if size == PMP_GRAIN {
+            self.write(base >> 2, perms.config(AddressMatching::Na4, locked))
+        } else {
+            self.write((base | (size / 2 - 1)) >> 2, perms.config(AddressMatching::Napot, locked))
+        }
+    }
+
+    fn reserve(&self, n: usize) -> Result<(), PmpError> {
+        if self.next + n > PMP_ENTRIES {
+            return Err(PmpError::new("No free PMP entries left"));
+        }
+        Ok(())
+    }
+
+    /// Fills in the next entry, unless it is locked: locked entries
+    /// can't be changed until reset, so whatever it protects would not
+    /// be protected as asked.
+    fn write(&mut self, addr: usize, cfg: PmpConfig) -> Result<(), PmpError> {
+        if read_config(self.next).get(PmpConfig::LOCKED) {
+            return Err(PmpError::new("PMP entry is locked"));
+        }
+        write_addr(self.next, addr);
+        write_config(self.next, cfg);
+        self.next += 1;
+
+        // Changes to PMP entries are only guaranteed to be
+        // observed by address translation after a fence.
+        unsafe { asm!("sfence.vma") };
+        Ok(())
+    }
+
+    /// Prints every enabled PMP entry with the region it covers.
+    pub fn dump() {
+        info!("PMP configuration:");
+        let mut prev_addr = 0;
+        for i in 0..PMP_ENTRIES {
+            let cfg = read_config(i);
+            let addr = read_addr(i);
+            let range = match cfg.get(PmpConfig::MATCHING) {
+                AddressMatching::Off => None,
+                AddressMatching::Tor => Some((prev_addr << 2, addr << 2)),
+                AddressMatching::Na4 => Some((addr << 2, (addr << 2) + PMP_GRAIN)),
+                AddressMatching::Napot => {
+                    let ones = addr.trailing_ones();
+                    let base = (addr & !((1 << ones) - 1)) << 2;
+                    Some((base, base.wrapping_add(1 << (ones + 3))))
+                }
+            };
+            prev_addr = addr;
+
+            if let Some((start, end)) = range {
+                info!(
+                    "  pmp{:<2} {:#018x}..{:#018x} {:?} {}{}{}{}",
+                    i,
+                    start,
+                    end,
+                    cfg.get(PmpConfig::MATCHING),
+                    if cfg.get(PmpConfig::READ) { 'r' } else { '-' },
+                    if cfg.get(PmpConfig::WRITE) { 'w' } else { '-' },
+                    if cfg.get(PmpConfig::EXEC) { 'x' } else { '-' },
+                    if cfg.get(PmpConfig::LOCKED) { " locked" } else { "" },
+                );
+            }
+        }
+    }
+}
+
+impl Default for Pmp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Generates a `match` over every PMP entry index, as CSR numbers
+/// have to be known at compile time.
+macro_rules! pmpaddr_op {
+    ($i:expr, $pre:literal, $post:literal, $($operands:tt)+) => {
+        match $i {
+            0 => asm!(concat!($pre, "pmpaddr0", $post), $($operands)+),
+            1 => asm!(concat!($pre, "pmpaddr1", $post), $($operands)+),
+            2 => asm!(concat!($pre, "pmpaddr2", $post), $($operands)+),
+            3 => asm!(concat!($pre, "pmpaddr3", $post), $($operands)+),
+            4 => asm!(concat!($pre, "pmpaddr4", $post), $($operands)+),
+            5 => asm!(concat!($pre, "pmpaddr5", $post), $($operands)+),
+            6 => asm!(concat!($pre, "pmpaddr6", $post), $($operands)+),
+            7 => asm!(concat!($pre, "pmpaddr7", $post), $($operands)+),
+            8 => asm!(concat!($pre, "pmpaddr8", $post), $($operands)+),
+            9 => asm!(concat!($pre, "pmpaddr9", $post), $($operands)+),
+            10 => asm!(concat!($pre, "pmpaddr10", $post), $($operands)+),
+            11 => asm!(concat!($pre, "pmpaddr11", $post), $($operands)+),
+            12 => asm!(concat!($pre, "pmpaddr12", $post), $($operands)+),
+            13 => asm!(concat!($pre, "pmpaddr13", $post), $($operands)+),
+            14 => asm!(concat!($pre, "pmpaddr14", $post), $($operands)+),
+            15 => asm!(concat!($pre, "pmpaddr15", $post), $($operands)+),
+            _ => unreachable!("Invalid PMP entry"),
+        }
+    };
+}
+
+fn read_addr(i: usize) -> usize {
+    let v: usize;
+    unsafe { pmpaddr_op!(i, "csrr {0}, ", "", out(reg) v) };
+    v
+}
+
+fn write_addr(i: usize, v: usize) {
+    unsafe { pmpaddr_op!(i, "csrw ", ", {0}", in(reg) v) };
+}
+
+/// On RV64 the configuration bytes of entries 0-7 live in `pmpcfg0`
+/// and those of entries 8-15 in `pmpcfg2`.
+fn read_cfg_reg(i: usize) -> usize {
+    let v: usize;
+    unsafe {
+        if i < 8 {
+            asm!("csrr {0}, pmpcfg0", out(reg) v);
+        } else {
+            asm!("csrr {0}, pmpcfg2", out(reg) v);
+        }
+    }
+    v
+}
+
+fn read_config(i: usize) -> PmpConfig {
+    PmpConfig::from_bits((read_cfg_reg(i) >> ((i % 8) * 8)) as u8)
+}
+
+fn write_config(i: usize, cfg: PmpConfig) {
+    let shift = (i % 8) * 8;
+    let v = (read_cfg_reg(i) & !(0xff << shift)) | ((cfg.bits() as usize) << shift);
+    unsafe {
+        if i < 8 {
+            asm!("csrw pmpcfg0, {0}", in(reg) v);
+        } else {
+            asm!("csrw pmpcfg2, {0}", in(reg) v);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PmpError {
+    details: &'static str,
+}
+
+impl PmpError {
+    pub fn new(msg: &'static str) -> PmpError {
+        PmpError { details: msg }
+    }
+}
+
+impl core::fmt::Display for PmpError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl core::error::Error for PmpError {}
+
+impl From<PmpError> for WalnutError {
+    fn from(value: PmpError) -> Self {
+        Self::new(value.details)
+    }
+}
@@ -1,6 +1,50 @@
-use crate::debug;
+use crate::{
+    debug, error,
+    mem::{space::Access, stack},
+    process::{self, sched, signal::{self, Signal}},
+};
+
+use super::{hart, mode::Mode};
+
+/// Registers of an interrupted context, as saved by `kernelvec`
+/// (see `asm/trap.s`) on the kernel stack.
+///
+/// A `TrapFrame` also describes a context we are yet to enter, which is
+/// how `cpu::transition` starts code in a less privileged mode.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TrapFrame {
+    /// General purpose registers, indexed by register number (so `a0` is `regs[10]`).
+    pub regs: [usize; 32],
+    /// Where to resume execution.
+    pub pc: usize,
+    pub sstatus: usize,
+    pub stval: usize,
+    pub scause: usize,
+}
 
-use super::csr::ControlStatusRegister;
+impl TrapFrame {
+    pub const RA: usize = 1;
+    pub const SP: usize = 2;
+    pub const GP: usize = 3;
+    pub const TP: usize = 4;
+    pub const A0: usize = 10;
+    pub const A7: usize = 17;
+
+    /// The mode the context described by this frame runs in.
+    pub fn mode(&self) -> Mode {
+        Mode::from_sstatus(self.sstatus)
+    }
+
+    /// Gets argument register `a{n}`.
+    pub fn arg(&self, n: usize) -> usize {
+        self.regs[Self::A0 + n]
+    }
+
+    pub fn set_arg(&mut self, n: usize, v: usize) {
+        self.regs[Self::A0 + n] = v;
+    }
+}
 
 #[derive(Debug)]
 pub enum Interrupt {
@@ -25,12 +69,23 @@ impl From<usize> for Interrupt {
     
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum Exception {
     InstructionAddressMisaligned,
     InstructionAccessFault,
     IllegalInstruction,
     Breakpoint,
+    LoadAddressMisaligned,
+    LoadAccessFault,
+    StoreAddressMisaligned,
+    StoreAccessFault,
+    UserEnvCall,
+    SupervisorEnvCall,
+    MachineEnvCall,
+    InstructionPageFault,
+    LoadPageFault,
+    StorePageFault,
+    Reserved(usize),
 }
 
 impl From<usize> for Exception {
@@ -41,7 +96,18 @@ impl From<usize> for Exception {
             0 => Self::InstructionAddressMisaligned,
             1 => Self::InstructionAccessFault,
             2 => Self::IllegalInstruction,
-            _ => todo!(),
+            3 => Self::Breakpoint,
+            4 => Self::LoadAddressMisaligned,
+            5 => Self::LoadAccessFault,
+            6 => Self::StoreAddressMisaligned,
+            7 => Self::StoreAccessFault,
+            8 => Self::UserEnvCall,
+            9 => Self::SupervisorEnvCall,
+            11 => Self::MachineEnvCall,
+            12 => Self::InstructionPageFault,
+            13 => Self::LoadPageFault,
+            15 => Self::StorePageFault,
+            code => Self::Reserved(code),
         }
     }
 }
@@ -49,43 +115,133 @@ impl From<usize> for Exception {
 
 
 #[no_mangle]
-extern "C" fn handle_trap() 
+extern "C" fn handle_trap(frame: &mut TrapFrame)
 {
-    let sepc = ControlStatusRegister::Sepc.read();
-    let status = ControlStatusRegister::SStatus.read(); 
-    let scause = ControlStatusRegister::Scause.read(); 
-    let trap_val = ControlStatusRegister::Scause.read(); 
-    crate::info!("SEPC={:#0x} SSTATUS={:#0x} SCAUSE={:#0x} STVAL={:#0x} ", sepc, status, scause, trap_val);
-
-    if is_interrupt(scause) {
-        handle_interrupt()
+    let hart = hart::current();
+    hart.enter_trap(frame.mode());
+
+    if is_interrupt(frame.scause) {
+        handle_interrupt(frame)
     } else {
-        handle_exception()
+        handle_exception(frame)
     }
-}
 
-fn handle_interrupt() {
-    let scause = ControlStatusRegister::Scause.read(); 
+    // Signals, including another thread ending the process,
+    // are acted on right before going back to U-mode.
+    if frame.mode() == Mode::User {
+        signal::deliver(frame);
+    }
+    hart.exit_trap(frame.mode());
+}
 
-    debug!("Interrupt: {:?}", Interrupt::from(scause));
+fn handle_interrupt(frame: &mut TrapFrame) {
+    match Interrupt::from(frame.scause) {
+        Interrupt::Timer => {
+            crate::drivers::poll_console();
+            sched::timer_tick()
+        },
+        Interrupt::Software => sched::software_interrupt(),
+        interrupt => debug!("Interrupt: {:?}", interrupt),
+    }
+    // Whatever we handled may have woken up a more urgent thread
+    sched::resched_if_needed();
 }
-fn handle_exception() {
+
+fn handle_exception(frame: &mut TrapFrame) {
     use Exception::*;
 
-    let scause = ControlStatusRegister::Scause.read(); 
+    debug!("SEPC={:#0x} SSTATUS={:#0x} SCAUSE={:#0x} STVAL={:#0x} ", frame.pc, frame.sstatus, frame.scause, frame.stval);
 
-    let exception = Exception::from(scause);    
-    debug!("Exception: {:?}", exception);
+    let exception = Exception::from(frame.scause);
+    debug!("Exception: {:?} from {:?}", exception, frame.mode());
 
     match exception {
-        IllegalInstruction => {
+        IllegalInstruction if frame.mode() == Mode::Supervisor => {
             panic!("ILLEGAL INSTRUCTION DETECTED");
         },
-        _ => {},
+        Breakpoint if frame.mode() == Mode::Supervisor => {
+            frame.pc += instruction_len(frame.pc);
+        },
+        UserEnvCall => {
+            // `ecall` is never compressed
+            frame.pc += 4;
+            crate::syscall::dispatch(frame);
+        },
+        InstructionPageFault | LoadPageFault | StorePageFault
+            if frame.mode() == Mode::User && process::handle_page_fault(frame.stval, access(exception)) => {},
+        // Whatever else user code did wrong, it is its own problem.
+        IllegalInstruction | Breakpoint if frame.mode() == Mode::User => {
+            signal::force(fault_signal(exception), frame.pc);
+        },
+        _ if frame.mode() == Mode::User => {
+            signal::force(fault_signal(exception), frame.stval);
+        },
+        LoadPageFault | StorePageFault if frame.mode() == Mode::Supervisor => {
+            // User memory a system call copies to or from may just not be
+            // there yet, or be a bad pointer.
+            if let Some(fixup) = crate::syscall::uaccess::search_fixup(frame.pc) {
+                if !process::handle_page_fault(frame.stval, access(exception)) {
+                    frame.pc = fixup;
+                }
+                return;
+            }
+            if let Some(bottom) = stack::guarded_stack(frame.stval) {
+                error!(
+                    "Kernel stack overflow on hart {}: stack at {:#0x}, sp={:#0x}, pc={:#0x}",
+                    hart::current().id(),
+                    bottom,
+                    frame.regs[TrapFrame::SP],
+                    frame.pc
+                );
+                panic!("Kernel stack overflow on hart {}", hart::current().id());
+            }
+            unhandled_exception(frame, exception);
+        },
+        _ => unhandled_exception(frame, exception),
     }
+}
+
+/// The kind of access a page fault was caused by.
+fn access(exception: Exception) -> Access {
+    match exception {
+        Exception::InstructionPageFault => Access::Execute,
+        Exception::StorePageFault => Access::Write,
+        _ => Access::Read,
+    }
+}
 
-    // Increment the SEPC
-    ControlStatusRegister::Sepc.write(ControlStatusRegister::Sepc.read()+4);
+/// The signal user code gets for causing `exception`.
+fn fault_signal(exception: Exception) -> Signal {
+    match exception {
+        Exception::IllegalInstruction => signal::SIGILL,
+        Exception::Breakpoint => signal::SIGTRAP,
+        Exception::InstructionAddressMisaligned
+        | Exception::LoadAddressMisaligned
+        | Exception::StoreAddressMisaligned => signal::SIGBUS,
+        _ => signal::SIGSEGV,
+    }
+}
+
+fn unhandled_exception(frame: &TrapFrame, exception: Exception) -> ! {
+    error!(
+        "Unhandled exception {:?} from {:?} at {:#0x}, STVAL={:#0x}",
+        exception,
+        frame.mode(),
+        frame.pc,
+        frame.stval
+    );
+    panic!("Unhandled exception: {:?}", exception);
+}
+
+/// Length of the instruction at `pc`, which may be compressed.
+fn instruction_len(pc: usize) -> usize {
+    // The two lowest bits are `11` for every 32-bit instruction,
+    // anything else is a 16-bit compressed instruction.
+    if unsafe { (pc as *const u16).read_volatile() } & 0b11 == 0b11 {
+        4
+    } else {
+        2
+    }
 }
 
 
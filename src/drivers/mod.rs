@@ -1,18 +1,100 @@
 pub mod uart_16550;
 
+use alloc::collections::VecDeque;
+use core::sync::atomic::{AtomicBool, Ordering};
+
 use uart_16550::SerialPort;
 
-use crate::sync::spinlock::{OnceCell, SpinLock};
+use crate::{
+    sync::{
+        spinlock::{OnceCell, SpinLock},
+        WaitQueue,
+    },
+    syscall::SysResult,
+};
 
 pub static mut SERIAL: OnceCell<SpinLock<SerialPort>> = OnceCell::new();
 pub static mut UART_DONE: bool = false;
 
+/// A device processes can hold a handle to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Device {
+    /// The serial console, see [`console_read`] and [`console_write`].
+    Console,
+}
+
+/// Threads waiting for console input.
+static CONSOLE_READERS: WaitQueue = WaitQueue::new();
+
+/// Console input nobody read yet.
+static CONSOLE_INPUT: SpinLock<VecDeque<u8>> = SpinLock::new(VecDeque::new());
+
+/// Most console input kept for readers, past which it is dropped.
+const CONSOLE_INPUT_MAX: usize = 4096;
+
+/// Typing this asks the kernel to log what processes use, the way `^T`
+/// does on BSD, rather than being read.
+const STATUS_KEY: u8 = 0x14;
+
+static STATUS_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+fn serial() -> &'static SpinLock<SerialPort> {
+    unsafe { SERIAL.get_or_init(|| SpinLock::new(SerialPort::new(0x1000_0000))) }
+}
+
+/// Writes raw bytes to the console, which need not be UTF-8.
+pub fn console_write(bytes: &[u8]) {
+    let port = serial().lock();
+    let regs = port.lock();
+    for b in bytes {
+        regs.write_char(*b);
+    }
+}
+
+/// Takes what the UART received, keeping it for readers.
+fn receive() {
+    let port = serial().lock();
+    let mut input = CONSOLE_INPUT.lock();
+    while let Some(c) = port.read_char_non_blocking() {
+        match c as u8 {
+            STATUS_KEY => STATUS_REQUESTED.store(true, Ordering::Relaxed),
+            _ if input.len() >= CONSOLE_INPUT_MAX => {}
+            b => input.push_back(b),
+        }
+    }
+}
+
+/// Reads a byte from the console, if one was received.
+pub fn console_read() -> Option<u8> {
+    receive();
+    CONSOLE_INPUT.lock().pop_front()
+}
+
+/// Reads a byte from the console, sleeping until one is received,
+/// or failing with `EINTR` if the thread is interrupted first.
+pub fn console_read_wait() -> SysResult<u8> {
+    CONSOLE_READERS.wait_interruptible(console_read)
+}
+
+/// Takes console input, waking up threads waiting for it if there is
+/// some, and logs what processes use if asked to. The UART interrupt is
+/// not wired up, so this is called on every timer tick.
+pub fn poll_console() {
+    receive();
+    if !CONSOLE_READERS.is_empty() && !CONSOLE_INPUT.lock().is_empty() {
+        CONSOLE_READERS.wake_all();
+    }
+    if STATUS_REQUESTED.swap(false, Ordering::Relaxed) {
+        crate::process::log_usage();
+    }
+}
+
 #[macro_export]
 macro_rules! print {
      	($($args:tt)+) => ({
                 use core::fmt::Write;
                 unsafe {
-                        if !$crate::drivers::UART_DONE && $crate::cpu::util::my_hart() != 0 {
+                        if !$crate::drivers::UART_DONE && $crate::cpu::util::my_hart() != $crate::cpu::boot_hart() {
                                 while !$crate::drivers::SERIAL.is_initialized() {
                                 core::hint::spin_loop();
                                 }
@@ -162,6 +162,8 @@ impl SerialPort {
         self.regs.lock()
     }
 
+    /// Spins until a character arrives. Threads should rather
+    /// sleep in [`crate::drivers::console_read_wait`].
     pub fn read_char(&self) -> char {
         while !self.regs.lock().read_rdy() {
             core::hint::spin_loop()
@@ -169,6 +171,11 @@ impl SerialPort {
         unsafe { self.regs.lock().data.readb() as char }
     }
 
+    /// Whether a character was received and not read yet.
+    pub fn has_input(&self) -> bool {
+        self.regs.lock().read_rdy()
+    }
+
     pub fn read_char_non_blocking(&self) -> Option<char> {
         let r = self.regs.lock();
         if r.read_rdy() {
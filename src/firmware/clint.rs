@@ -0,0 +1,38 @@
+//! Core Local Interruptor (CLINT) on the QEMU `virt` machine.
+//!
+//! The CLINT owns the machine timer (`mtime`/`mtimecmp`) and the
+//! machine software interrupt (`msip`) of every hart. It is only
+//! reachable from M-mode, S-mode reaches it through the SBI.
+
+const CLINT_BASE: usize = 0x0200_0000;
+const MSIP_OFFSET: usize = 0x0;
+const MTIMECMP_OFFSET: usize = 0x4000;
+const MTIME_OFFSET: usize = 0xbff8;
+
+fn msip(hartid: usize) -> *mut u32 {
+    (CLINT_BASE + MSIP_OFFSET + 4 * hartid) as *mut u32
+}
+
+fn mtimecmp(hartid: usize) -> *mut u64 {
+    (CLINT_BASE + MTIMECMP_OFFSET + 8 * hartid) as *mut u64
+}
+
+/// Current value of the machine timer.
+pub fn mtime() -> u64 {
+    unsafe { ((CLINT_BASE + MTIME_OFFSET) as *const u64).read_volatile() }
+}
+
+/// Programs the timer compare register of `hartid`.
+pub fn set_timer(hartid: usize, value: u64) {
+    unsafe { mtimecmp(hartid).write_volatile(value) }
+}
+
+/// Raises a machine software interrupt on `hartid`.
+pub fn send_soft(hartid: usize) {
+    unsafe { msip(hartid).write_volatile(1) }
+}
+
+/// Acknowledges a machine software interrupt on `hartid`.
+pub fn clear_soft(hartid: usize) {
+    unsafe { msip(hartid).write_volatile(0) }
+}
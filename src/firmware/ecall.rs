@@ -0,0 +1,224 @@
+//! Handlers for the SBI extensions the firmware implements.
+//!
+//! Each handler receives the arguments from `a0`-`a5` of the calling
+//! hart and returns the value to place in `a1`, or the error for `a0`.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{
+    cpu::{csr::ControlStatusRegister, port::Port, MAX_HARTS},
+    sbi::{self, ResetReason, ResetType, SbiError, SbiResult},
+};
+
+use super::{clint, hsm, MachineTrapFrame, MIE_MTIE, MIP_SSIP, MIP_STIP};
+
+/// Implementation ID reported by `sbi_get_impl_id`, picked outside
+/// of the range assigned to known implementations.
+const IMPL_ID: usize = 0x574e;
+
+/// QEMU `virt` test finisher, used for SRST.
+const TEST_FINISHER: usize = 0x10_0000;
+const FINISHER_PASS: u32 = 0x5555;
+const FINISHER_FAIL: u32 = 0x3333;
+const FINISHER_RESET: u32 = 0x7777;
+
+/// UART the legacy console extension writes to.
+const UART_BASE: u32 = 0x1000_0000;
+
+/// Work requested of a hart through its machine software interrupt.
+pub const PENDING_IPI: usize = 1 << 0;
+pub const PENDING_FENCE_I: usize = 1 << 1;
+pub const PENDING_SFENCE_VMA: usize = 1 << 2;
+
+#[link_section = ".data.firmware"]
+static PENDING: [AtomicUsize; MAX_HARTS] = [const { AtomicUsize::new(0) }; MAX_HARTS];
+
+/// Dispatches an `ecall` made from S-mode.
+///
+/// Legacy extensions only return a single value in `a0`,
+/// every other extension returns an `(error, value)` pair.
+pub fn dispatch(hartid: usize, frame: &mut MachineTrapFrame) {
+    let eid = frame.regs[17];
+    let fid = frame.regs[16];
+    let args = [
+        frame.regs[10],
+        frame.regs[11],
+        frame.regs[12],
+        frame.regs[13],
+    ];
+
+    let ret = match eid {
+        sbi::EID_LEGACY_CONSOLE_PUTCHAR => {
+            console_putchar(args[0] as u8);
+            frame.regs[10] = 0;
+            return;
+        }
+        sbi::EID_LEGACY_CONSOLE_GETCHAR => {
+            frame.regs[10] = console_getchar().map_or(usize::MAX, |c| c as usize);
+            return;
+        }
+        sbi::EID_BASE => base(fid, args),
+        sbi::EID_TIME if fid == 0 => set_timer(hartid, args[0] as u64),
+        sbi::EID_IPI if fid == 0 => send_to_harts(hartid, args[0], args[1], PENDING_IPI),
+        sbi::EID_RFENCE => match fid {
+            0 => send_to_harts(hartid, args[0], args[1], PENDING_FENCE_I),
+            // We always flush the whole TLB, so the ASID
+            // variant is handled the same way.
+            1 | 2 => send_to_harts(hartid, args[0], args[1], PENDING_SFENCE_VMA),
+            _ => Err(SbiError::NotSupported),
+        },
+        sbi::EID_HSM => match fid {
+            0 => hsm::hart_start(args[0], args[1], args[2]),
+            1 => {
+                hsm::hart_stop(hartid);
+                let (start_addr, opaque) = hsm::wait_for_start(hartid);
+                super::restart_in_frame(frame, hartid, start_addr, opaque);
+                return;
+            }
+            2 => hsm::hart_get_status(args[0]),
+            _ => Err(SbiError::NotSupported),
+        },
+        sbi::EID_SRST if fid == 0 => system_reset(args[0], args[1]),
+        _ => Err(SbiError::NotSupported),
+    };
+
+    match ret {
+        Ok(value) => {
+            frame.regs[10] = 0;
+            frame.regs[11] = value;
+        }
+        Err(e) => frame.regs[10] = e.code() as usize,
+    }
+}
+
+fn base(fid: usize, args: [usize; 4]) -> SbiResult<usize> {
+    match fid {
+        0 => Ok(sbi::SPEC_VERSION),
+        1 => Ok(IMPL_ID),
+        2 => Ok(env!("CARGO_PKG_VERSION_MINOR").parse().unwrap_or(0)),
+        3 => Ok(matches!(
+            args[0],
+            sbi::EID_BASE
+                | sbi::EID_TIME
+                | sbi::EID_IPI
+                | sbi::EID_RFENCE
+                | sbi::EID_HSM
+                | sbi::EID_SRST
+                | sbi::EID_LEGACY_CONSOLE_PUTCHAR
+                | sbi::EID_LEGACY_CONSOLE_GETCHAR
+        ) as usize),
+        4 => Ok(ControlStatusRegister::Mvendorid.read()),
+        5 => Ok(ControlStatusRegister::Marchid.read()),
+        6 => Ok(ControlStatusRegister::Mimpid.read()),
+        _ => Err(SbiError::NotSupported),
+    }
+}
+
+fn set_timer(hartid: usize, stime_value: u64) -> SbiResult<usize> {
+    clint::set_timer(hartid, stime_value);
+    // The supervisor timer interrupt we may have injected is acknowledged
+    // by programming the next event, and we want to hear about that one.
+    ControlStatusRegister::Mip.clear_bits(MIP_STIP);
+    ControlStatusRegister::Mie.set_bits(MIE_MTIE);
+    Ok(0)
+}
+
+/// Hands `work` to every hart selected by `hart_mask`/`hart_mask_base`
+/// and kicks them with a machine software interrupt.
+///
+/// Fences are waited for, so that once the call returns every
+/// targeted hart is guaranteed to have executed it.
+fn send_to_harts(hartid: usize, hart_mask: usize, hart_mask_base: usize, work: usize) -> SbiResult<usize> {
+    let targets = targeted_harts(hart_mask, hart_mask_base)?;
+
+    for hart in (0..MAX_HARTS).filter(|h| targets & (1 << h) != 0) {
+        if hsm::hart_get_status(hart) != Ok(sbi::HartState::Started as usize) {
+            continue;
+        }
+        PENDING[hart].fetch_or(work, Ordering::AcqRel);
+        if hart == hartid {
+            process_pending(hartid);
+        } else {
+            clint::send_soft(hart);
+        }
+    }
+
+    if work != PENDING_IPI {
+        for hart in (0..MAX_HARTS).filter(|h| targets & (1 << h) != 0) {
+            while PENDING[hart].load(Ordering::Acquire) & work != 0 {
+                // The target may itself be waiting on us.
+                process_pending(hartid);
+                core::hint::spin_loop();
+            }
+        }
+    }
+    Ok(0)
+}
+
+fn targeted_harts(hart_mask: usize, hart_mask_base: usize) -> SbiResult<usize> {
+    if hart_mask_base == usize::MAX {
+        return Ok((1 << MAX_HARTS) - 1);
+    }
+    if hart_mask_base >= MAX_HARTS {
+        return Err(SbiError::InvalidParam);
+    }
+    Ok((hart_mask << hart_mask_base) & ((1 << MAX_HARTS) - 1))
+}
+
+/// Performs the work other harts asked of us.
+pub fn process_pending(hartid: usize) {
+    let work = PENDING[hartid].swap(0, Ordering::AcqRel);
+
+    if work & PENDING_FENCE_I != 0 {
+        unsafe { core::arch::asm!("fence.i") };
+    }
+    if work & PENDING_SFENCE_VMA != 0 {
+        unsafe { core::arch::asm!("sfence.vma") };
+    }
+    if work & PENDING_IPI != 0 {
+        ControlStatusRegister::Mip.set_bits(MIP_SSIP);
+    }
+}
+
+fn system_reset(ty: usize, reason: usize) -> SbiResult<usize> {
+    let value = match ty {
+        t if t == ResetType::Shutdown as usize && reason == ResetReason::SystemFailure as usize => {
+            FINISHER_FAIL | (1 << 16)
+        }
+        t if t == ResetType::Shutdown as usize => FINISHER_PASS,
+        t if t == ResetType::ColdReboot as usize || t == ResetType::WarmReboot as usize => {
+            FINISHER_RESET
+        }
+        _ => return Err(SbiError::InvalidParam),
+    };
+
+    unsafe { (TEST_FINISHER as *mut u32).write_volatile(value) };
+
+    // The finisher should have taken the machine down with it.
+    Err(SbiError::Failed)
+}
+
+/// Writes straight to the UART, without going through the kernel's
+/// `SERIAL` lock, as the hart we trapped from may well be holding it.
+pub fn console_putchar(c: u8) {
+    let data = Port::new(UART_BASE);
+    let line_status = Port::new(UART_BASE + 5);
+    unsafe {
+        while line_status.readb() & 0x20 == 0 {
+            core::hint::spin_loop();
+        }
+        data.writeb(c);
+    }
+}
+
+fn console_getchar() -> Option<u8> {
+    let data = Port::new(UART_BASE);
+    let line_status = Port::new(UART_BASE + 5);
+    unsafe {
+        if line_status.readb() & 0x1 == 1 {
+            Some(data.readb())
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! Hart State Management.
+//!
+//! Every hart but the boot hart is parked in M-mode as soon as the firmware
+//! is set up, until the kernel asks for it with `sbi_hart_start`. Harts
+//! stopped with `sbi_hart_stop` return to the same parked state.
+//!
+//! The boot hart marks every hart the device tree lists as stopped before
+//! it enters the kernel, so the kernel can start a hart that is still on
+//! its way to being parked: it picks up the request once it gets there.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::{
+    cpu::{csr::ControlStatusRegister, MAX_HARTS},
+    sbi::{HartState, SbiError, SbiResult},
+    util::fdt::{self, DeviceTree},
+    warn,
+};
+
+use super::{clint, MIP_MSIP};
+
+struct HartSlot {
+    /// Set for the harts the device tree lists, or once a hart reached
+    /// the firmware, so we never try to start a hart QEMU was not given.
+    present: AtomicBool,
+    state: AtomicUsize,
+    /// Set once `start_addr` and `opaque` hold what the hart was started
+    /// with, which `hart_start` only writes after it won the start.
+    start_ready: AtomicBool,
+    start_addr: AtomicUsize,
+    opaque: AtomicUsize,
+}
+
+impl HartSlot {
+    const fn new() -> Self {
+        Self {
+            present: AtomicBool::new(false),
+            state: AtomicUsize::new(HartState::Stopped as usize),
+            start_ready: AtomicBool::new(false),
+            start_addr: AtomicUsize::new(0),
+            opaque: AtomicUsize::new(0),
+        }
+    }
+
+    fn state(&self) -> HartState {
+        HartState::try_from(self.state.load(Ordering::Acquire)).unwrap()
+    }
+
+    fn set_state(&self, s: HartState) {
+        self.state.store(s as usize, Ordering::Release);
+    }
+}
+
+#[link_section = ".data.firmware"]
+static HARTS: [HartSlot; MAX_HARTS] = [const { HartSlot::new() }; MAX_HARTS];
+
+/// Marks `hartid` as the one running the kernel from the very start.
+pub fn mark_boot_hart(hartid: usize) {
+    HARTS[hartid].present.store(true, Ordering::Release);
+    HARTS[hartid].set_state(HartState::Started);
+}
+
+/// Marks every hart listed under `/cpus` in the device tree at `dtb` as
+/// present. They start out stopped, whether or not they are parked yet.
+pub fn mark_present(dtb: usize) {
+    let tree = match unsafe { DeviceTree::from_addr(dtb) } {
+        Ok(tree) => tree,
+        Err(e) => return warn!("Unable to list the harts: {}, only parked ones can be started", e),
+    };
+    tree.for_each_child("/cpus", "reg", |reg| {
+        if let Some(slot) = fdt::read_cells(reg).and_then(|id| HARTS.get(id as usize)) {
+            slot.present.store(true, Ordering::Release);
+        }
+    });
+}
+
+/// Parks the calling hart until somebody calls `sbi_hart_start` on it,
+/// and returns the `(start_addr, opaque)` pair it was started with.
+/// A hart that was asked to start before it got here goes right away.
+pub fn wait_for_start(hartid: usize) -> (usize, usize) {
+    let slot = &HARTS[hartid];
+    // Stopping harts are stopped once here, and harts that were just
+    // powered on already are, unless someone started them meanwhile.
+    let _ = slot.state.compare_exchange(
+        HartState::StopPending as usize,
+        HartState::Stopped as usize,
+        Ordering::AcqRel,
+        Ordering::Acquire,
+    );
+    slot.present.store(true, Ordering::Release);
+
+    // Only a software interrupt can wake us from here,
+    // `wfi` does not need MIE to be set to return.
+    let mie = ControlStatusRegister::Mie.read();
+    ControlStatusRegister::Mie.write(super::MIE_MSIE);
+
+    while !slot.start_ready.load(Ordering::Acquire) {
+        unsafe { core::arch::asm!("wfi") };
+        clint::clear_soft(hartid);
+    }
+    // What `hart_start` sent when it came before we waited.
+    clint::clear_soft(hartid);
+    ControlStatusRegister::Mip.clear_bits(MIP_MSIP);
+    ControlStatusRegister::Mie.write(mie);
+
+    let start = (
+        slot.start_addr.load(Ordering::Acquire),
+        slot.opaque.load(Ordering::Acquire),
+    );
+    slot.start_ready.store(false, Ordering::Release);
+    slot.set_state(HartState::Started);
+    start
+}
+
+pub fn hart_start(hartid: usize, start_addr: usize, opaque: usize) -> SbiResult<usize> {
+    let slot = HARTS.get(hartid).ok_or(SbiError::InvalidParam)?;
+    if !slot.present.load(Ordering::Acquire) {
+        return Err(SbiError::InvalidParam);
+    }
+    if start_addr == 0 {
+        return Err(SbiError::InvalidAddress);
+    }
+
+    // Only the caller that moves the hart out of `Stopped` gets to say
+    // where it starts, a concurrent one fails without touching that.
+    slot.state
+        .compare_exchange(
+            HartState::Stopped as usize,
+            HartState::StartPending as usize,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        )
+        .map_err(|_| SbiError::AlreadyAvailable)?;
+    slot.start_addr.store(start_addr, Ordering::Release);
+    slot.opaque.store(opaque, Ordering::Release);
+    slot.start_ready.store(true, Ordering::Release);
+
+    clint::send_soft(hartid);
+    Ok(0)
+}
+
+pub fn hart_get_status(hartid: usize) -> SbiResult<usize> {
+    match HARTS.get(hartid) {
+        Some(slot) if slot.present.load(Ordering::Acquire) => Ok(slot.state() as usize),
+        _ => Err(SbiError::InvalidParam),
+    }
+}
+
+/// Marks the calling hart as stopping, the caller is
+/// expected to then call [`wait_for_start`].
+pub fn hart_stop(hartid: usize) {
+    HARTS[hartid].set_state(HartState::StopPending);
+}
@@ -0,0 +1,192 @@
+//! Walnut's M-mode firmware.
+//!
+//! We boot QEMU with `-bios none`, so nothing but us runs in M-mode.
+//! This module is the small amount of machine-level code needed to
+//! provide the S-mode kernel with a Supervisor Binary Interface, so
+//! that the kernel itself only ever talks to the machine through
+//! `ecall` (see [`crate::sbi`]) and can run unchanged on top of a
+//! real SBI implementation like OpenSBI.
+//!
+//! The firmware keeps a small stack per hart, whose top lives in
+//! `mscratch` while the hart is running in S-mode, and handles every
+//! trap that is not delegated to S-mode in `machinevec` (see `asm/mtrap.s`).
+
+use crate::{
+    cpu::{
+        csr::ControlStatusRegister,
+        delegate_traps,
+        mode::{self, Mode},
+        trap::TrapFrame,
+        transition,
+        pmp::{Permissions, Pmp},
+        SSTATUS_SIE,
+    },
+    error, FIRMWARE_END, FIRMWARE_START, TEXT_END, TEXT_START,
+};
+
+pub mod clint;
+pub mod ecall;
+pub mod hsm;
+
+/// The hart that is sent into the kernel straight away,
+/// every other one waits for `sbi_hart_start`.
+pub const BOOT_HART: usize = 0;
+
+
+pub const MIP_SSIP: usize = 1 << 1;
+pub const MIP_MSIP: usize = 1 << 3;
+pub const MIP_STIP: usize = 1 << 5;
+pub const MIE_MSIE: usize = 1 << 3;
+pub const MIE_MTIE: usize = 1 << 7;
+
+const MCAUSE_INTERRUPT: usize = 1 << 63;
+const IRQ_M_SOFT: usize = 3;
+const IRQ_M_TIMER: usize = 7;
+const EXC_ECALL_FROM_S: usize = 9;
+
+extern "C" {
+    fn machinevec();
+}
+
+/// General purpose registers of the interrupted hart, indexed
+/// by register number (so `a0` is `regs[10]`), as saved by `machinevec`.
+#[repr(C)]
+pub struct MachineTrapFrame {
+    pub regs: [usize; 32],
+}
+
+/// Sets up the calling hart's M-mode state: trap vector, delegation
+/// of everything S-mode can handle itself, and counter access.
+///
+/// Must run in M-mode, with `mscratch` already pointing
+/// to the top of this hart's firmware stack.
+pub fn init_hart() {
+    ControlStatusRegister::Mtvec.write(machinevec as *const () as usize);
+
+    delegate_traps();
+
+    // Let S-mode (and U-mode through it) read `cycle`, `time` and `instret`
+    ControlStatusRegister::Mcounteren.write(0b111);
+
+    // The machine timer is only armed once the kernel asks for it
+    ControlStatusRegister::Mie.write(MIE_MSIE);
+
+    crate::cpu::set_isa(ControlStatusRegister::Misa.read());
+}
+
+/// Configures Physical Memory Protection for the calling hart:
+///
+/// - the `.firmware` section (the M-mode entry point and trap vector,
+///   stacks, and the statics placed in `.data.firmware`) is off-limits
+///   to S-mode. The rest of the firmware's Rust code is linked into the
+///   kernel's `.text` with everything else, so it is covered below
+/// - the kernel's `.text` can be read and executed, but not written
+/// - everything else is fully accessible
+///
+/// None of these are locked, so M-mode itself is not restricted.
+pub fn protect_memory() -> crate::Result<()> {
+    let mut pmp = Pmp::new();
+    unsafe {
+        pmp.add_tor(FIRMWARE_START, FIRMWARE_END, Permissions::NONE, false)?;
+        pmp.add_tor(TEXT_START, TEXT_END, Permissions::READ | Permissions::EXEC, false)?;
+    }
+    pmp.add_napot(0, 1 << 56, Permissions::ALL, false)?;
+
+    crate::main_thread_only!({
+        Pmp::dump();
+    });
+    Ok(())
+}
+
+/// Boots the S-mode kernel on `hartid`.
+///
+/// The boot hart goes straight to `entry`, every other hart is parked until
+/// the kernel starts it through the HSM extension.
+pub fn boot(hartid: usize, entry: usize, dtb: usize) -> ! {
+    if hartid == BOOT_HART {
+        hsm::mark_present(dtb);
+        hsm::mark_boot_hart(hartid);
+        unsafe { enter_supervisor(hartid, entry, dtb) }
+    }
+
+    let (start_addr, opaque) = hsm::wait_for_start(hartid);
+    unsafe { enter_supervisor(hartid, start_addr, opaque) }
+}
+
+/// Drops into S-mode at `addr` with paging disabled,
+/// `a0 = hartid` and `a1 = opaque`, as the SBI specifies.
+///
+/// # Safety
+///
+/// `addr` must point to code that is able to run from that state.
+unsafe fn enter_supervisor(hartid: usize, addr: usize, opaque: usize) -> ! {
+    ControlStatusRegister::Satp.write(0);
+
+    let mut frame = TrapFrame {
+        pc: addr,
+        ..Default::default()
+    };
+    frame.set_arg(0, hartid);
+    frame.set_arg(1, opaque);
+
+    match transition(Mode::Supervisor, &mut frame) {
+        Err(e) => panic!("Unable to enter the kernel on hart {}: {}", hartid, e),
+    }
+}
+
+/// Rewrites the trap frame of a hart that was stopped from an `ecall`,
+/// so that returning from the trap lands in S-mode at `addr`.
+fn restart_in_frame(frame: &mut MachineTrapFrame, hartid: usize, addr: usize, opaque: usize) {
+    mode::set_prev_privilege_mode(Mode::Supervisor);
+    ControlStatusRegister::Mstatus.clear_bits(SSTATUS_SIE);
+    ControlStatusRegister::Mepc.write(addr);
+    ControlStatusRegister::Satp.write(0);
+
+    frame.regs[10] = hartid;
+    frame.regs[11] = opaque;
+}
+
+#[no_mangle]
+extern "C" fn handle_machine_trap(frame: &mut MachineTrapFrame) {
+    let hartid = ControlStatusRegister::Mhartid.read();
+    let mcause = ControlStatusRegister::Mcause.read();
+
+    if mcause & MCAUSE_INTERRUPT != 0 {
+        match mcause & !MCAUSE_INTERRUPT {
+            IRQ_M_TIMER => {
+                // Forward the timer to S-mode, and keep quiet
+                // until the kernel programs the next event.
+                ControlStatusRegister::Mie.clear_bits(MIE_MTIE);
+                ControlStatusRegister::Mip.set_bits(MIP_STIP);
+            }
+            IRQ_M_SOFT => {
+                clint::clear_soft(hartid);
+                ecall::process_pending(hartid);
+            }
+            _ => unhandled_trap(hartid, mcause),
+        }
+        return;
+    }
+
+    match mcause {
+        EXC_ECALL_FROM_S => {
+            // Return past the `ecall`, unless the call moved us somewhere else
+            ControlStatusRegister::Mepc.write(ControlStatusRegister::Mepc.read() + 4);
+            ecall::dispatch(hartid, frame);
+        }
+        _ => unhandled_trap(hartid, mcause),
+    }
+}
+
+fn unhandled_trap(hartid: usize, mcause: usize) -> ! {
+    error!(
+        "Firmware: unhandled trap on hart {}: MCAUSE={:#0x} MEPC={:#0x} MTVAL={:#0x}",
+        hartid,
+        mcause,
+        ControlStatusRegister::Mepc.read(),
+        ControlStatusRegister::Mtval.read()
+    );
+    loop {
+        unsafe { core::arch::asm!("wfi") };
+    }
+}
@@ -0,0 +1,102 @@
+//! Reading `newc` cpio archives, the format of initramfs images.
+//!
+//! Every member is a header of 13 fields in ASCII hex, its NUL terminated
+//! name and its data, each padded to 4 bytes, and the archive ends with a
+//! member called `TRAILER!!!`.
+
+use crate::syscall::{errno::Errno, SysResult};
+
+const MAGIC: &[u8] = b"070701";
+/// The same as [`MAGIC`], with a checksum of the data we do not check.
+const MAGIC_CRC: &[u8] = b"070702";
+const HEADER_SIZE: usize = 110;
+const TRAILER: &str = "TRAILER!!!";
+
+/// A member of an archive.
+#[derive(Debug, Clone, Copy)]
+pub struct Entry<'a> {
+    pub name: &'a str,
+    pub ino: u32,
+    /// File type and permission bits, as in `st_mode`.
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub nlink: u32,
+    /// Seconds since the epoch.
+    pub mtime: u32,
+    pub dev_major: u32,
+    pub dev_minor: u32,
+    /// The file's data, or where a symbolic link points.
+    pub data: &'a [u8],
+}
+
+/// Iterates over the members of an archive, up to the trailer or the
+/// first that is not valid.
+pub struct Archive<'a> {
+    data: &'a [u8],
+    offset: usize,
+    done: bool,
+}
+
+impl<'a> Archive<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0, done: false }
+    }
+
+    fn next_entry(&mut self) -> SysResult<Option<Entry<'a>>> {
+        // Archives may be padded with zeroes, like several concatenated ones.
+        while self.data.get(self.offset..self.offset + 4) == Some(&[0; 4]) {
+            self.offset += 4;
+        }
+        if self.offset >= self.data.len() {
+            return Ok(None);
+        }
+
+        let header = self.data.get(self.offset..self.offset + HEADER_SIZE).ok_or(Errno::EINVAL)?;
+        if &header[..6] != MAGIC && &header[..6] != MAGIC_CRC {
+            return Err(Errno::EINVAL);
+        }
+        let field = |i: usize| -> SysResult<u32> {
+            let hex = core::str::from_utf8(&header[6 + i * 8..14 + i * 8]).map_err(|_| Errno::EINVAL)?;
+            u32::from_str_radix(hex, 16).map_err(|_| Errno::EINVAL)
+        };
+        let (size, name_size) = (field(6)? as usize, field(11)? as usize);
+
+        let name_start = self.offset + HEADER_SIZE;
+        let name = self.data.get(name_start..name_start + name_size).ok_or(Errno::EINVAL)?;
+        let name = name.strip_suffix(&[0]).ok_or(Errno::EINVAL)?;
+        let name = core::str::from_utf8(name).map_err(|_| Errno::EINVAL)?;
+        let data_start = (name_start + name_size).next_multiple_of(4);
+        let data = self.data.get(data_start..data_start + size).ok_or(Errno::EINVAL)?;
+        self.offset = (data_start + size).next_multiple_of(4);
+
+        if name == TRAILER {
+            return Ok(None);
+        }
+        Ok(Some(Entry {
+            name,
+            ino: field(0)?,
+            mode: field(1)?,
+            uid: field(2)?,
+            gid: field(3)?,
+            nlink: field(4)?,
+            mtime: field(5)?,
+            dev_major: field(7)?,
+            dev_minor: field(8)?,
+            data,
+        }))
+    }
+}
+
+impl<'a> Iterator for Archive<'a> {
+    type Item = SysResult<Entry<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let entry = self.next_entry().transpose();
+        self.done = !matches!(entry, Some(Ok(_)));
+        entry
+    }
+}
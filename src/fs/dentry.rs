@@ -0,0 +1,118 @@
+//! Directory entries: inodes under the names they were found by.
+//!
+//! Dentries make up the tree paths are resolved in, across filesystems.
+//! Every dentry keeps its parent alive, while a directory only remembers
+//! the children someone still uses, so the tree holds just the paths in
+//! use. A filesystem mounted on a dentry hides it behind the root dentry
+//! of that filesystem, which takes its place in the tree.
+
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+
+use crate::{sync::spinlock::SpinLock, syscall::SysResult};
+
+use super::inode::{FileType, Inode};
+
+pub struct Dentry {
+    name: String,
+    inode: Arc<dyn Inode>,
+    /// `None` for the root of the tree.
+    parent: Option<Arc<Dentry>>,
+    children: SpinLock<BTreeMap<String, Weak<Dentry>>>,
+    /// Root of the filesystem mounted here, if any.
+    mounted: SpinLock<Option<Arc<Dentry>>>,
+}
+
+impl Dentry {
+    pub fn new(name: &str, inode: Arc<dyn Inode>, parent: Option<Arc<Dentry>>) -> Arc<Self> {
+        Arc::new(Self {
+            name: String::from(name),
+            inode,
+            parent,
+            children: SpinLock::new(BTreeMap::new()),
+            mounted: SpinLock::new(None),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn inode(&self) -> &Arc<dyn Inode> {
+        &self.inode
+    }
+
+    pub fn parent(&self) -> Option<&Arc<Dentry>> {
+        self.parent.as_ref()
+    }
+
+    pub fn kind(&self) -> FileType {
+        self.inode.metadata().kind
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.kind() == FileType::Directory
+    }
+
+    /// What is seen at this dentry: the root of the filesystem mounted
+    /// last on it, or the dentry itself.
+    pub fn mounted(self: &Arc<Self>) -> Arc<Dentry> {
+        let mut dentry = self.clone();
+        loop {
+            let Some(mounted) = dentry.mounted.lock().clone() else {
+                return dentry;
+            };
+            dentry = mounted;
+        }
+    }
+
+    /// Puts `root`, the root dentry of a filesystem, in place of this one.
+    pub(super) fn mount(self: &Arc<Self>, root: Arc<Dentry>) {
+        *self.mounted().mounted.lock() = Some(root);
+    }
+
+    /// The child called `name`, looked up in the directory unless it was
+    /// already, with whatever is mounted on it.
+    pub fn child(self: &Arc<Self>, name: &str) -> SysResult<Arc<Dentry>> {
+        if let Some(child) = self.children.lock().get(name).and_then(Weak::upgrade) {
+            return Ok(child.mounted());
+        }
+        let inode = self.inode.lookup(name)?;
+        let child = Dentry::new(name, inode, Some(self.clone()));
+        let mut children = self.children.lock();
+        // Someone else may have looked it up meanwhile.
+        if let Some(existing) = children.get(name).and_then(Weak::upgrade) {
+            return Ok(existing.mounted());
+        }
+        children.retain(|_, c| c.strong_count() > 0);
+        children.insert(String::from(name), Arc::downgrade(&child));
+        Ok(child)
+    }
+
+    /// Forgets the child called `name`, which is gone from the directory.
+    pub fn forget(&self, name: &str) {
+        self.children.lock().remove(name);
+    }
+
+    /// The absolute path of the dentry.
+    pub fn path(&self) -> String {
+        let mut names = Vec::new();
+        let mut dentry = self;
+        while let Some(parent) = &dentry.parent {
+            names.push(dentry.name.as_str());
+            dentry = parent;
+        }
+        if names.is_empty() {
+            return String::from("/");
+        }
+        names.iter().rev().fold(String::new(), |mut path, name| {
+            path.push('/');
+            path.push_str(name);
+            path
+        })
+    }
+}
@@ -0,0 +1,155 @@
+//! Open files.
+//!
+//! A [`File`] is what opening a path gives: the dentry it was found at,
+//! what to read and write it through, and an offset. Handles duplicated
+//! from one another, or inherited by forked children, share it, offset
+//! and all.
+
+use alloc::{sync::Arc, vec::Vec};
+
+use crate::{
+    sync::Mutex,
+    syscall::{errno::Errno, SysResult},
+};
+
+use super::{
+    dentry::Dentry,
+    inode::{DirEntry, FileOps, FileType, Metadata},
+};
+
+/// Where `seek` counts from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Whence {
+    Start,
+    Current,
+    End,
+}
+
+pub struct File {
+    dentry: Arc<Dentry>,
+    /// `None` for directories.
+    ops: Option<Arc<dyn FileOps>>,
+    /// Where the next read or write goes, or for directories the index of
+    /// the next entry.
+    offset: Mutex<u64>,
+    /// Writes go to the end of the file, wherever the offset is.
+    append: bool,
+}
+
+impl File {
+    /// Opens the inode at `dentry`.
+    pub fn open(dentry: Arc<Dentry>, append: bool) -> SysResult<Arc<Self>> {
+        let ops = match dentry.kind() {
+            FileType::Directory => None,
+            _ => Some(dentry.inode().clone().open()?),
+        };
+        Ok(Arc::new(Self { dentry, ops, offset: Mutex::new(0), append }))
+    }
+
+    pub fn dentry(&self) -> &Arc<Dentry> {
+        &self.dentry
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        self.dentry.inode().metadata()
+    }
+
+    fn ops(&self) -> SysResult<&Arc<dyn FileOps>> {
+        self.ops.as_ref().ok_or(Errno::EISDIR)
+    }
+
+    /// Reads into `buf` from the offset, moving it past what was read.
+    pub fn read(&self, buf: &mut [u8]) -> SysResult<usize> {
+        let ops = self.ops()?;
+        if !ops.seekable() {
+            return ops.read(0, buf);
+        }
+        let mut offset = self.offset.lock();
+        let n = ops.read(*offset, buf)?;
+        *offset += n as u64;
+        Ok(n)
+    }
+
+    /// Reads from the offset to the end of the file, as big as it was
+    /// when this started.
+    pub fn read_to_end(&self) -> SysResult<Vec<u8>> {
+        let size = usize::try_from(self.metadata().size).map_err(|_| Errno::EFBIG)?;
+        let mut data = Vec::new();
+        data.try_reserve_exact(size).map_err(|_| Errno::ENOMEM)?;
+        data.resize(size, 0);
+        let mut done = 0;
+        while done < data.len() {
+            match self.read(&mut data[done..])? {
+                0 => break,
+                n => done += n,
+            }
+        }
+        data.truncate(done);
+        Ok(data)
+    }
+
+    /// Writes `data` at the offset, or at the end of the file for files
+    /// opened to append, moving the offset past what was written.
+    pub fn write(&self, data: &[u8]) -> SysResult<usize> {
+        let ops = self.ops()?;
+        if !ops.seekable() {
+            return ops.write(0, data);
+        }
+        let mut offset = self.offset.lock();
+        if self.append {
+            *offset = self.metadata().size;
+        }
+        let n = ops.write(*offset, data)?;
+        *offset += n as u64;
+        Ok(n)
+    }
+
+    pub fn truncate(&self, size: u64) -> SysResult<()> {
+        self.ops()?.truncate(size)
+    }
+
+    /// Moves the offset to `offset` from `whence`, returning where it ends up.
+    /// Directories can only go back to their start.
+    pub fn seek(&self, offset: i64, whence: Whence) -> SysResult<u64> {
+        if self.ops.as_ref().is_some_and(|ops| !ops.seekable()) {
+            return Err(Errno::ESPIPE);
+        }
+        let mut current = self.offset.lock();
+        if self.ops.is_none() && (whence, offset) != (Whence::Start, 0) {
+            return Err(Errno::EINVAL);
+        }
+        let base = match whence {
+            Whence::Start => 0,
+            Whence::Current => *current,
+            Whence::End => self.metadata().size,
+        };
+        let new = base.checked_add_signed(offset).ok_or(Errno::EINVAL)?;
+        *current = new;
+        Ok(new)
+    }
+
+    /// The next entry of the directory, `.` and `..` first, moving past it
+    /// once `take` accepts it.
+    pub fn next_entry(&self, take: impl FnOnce(&DirEntry) -> bool) -> SysResult<Option<DirEntry>> {
+        if self.ops.is_some() {
+            return Err(Errno::ENOTDIR);
+        }
+        let mut index = self.offset.lock();
+        let entry = match *index {
+            0 => Some(DirEntry { name: ".".into(), ino: self.metadata().ino, kind: FileType::Directory }),
+            1 => {
+                let parent = self.dentry.parent().map_or(self.dentry.clone(), |p| p.mounted());
+                let ino = parent.inode().metadata().ino;
+                Some(DirEntry { name: "..".into(), ino, kind: FileType::Directory })
+            }
+            i => self.dentry.inode().readdir(i as usize - 2)?,
+        };
+        match entry {
+            Some(entry) if take(&entry) => {
+                *index += 1;
+                Ok(Some(entry))
+            }
+            _ => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! The initramfs: an archive of the files user space starts out with,
+//! unpacked into a [`RamFs`] mounted at `/`, from which `/init` is run.
+//!
+//! The archive is built into the kernel when `WALNUT_INITRAMFS` names a
+//! `newc` cpio archive at build time, and the boot loader may load one next
+//! to the kernel, like QEMU does with `-initrd`, telling us where through
+//! the device tree. When there are both, the loaded one is unpacked over
+//! the built-in one. A [`Tmpfs`] is mounted on `/tmp`, if the archive
+//! has that directory.
+
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+
+use crate::{
+    debug, info,
+    mem::{
+        pages::{PAGE_ALLOCATOR, PAGE_SIZE},
+        table,
+    },
+    process::{self, thread},
+    sync::spinlock::SpinLock,
+    syscall::{errno::Errno, SysResult},
+    util::fdt::{self, DeviceTree},
+    warn, HEAP_SIZE, HEAP_START, KERNEL_STACK_END,
+};
+
+use super::{
+    cpio::{Archive, Entry},
+    ramfs::RamFs,
+    tmpfs::Tmpfs,
+    Dentry, FileType, Inode, OpenOptions,
+};
+
+#[cfg(initramfs)]
+static BUILTIN: &[u8] = include_bytes!(env!("WALNUT_INITRAMFS"));
+#[cfg(not(initramfs))]
+static BUILTIN: &[u8] = &[];
+
+/// The program started as pid 1.
+const INIT: &str = "/init";
+const INIT_ENV: &[&str] = &["HOME=/", "TERM=linux"];
+
+/// Where a tmpfs is mounted, if the initramfs has a directory there.
+const TMP: &str = "/tmp";
+
+/// Where the boot loader put its archive.
+struct Loaded {
+    start: usize,
+    end: usize,
+    /// Whether it is in memory the page allocator manages, and was taken
+    /// out of it. Anywhere else it is past all the memory we use.
+    reserved: bool,
+}
+
+static LOADED: SpinLock<Option<Loaded>> = SpinLock::new(None);
+
+/// Looks for an archive the boot loader loaded in the device tree at `dtb`,
+/// and keeps the page allocator from handing out the memory it is in.
+///
+/// # Safety
+/// This has to run right after the page allocator is set up, before it
+/// handed out pages the archive may be in.
+pub unsafe fn reserve(dtb: usize) {
+    let tree = match DeviceTree::from_addr(dtb) {
+        Ok(tree) => tree,
+        Err(e) => return warn!("Unable to read the device tree: {}", e),
+    };
+    let cells = |name| tree.property("/chosen", name).and_then(fdt::read_cells);
+    let (Some(start), Some(end)) = (cells("linux,initrd-start"), cells("linux,initrd-end")) else {
+        return debug!("The boot loader did not load an initramfs");
+    };
+    let (start, end) = (start as usize, end as usize);
+    if start >= end {
+        return warn!("Ignoring the initramfs at {:#x}, it ends at {:#x}", start, end);
+    }
+
+    // Anywhere the allocator does not manage, it has to be clear of all
+    // the memory the kernel uses, the heap and the kernel stacks.
+    let reserved = PAGE_ALLOCATOR.reserve(start, end);
+    if !reserved && start < (HEAP_START + HEAP_SIZE).max(KERNEL_STACK_END) {
+        return warn!("Ignoring the initramfs at {:#x}, it is in memory the kernel uses", start);
+    }
+    info!("Found an initramfs of {} bytes at {:#x}", end - start, start);
+    *LOADED.lock() = Some(Loaded { start, end, reserved });
+}
+
+/// Unpacks the initramfs and starts `/init`, from a kernel thread. The
+/// boot hart calls this once it runs threads.
+pub fn start() {
+    if let Err(e) = thread::spawn("initramfs", run) {
+        warn!("Unable to start unpacking the initramfs: {}", e);
+    }
+}
+
+fn run() {
+    if let Err(e) = super::mount(RamFs::new(), "/") {
+        return warn!("Unable to mount the root filesystem: {}", e);
+    }
+    let Ok(root) = super::root() else { return };
+
+    let mut files = 0;
+    if !BUILTIN.is_empty() {
+        files += unpack("built-in", BUILTIN, &root);
+    }
+    let loaded = LOADED.lock().take();
+    if let Some(loaded) = loaded {
+        if !loaded.reserved {
+            // Past the memory the kernel maps.
+            if let Err(e) = table::id_map_range(loaded.start, loaded.end, table::PTE_READ) {
+                return warn!("Unable to map the initramfs: {}", e);
+            }
+            table::flush_tlb();
+        }
+        let archive = unsafe { core::slice::from_raw_parts(loaded.start as *const u8, loaded.end - loaded.start) };
+        files += unpack("loaded", archive, &root);
+        if loaded.reserved {
+            for page in (loaded.start & !(PAGE_SIZE - 1)..loaded.end).step_by(PAGE_SIZE) {
+                unsafe { PAGE_ALLOCATOR.dealloc(page as *const u8) };
+            }
+        }
+    }
+    if files == 0 {
+        return warn!("There is no initramfs, not starting {}", INIT);
+    }
+    if super::lookup(None, TMP, true).is_ok_and(|tmp| tmp.is_dir()) {
+        if let Err(e) = super::mount(Tmpfs::new(Tmpfs::default_size()), TMP) {
+            warn!("Unable to mount a tmpfs at {}: {}", TMP, e);
+        }
+    }
+
+    match read_init() {
+        Ok(data) => match process::spawn_init(&data, &[INIT], INIT_ENV) {
+            Ok(init) => info!("Started {} as pid {}", INIT, init.pid()),
+            Err(e) => warn!("Unable to start {}: {}", INIT, e),
+        },
+        Err(e) => warn!("Unable to read {}: {}", INIT, e),
+    }
+}
+
+/// Unpacks `archive` into `root`, returning how many members it had.
+/// Members that can not be unpacked are skipped, and a corrupted archive
+/// is unpacked up to where it is corrupted.
+fn unpack(what: &str, archive: &[u8], root: &Arc<Dentry>) -> usize {
+    // The first member of each set of hard links, by device and inode.
+    let mut links = BTreeMap::new();
+    let mut count = 0;
+    for entry in Archive::new(archive) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_) => {
+                warn!("The {} initramfs is corrupted past {} members", what, count);
+                break;
+            }
+        };
+        count += 1;
+        if let Err(e) = unpack_entry(&entry, root, &mut links) {
+            warn!("Unable to unpack {} from the initramfs: {}", entry.name, e);
+        }
+    }
+    info!("Unpacked {} members of the {} initramfs", count, what);
+    count
+}
+
+fn unpack_entry(entry: &Entry, root: &Arc<Dentry>, links: &mut BTreeMap<(u32, u32, u32), Arc<dyn Inode>>) -> SysResult<()> {
+    let path = entry.name.trim_start_matches("./");
+    if path.is_empty() || path == "." {
+        return Ok(());
+    }
+    let (dir, name) = super::lookup_parent(Some(root.clone()), path)?;
+    let dir_inode = dir.inode();
+    let mode = entry.mode & 0o7777;
+    // Archive times are wall clock times, which we have none of.
+    match FileType::from_mode(entry.mode) {
+        Some(FileType::Directory) => match dir_inode.create(&name, FileType::Directory, mode) {
+            Err(Errno::EEXIST) if dir.child(&name)?.is_dir() => Ok(()),
+            created => created.map(|_| ()),
+        },
+        Some(FileType::Regular) => {
+            remove(&dir, &name)?;
+            let key = (entry.dev_major, entry.dev_minor, entry.ino);
+            // Hard links share the data, which only the last of them has.
+            let inode = match links.get(&key) {
+                Some(inode) => {
+                    dir_inode.link(&name, inode)?;
+                    inode.clone()
+                }
+                None => dir_inode.create(&name, FileType::Regular, mode)?,
+            };
+            if !entry.data.is_empty() {
+                inode.clone().open()?.write(0, entry.data)?;
+            }
+            if entry.nlink > 1 {
+                links.insert(key, inode);
+            }
+            Ok(())
+        }
+        Some(FileType::Symlink) => {
+            remove(&dir, &name)?;
+            let target = core::str::from_utf8(entry.data).map_err(|_| Errno::EINVAL)?;
+            dir_inode.symlink(&name, target).map(|_| ())
+        }
+        // Device files and FIFOs, which there is nothing to back yet.
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+/// Removes whatever non-directory is called `name` in `dir`, to be replaced.
+fn remove(dir: &Arc<Dentry>, name: &str) -> SysResult<()> {
+    match dir.inode().lookup(name) {
+        Ok(inode) if inode.metadata().kind == FileType::Directory => Err(Errno::EISDIR),
+        Ok(_) => {
+            dir.inode().unlink(name)?;
+            dir.forget(name);
+            Ok(())
+        }
+        Err(Errno::ENOENT) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+fn read_init() -> SysResult<Vec<u8>> {
+    super::open(None, INIT, &OpenOptions::default())?.read_to_end()
+}
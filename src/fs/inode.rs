@@ -0,0 +1,162 @@
+//! What filesystems and drivers implement: [`FileSystem`]s hold [`Inode`]s,
+//! which open as [`FileOps`].
+
+use alloc::{string::String, sync::Arc};
+
+use crate::syscall::{errno::Errno, SysResult};
+
+pub type Ino = u64;
+
+/// Bits of `st_mode` that tell the type of a file.
+pub const S_IFMT: u32 = 0o170000;
+
+/// What an inode is, with the values `st_mode` has for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    Regular = 0o100000,
+    Directory = 0o040000,
+    Symlink = 0o120000,
+    CharDevice = 0o020000,
+    Fifo = 0o010000,
+}
+
+impl FileType {
+    /// The type the file type bits of `mode` stand for, if we have it.
+    pub fn from_mode(mode: u32) -> Option<Self> {
+        [Self::Regular, Self::Directory, Self::Symlink, Self::CharDevice, Self::Fifo]
+            .into_iter()
+            .find(|&kind| mode & S_IFMT == kind as u32)
+    }
+
+    /// The type as `getdents64` gives it in `d_type`.
+    pub fn dirent_type(self) -> u8 {
+        match self {
+            Self::Regular => 8,
+            Self::Directory => 4,
+            Self::Symlink => 10,
+            Self::CharDevice => 2,
+            Self::Fifo => 1,
+        }
+    }
+}
+
+/// What `stat` tells about an inode. Times are in timer ticks since boot
+/// (see [`crate::process::sched::now`]), as there is no wall clock.
+#[derive(Debug, Clone, Copy)]
+pub struct Metadata {
+    /// Which filesystem the inode is in.
+    pub dev: u64,
+    pub ino: Ino,
+    pub kind: FileType,
+    /// Permission bits, `0o7777` at most.
+    pub mode: u32,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// The device a device file stands for.
+    pub rdev: u64,
+    pub size: u64,
+    /// Bytes of storage the inode takes, in 512 byte blocks.
+    pub blocks: u64,
+    pub atime: u64,
+    pub mtime: u64,
+    pub ctime: u64,
+}
+
+/// An entry of a directory, as read from it.
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub ino: Ino,
+    pub kind: FileType,
+}
+
+pub trait FileSystem: Send + Sync {
+    /// The directory at the top of the filesystem.
+    fn root(&self) -> Arc<dyn Inode>;
+
+    /// What kind of filesystem it is, like `ramfs`.
+    fn name(&self) -> &'static str;
+}
+
+/// A file, directory or anything else with a name in a filesystem.
+///
+/// Operations on directories fail with `ENOTDIR` unless implemented, and
+/// names passed to them are single path components, never `.` or `..`.
+pub trait Inode: Send + Sync {
+    fn metadata(&self) -> Metadata;
+
+    /// The inode called `name` in this directory.
+    fn lookup(&self, _name: &str) -> SysResult<Arc<dyn Inode>> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Creates an empty file or directory called `name` in this directory,
+    /// failing with `EEXIST` if there is one.
+    fn create(&self, _name: &str, _kind: FileType, _mode: u32) -> SysResult<Arc<dyn Inode>> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Creates a symbolic link called `name` in this directory, pointing
+    /// at `target`.
+    fn symlink(&self, _name: &str, _target: &str) -> SysResult<Arc<dyn Inode>> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Gives `inode` another name, `name` in this directory. Inodes can
+    /// only be linked within their filesystem, `EXDEV` otherwise.
+    fn link(&self, _name: &str, _inode: &Arc<dyn Inode>) -> SysResult<()> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Removes the name `name` from this directory, and the inode with it
+    /// if it was its last name. Directories have to be empty.
+    fn unlink(&self, _name: &str) -> SysResult<()> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// The entry at `index` of this directory, if there are that many.
+    fn readdir(&self, _index: usize) -> SysResult<Option<DirEntry>> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Changes the permission bits to `mode`.
+    fn set_mode(&self, _mode: u32) -> SysResult<()> {
+        Err(Errno::EPERM)
+    }
+
+    /// Where this symbolic link points.
+    fn read_link(&self) -> SysResult<String> {
+        Err(Errno::EINVAL)
+    }
+
+    /// What to read and write through when the inode is opened. Only
+    /// directories, which are read with [`Inode::readdir`], have none.
+    fn open(self: Arc<Self>) -> SysResult<Arc<dyn FileOps>> {
+        Err(Errno::EISDIR)
+    }
+}
+
+/// The data of an open file, as a filesystem or driver gives access to it.
+/// Offsets are only meaningful to seekable files.
+pub trait FileOps: Send + Sync {
+    /// Reads into `buf` from `offset`, returning how much was read, 0 at the end.
+    fn read(&self, _offset: u64, _buf: &mut [u8]) -> SysResult<usize> {
+        Err(Errno::EINVAL)
+    }
+
+    /// Writes `data` at `offset`, returning how much was written.
+    fn write(&self, _offset: u64, _data: &[u8]) -> SysResult<usize> {
+        Err(Errno::EINVAL)
+    }
+
+    /// Cuts the file down, or extends it with zeroes, to `size` bytes.
+    fn truncate(&self, _size: u64) -> SysResult<()> {
+        Err(Errno::EINVAL)
+    }
+
+    /// Whether the file has offsets, unlike a device or a pipe.
+    fn seekable(&self) -> bool {
+        true
+    }
+}
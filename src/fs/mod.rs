@@ -0,0 +1,302 @@
+//! The virtual file system.
+//!
+//! Filesystems and drivers implement [`Inode`] and [`FileOps`], and are
+//! [`mount`]ed somewhere in a single tree of [`Dentry`]s, starting at `/`.
+//! Paths are resolved in that tree, following symbolic links and crossing
+//! into mounted filesystems, relative to the root or to a directory like
+//! the working directory of a process. Opening a path gives a [`File`],
+//! which processes hold through their handle table.
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{
+    info,
+    sync::spinlock::SpinLock,
+    syscall::{errno::Errno, SysResult},
+};
+
+pub mod cpio;
+pub mod dentry;
+pub mod file;
+pub mod initramfs;
+pub mod inode;
+pub mod ramfs;
+pub mod tmpfs;
+
+pub use dentry::Dentry;
+pub use file::{File, Whence};
+pub use inode::{DirEntry, FileOps, FileSystem, FileType, Inode, Metadata};
+
+/// Longest path, including the NUL at its end.
+pub const PATH_MAX: usize = 4096;
+
+/// Longest name of a single path component.
+pub const NAME_MAX: usize = 255;
+
+/// How many symbolic links a single resolution follows before giving up.
+const MAX_SYMLINKS: usize = 40;
+
+/// The root of the tree, once something is mounted there.
+static ROOT: SpinLock<Option<Arc<Dentry>>> = SpinLock::new(None);
+
+struct Mount {
+    path: String,
+    fs: Arc<dyn FileSystem>,
+    /// What the filesystem is mounted on, kept so it stays in the tree.
+    _mountpoint: Option<Arc<Dentry>>,
+}
+
+static MOUNTS: SpinLock<Vec<Mount>> = SpinLock::new(Vec::new());
+
+static NEXT_DEV: AtomicU64 = AtomicU64::new(1);
+
+/// A device number for a filesystem that is not on a device, like one in memory.
+pub fn anonymous_dev() -> u64 {
+    NEXT_DEV.fetch_add(1, Ordering::Relaxed)
+}
+
+/// What is at `/`.
+pub fn root() -> SysResult<Arc<Dentry>> {
+    let root = ROOT.lock().clone();
+    root.map(|r| r.mounted()).ok_or(Errno::ENOENT)
+}
+
+/// Mounts `fs` on the directory at `path`, hiding what was there. The
+/// first filesystem has to be mounted at `/`.
+pub fn mount(fs: Arc<dyn FileSystem>, path: &str) -> SysResult<()> {
+    let mountpoint = match path {
+        "/" => ROOT.lock().clone(),
+        path => Some(lookup(None, path, true)?),
+    };
+    let root = match &mountpoint {
+        Some(mountpoint) if !mountpoint.is_dir() => return Err(Errno::ENOTDIR),
+        Some(mountpoint) => {
+            let root = Dentry::new(mountpoint.name(), fs.root(), mountpoint.parent().cloned());
+            mountpoint.mount(root.clone());
+            root
+        }
+        None => {
+            let root = Dentry::new("/", fs.root(), None);
+            *ROOT.lock() = Some(root.clone());
+            root
+        }
+    };
+    info!("Mounted {} at {}", fs.name(), root.path());
+    MOUNTS.lock().push(Mount { path: root.path(), fs, _mountpoint: mountpoint });
+    Ok(())
+}
+
+/// Logs every mounted filesystem.
+pub fn log_mounts() {
+    let mounts = MOUNTS.lock();
+    info!("Mounts:");
+    for mount in mounts.iter() {
+        info!("  {} on {}", mount.fs.name(), mount.path);
+    }
+}
+
+/// Splits `path` into the directory part and its last component, leaving
+/// out slashes at the end. That of `/` is `.`.
+fn split_last(path: &str) -> (&str, &str) {
+    let trimmed = path.trim_end_matches('/');
+    match trimmed.rfind('/') {
+        Some(i) => (&trimmed[..i + 1], &trimmed[i + 1..]),
+        None if trimmed.is_empty() => ("/", "."),
+        None => ("", trimmed),
+    }
+}
+
+/// Resolves `path` relative to `dir`, or to the root if there is none or
+/// the path is absolute. A symbolic link at the end is followed only if
+/// `follow` is set.
+pub fn lookup(dir: Option<Arc<Dentry>>, path: &str, follow: bool) -> SysResult<Arc<Dentry>> {
+    let mut links = 0;
+    resolve(dir, path, follow, &mut links)
+}
+
+/// Resolves all of `path` but its last component, returning the directory
+/// it is in and the name of it, which may be `.` or `..`.
+pub fn lookup_parent(dir: Option<Arc<Dentry>>, path: &str) -> SysResult<(Arc<Dentry>, String)> {
+    if path.is_empty() {
+        return Err(Errno::ENOENT);
+    }
+    let (dir_path, name) = split_last(path);
+    let dir = match dir_path {
+        "" => start(dir, "")?,
+        dir_path => lookup(dir, dir_path, true)?,
+    };
+    if !dir.is_dir() {
+        return Err(Errno::ENOTDIR);
+    }
+    Ok((dir, String::from(name)))
+}
+
+/// Creates an empty file or directory at `path`, failing with `EEXIST`
+/// if there is something there already.
+pub fn create(dir: Option<Arc<Dentry>>, path: &str, kind: FileType, mode: u32) -> SysResult<Arc<Dentry>> {
+    let (parent, name) = lookup_parent(dir, path)?;
+    if name == "." || name == ".." {
+        return Err(Errno::EEXIST);
+    }
+    if kind != FileType::Directory && path.ends_with('/') {
+        return Err(Errno::EISDIR);
+    }
+    parent.inode().create(&name, kind, mode & 0o7777)?;
+    parent.child(&name)
+}
+
+/// Removes the name at `path`, which has to be an empty directory if
+/// `directory` is set, and anything but a directory otherwise.
+pub fn unlink(dir: Option<Arc<Dentry>>, path: &str, directory: bool) -> SysResult<()> {
+    let (parent, name) = lookup_parent(dir, path)?;
+    match name.as_str() {
+        "." => return Err(Errno::EINVAL),
+        ".." => return Err(Errno::ENOTEMPTY),
+        _ => {}
+    }
+    let child = parent.child(&name)?;
+    match (child.is_dir(), directory) {
+        (false, true) => return Err(Errno::ENOTDIR),
+        (true, false) => return Err(Errno::EISDIR),
+        (false, false) if path.ends_with('/') => return Err(Errno::ENOTDIR),
+        _ => {}
+    }
+    // What is found there is something mounted on it, rather than itself.
+    let found = parent.inode().lookup(&name)?.metadata();
+    let seen = child.inode().metadata();
+    if (found.dev, found.ino) != (seen.dev, seen.ino) {
+        return Err(Errno::EBUSY);
+    }
+    parent.inode().unlink(&name)?;
+    parent.forget(&name);
+    Ok(())
+}
+
+/// Creates a symbolic link at `path` pointing at `target`.
+pub fn symlink(dir: Option<Arc<Dentry>>, path: &str, target: &str) -> SysResult<()> {
+    if target.is_empty() {
+        return Err(Errno::ENOENT);
+    }
+    let (parent, name) = new_name(dir, path)?;
+    parent.inode().symlink(&name, target).map(|_| ())
+}
+
+/// Gives what is at `old`, which can not be a directory, the name `path` too.
+pub fn link(old: &Dentry, dir: Option<Arc<Dentry>>, path: &str) -> SysResult<()> {
+    if old.is_dir() {
+        return Err(Errno::EPERM);
+    }
+    let (parent, name) = new_name(dir, path)?;
+    parent.inode().link(&name, old.inode())
+}
+
+/// The directory a new name at `path` goes in and the name, which can not
+/// be `.` or `..` as those always exist.
+fn new_name(dir: Option<Arc<Dentry>>, path: &str) -> SysResult<(Arc<Dentry>, String)> {
+    let (parent, name) = lookup_parent(dir, path)?;
+    if name == "." || name == ".." {
+        return Err(Errno::EEXIST);
+    }
+    if path.ends_with('/') {
+        return Err(Errno::ENOENT);
+    }
+    Ok((parent, name))
+}
+
+/// How to [`open`] a path.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OpenOptions {
+    /// Whether the file is going to be written, which directories can not be.
+    pub write: bool,
+    /// Create a regular file with these permissions if there is none.
+    pub create: Option<u32>,
+    /// Fail with `EEXIST` if there is a file already, when creating one.
+    pub exclusive: bool,
+    /// Empty the file once open.
+    pub truncate: bool,
+    /// Write to the end of the file, wherever the offset is.
+    pub append: bool,
+    /// Fail with `ENOTDIR` unless the path is a directory.
+    pub directory: bool,
+    /// Fail with `ELOOP` if the path is a symbolic link, rather than
+    /// opening where it points.
+    pub nofollow: bool,
+}
+
+/// Opens the file at `path`, creating it if asked to.
+pub fn open(dir: Option<Arc<Dentry>>, path: &str, options: &OpenOptions) -> SysResult<Arc<File>> {
+    let found = lookup(dir.clone(), path, !options.nofollow);
+    let dentry = match (found, options.create) {
+        (Ok(_), Some(_)) if options.exclusive => return Err(Errno::EEXIST),
+        (Ok(dentry), _) => dentry,
+        (Err(Errno::ENOENT), Some(mode)) => match create(dir.clone(), path, FileType::Regular, mode) {
+            // Someone else created it meanwhile.
+            Err(Errno::EEXIST) if !options.exclusive => lookup(dir, path, !options.nofollow)?,
+            created => created?,
+        },
+        (Err(e), _) => return Err(e),
+    };
+
+    match dentry.kind() {
+        FileType::Symlink => return Err(Errno::ELOOP),
+        FileType::Directory if options.write || options.create.is_some() => return Err(Errno::EISDIR),
+        FileType::Directory => {}
+        _ if options.directory => return Err(Errno::ENOTDIR),
+        _ => {}
+    }
+    let file = File::open(dentry, options.append)?;
+    if options.truncate && options.write && file.metadata().kind == FileType::Regular {
+        file.truncate(0)?;
+    }
+    Ok(file)
+}
+
+/// Where resolving `path` starts.
+fn start(dir: Option<Arc<Dentry>>, path: &str) -> SysResult<Arc<Dentry>> {
+    match dir {
+        Some(dir) if !path.starts_with('/') => Ok(dir),
+        _ => root(),
+    }
+}
+
+fn resolve(dir: Option<Arc<Dentry>>, path: &str, follow: bool, links: &mut usize) -> SysResult<Arc<Dentry>> {
+    if path.is_empty() {
+        return Err(Errno::ENOENT);
+    }
+    if path.len() >= PATH_MAX {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    let mut current = start(dir, path)?;
+    let mut components = path.split('/').filter(|c| !c.is_empty()).peekable();
+    while let Some(name) = components.next() {
+        if !current.is_dir() {
+            return Err(Errno::ENOTDIR);
+        }
+        if name.len() > NAME_MAX {
+            return Err(Errno::ENAMETOOLONG);
+        }
+        let next = match name {
+            "." => current.clone(),
+            ".." => current.parent().map_or(current.clone(), |p| p.mounted()),
+            name => current.child(name)?,
+        };
+        let last = components.peek().is_none();
+        // Paths ending in a slash name directories, links to them included.
+        let follow_this = !last || follow || path.ends_with('/');
+        if next.kind() == FileType::Symlink && follow_this {
+            *links += 1;
+            if *links > MAX_SYMLINKS {
+                return Err(Errno::ELOOP);
+            }
+            let target = next.inode().read_link()?;
+            current = resolve(Some(current), &target, true, links)?;
+        } else {
+            current = next;
+        }
+    }
+    if path.ends_with('/') && !current.is_dir() {
+        return Err(Errno::ENOTDIR);
+    }
+    Ok(current)
+}
@@ -0,0 +1,297 @@
+//! A filesystem that lives in the kernel heap, which the initramfs is
+//! unpacked into.
+//!
+//! Nothing bounds how much it holds, so only the kernel itself should put
+//! files in it.
+
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{
+    process::sched,
+    sync::{spinlock::SpinLock, Mutex},
+    syscall::{errno::Errno, SysResult},
+};
+
+use super::inode::{DirEntry, FileOps, FileSystem, FileType, Ino, Inode, Metadata};
+
+pub struct RamFs {
+    dev: u64,
+    next_ino: AtomicU64,
+    root: Arc<RamInode>,
+    /// Every inode by number, for linking them.
+    inodes: SpinLock<BTreeMap<Ino, Weak<RamInode>>>,
+}
+
+impl RamFs {
+    pub fn new() -> Arc<Self> {
+        Arc::new_cyclic(|fs: &Weak<RamFs>| {
+            let root = RamInode::new(fs.clone(), super::anonymous_dev(), 1, Content::dir(), 0o755);
+            let inodes = BTreeMap::from([(1, Arc::downgrade(&root))]);
+            Self { dev: root.dev, next_ino: AtomicU64::new(2), root, inodes: SpinLock::new(inodes) }
+        })
+    }
+
+    fn add(self: &Arc<Self>, content: Content, mode: u32) -> Arc<RamInode> {
+        let ino = self.next_ino.fetch_add(1, Ordering::Relaxed);
+        let inode = RamInode::new(Arc::downgrade(self), self.dev, ino, content, mode);
+        let mut inodes = self.inodes.lock();
+        inodes.retain(|_, i| i.strong_count() > 0);
+        inodes.insert(ino, Arc::downgrade(&inode));
+        inode
+    }
+}
+
+impl FileSystem for RamFs {
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+
+    fn name(&self) -> &'static str {
+        "ramfs"
+    }
+}
+
+enum Content {
+    Directory(SpinLock<BTreeMap<String, Arc<RamInode>>>),
+    Regular(Mutex<Vec<u8>>),
+    Symlink(String),
+}
+
+impl Content {
+    fn dir() -> Self {
+        Self::Directory(SpinLock::new(BTreeMap::new()))
+    }
+
+    fn kind(&self) -> FileType {
+        match self {
+            Self::Directory(_) => FileType::Directory,
+            Self::Regular(_) => FileType::Regular,
+            Self::Symlink(_) => FileType::Symlink,
+        }
+    }
+}
+
+/// What of the metadata changes.
+struct Attributes {
+    mode: u32,
+    nlink: u32,
+    size: u64,
+    atime: u64,
+    mtime: u64,
+    ctime: u64,
+}
+
+struct RamInode {
+    fs: Weak<RamFs>,
+    dev: u64,
+    ino: Ino,
+    content: Content,
+    attributes: SpinLock<Attributes>,
+}
+
+impl RamInode {
+    fn new(fs: Weak<RamFs>, dev: u64, ino: Ino, content: Content, mode: u32) -> Arc<Self> {
+        let now = sched::now();
+        let (nlink, size) = match &content {
+            Content::Directory(_) => (2, 0),
+            Content::Symlink(target) => (1, target.len() as u64),
+            Content::Regular(_) => (1, 0),
+        };
+        let attributes = Attributes { mode, nlink, size, atime: now, mtime: now, ctime: now };
+        Arc::new(Self { fs, dev, ino, content, attributes: SpinLock::new(attributes) })
+    }
+
+    fn entries(&self) -> SysResult<&SpinLock<BTreeMap<String, Arc<RamInode>>>> {
+        match &self.content {
+            Content::Directory(entries) => Ok(entries),
+            _ => Err(Errno::ENOTDIR),
+        }
+    }
+
+    fn data(&self) -> &Mutex<Vec<u8>> {
+        match &self.content {
+            Content::Regular(data) => data,
+            // Only regular files are ever opened.
+            _ => unreachable!(),
+        }
+    }
+
+    fn fs(&self) -> SysResult<Arc<RamFs>> {
+        self.fs.upgrade().ok_or(Errno::ENOENT)
+    }
+
+    /// Puts `inode` in this directory as `name`, unless there is something.
+    fn insert(&self, name: &str, inode: Arc<RamInode>) -> SysResult<()> {
+        let mut entries = self.entries()?.lock();
+        if entries.contains_key(name) {
+            return Err(Errno::EEXIST);
+        }
+        let is_dir = inode.content.kind() == FileType::Directory;
+        entries.insert(String::from(name), inode);
+        drop(entries);
+        self.touch(|a| a.nlink += is_dir as u32);
+        Ok(())
+    }
+
+    /// Changes the attributes with `f`, as a change of the content.
+    fn touch(&self, f: impl FnOnce(&mut Attributes)) {
+        let mut attributes = self.attributes.lock();
+        f(&mut attributes);
+        let now = sched::now();
+        attributes.mtime = now;
+        attributes.ctime = now;
+    }
+}
+
+impl Inode for RamInode {
+    fn metadata(&self) -> Metadata {
+        let a = self.attributes.lock();
+        Metadata {
+            dev: self.dev,
+            ino: self.ino,
+            kind: self.content.kind(),
+            mode: a.mode,
+            nlink: a.nlink,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            size: a.size,
+            blocks: a.size.div_ceil(512),
+            atime: a.atime,
+            mtime: a.mtime,
+            ctime: a.ctime,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> SysResult<Arc<dyn Inode>> {
+        let entries = self.entries()?.lock();
+        entries.get(name).cloned().map(|i| i as Arc<dyn Inode>).ok_or(Errno::ENOENT)
+    }
+
+    fn create(&self, name: &str, kind: FileType, mode: u32) -> SysResult<Arc<dyn Inode>> {
+        let content = match kind {
+            FileType::Directory => Content::dir(),
+            FileType::Regular => Content::Regular(Mutex::new(Vec::new())),
+            _ => return Err(Errno::EINVAL),
+        };
+        self.entries()?;
+        let inode = self.fs()?.add(content, mode);
+        self.insert(name, inode.clone())?;
+        Ok(inode)
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> SysResult<Arc<dyn Inode>> {
+        self.entries()?;
+        let inode = self.fs()?.add(Content::Symlink(String::from(target)), 0o777);
+        self.insert(name, inode.clone())?;
+        Ok(inode)
+    }
+
+    fn link(&self, name: &str, inode: &Arc<dyn Inode>) -> SysResult<()> {
+        let fs = self.fs()?;
+        let metadata = inode.metadata();
+        if metadata.dev != self.dev {
+            return Err(Errno::EXDEV);
+        }
+        if metadata.kind == FileType::Directory {
+            return Err(Errno::EPERM);
+        }
+        let inode = fs.inodes.lock().get(&metadata.ino).and_then(Weak::upgrade).ok_or(Errno::ENOENT)?;
+        self.insert(name, inode.clone())?;
+        inode.touch(|a| a.nlink += 1);
+        Ok(())
+    }
+
+    fn unlink(&self, name: &str) -> SysResult<()> {
+        let mut entries = self.entries()?.lock();
+        let inode = entries.get(name).ok_or(Errno::ENOENT)?;
+        let is_dir = match &inode.content {
+            Content::Directory(children) if !children.lock().is_empty() => return Err(Errno::ENOTEMPTY),
+            Content::Directory(_) => true,
+            _ => false,
+        };
+        let inode = entries.remove(name).ok_or(Errno::ENOENT)?;
+        drop(entries);
+        inode.touch(|a| a.nlink = if is_dir { 0 } else { a.nlink - 1 });
+        self.touch(|a| a.nlink -= is_dir as u32);
+        Ok(())
+    }
+
+    fn readdir(&self, index: usize) -> SysResult<Option<DirEntry>> {
+        let entries = self.entries()?.lock();
+        Ok(entries.iter().nth(index).map(|(name, inode)| DirEntry {
+            name: name.clone(),
+            ino: inode.ino,
+            kind: inode.content.kind(),
+        }))
+    }
+
+    fn read_link(&self) -> SysResult<String> {
+        match &self.content {
+            Content::Symlink(target) => Ok(target.clone()),
+            _ => Err(Errno::EINVAL),
+        }
+    }
+
+    fn set_mode(&self, mode: u32) -> SysResult<()> {
+        let mut attributes = self.attributes.lock();
+        attributes.mode = mode;
+        attributes.ctime = sched::now();
+        Ok(())
+    }
+
+    fn open(self: Arc<Self>) -> SysResult<Arc<dyn FileOps>> {
+        match &self.content {
+            Content::Regular(_) => Ok(self),
+            Content::Directory(_) => Err(Errno::EISDIR),
+            Content::Symlink(_) => Err(Errno::ELOOP),
+        }
+    }
+}
+
+impl FileOps for RamInode {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> SysResult<usize> {
+        let data = self.data().lock();
+        let start = (offset as usize).min(data.len());
+        let n = buf.len().min(data.len() - start);
+        buf[..n].copy_from_slice(&data[start..start + n]);
+        drop(data);
+        self.attributes.lock().atime = sched::now();
+        Ok(n)
+    }
+
+    fn write(&self, offset: u64, bytes: &[u8]) -> SysResult<usize> {
+        let offset = usize::try_from(offset).map_err(|_| Errno::EFBIG)?;
+        let end = offset.checked_add(bytes.len()).ok_or(Errno::EFBIG)?;
+        let mut data = self.data().lock();
+        let len = data.len();
+        if len < end {
+            data.try_reserve(end - len).map_err(|_| Errno::ENOSPC)?;
+            data.resize(end, 0);
+        }
+        data[offset..end].copy_from_slice(bytes);
+        let size = data.len() as u64;
+        drop(data);
+        self.touch(|a| a.size = size);
+        Ok(bytes.len())
+    }
+
+    fn truncate(&self, size: u64) -> SysResult<()> {
+        let size = usize::try_from(size).map_err(|_| Errno::EFBIG)?;
+        let mut data = self.data().lock();
+        let len = data.len();
+        if size > len {
+            data.try_reserve(size - len).map_err(|_| Errno::ENOSPC)?;
+        }
+        data.resize(size, 0);
+        drop(data);
+        self.touch(|a| a.size = size as u64);
+        Ok(())
+    }
+}
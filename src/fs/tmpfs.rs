@@ -0,0 +1,386 @@
+//! A writable filesystem in memory, for user space to keep files in.
+//!
+//! File data lives in whole pages from the page allocator, allocated as
+//! files are written and left out for holes, so pages of a file can be
+//! mapped as they are. What a filesystem holds is bounded by a number of
+//! pages and a number of inodes, so filling it up fails with `ENOSPC`
+//! rather than running the kernel out of memory.
+
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::{
+    mem::pages::{PAGE_ALLOCATOR, PAGE_SIZE},
+    process::sched,
+    sync::{spinlock::SpinLock, Mutex},
+    syscall::{errno::Errno, SysResult},
+};
+
+use super::{
+    inode::{DirEntry, FileOps, FileSystem, FileType, Ino, Inode, Metadata},
+    PATH_MAX,
+};
+
+pub struct Tmpfs {
+    root: Arc<TmpInode>,
+}
+
+/// What the inodes of a filesystem share.
+struct Shared {
+    dev: u64,
+    next_ino: AtomicU64,
+    /// Every inode by number, for linking them.
+    inodes: SpinLock<BTreeMap<Ino, Weak<TmpInode>>>,
+    max_pages: usize,
+    max_inodes: usize,
+    pages: AtomicUsize,
+    inode_count: AtomicUsize,
+}
+
+impl Shared {
+    /// Counts one more of what `count` counts, unless there are `max` already.
+    fn charge(count: &AtomicUsize, max: usize) -> SysResult<()> {
+        count
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| (n < max).then_some(n + 1))
+            .map(|_| ())
+            .map_err(|_| Errno::ENOSPC)
+    }
+}
+
+impl Tmpfs {
+    /// An empty filesystem that holds up to `size` bytes of data, and as
+    /// many inodes as that is pages.
+    pub fn new(size: usize) -> Arc<Self> {
+        let max_pages = size / PAGE_SIZE;
+        let shared = Arc::new(Shared {
+            dev: super::anonymous_dev(),
+            next_ino: AtomicU64::new(1),
+            inodes: SpinLock::new(BTreeMap::new()),
+            max_pages,
+            max_inodes: max_pages.max(1),
+            pages: AtomicUsize::new(0),
+            inode_count: AtomicUsize::new(0),
+        });
+        let root = TmpInode::new(&shared, Content::dir(), 0o1777).expect("An empty tmpfs has room for its root");
+        Arc::new(Self { root })
+    }
+
+    /// How big a filesystem is by default: half of the memory there is.
+    pub fn default_size() -> usize {
+        (unsafe { PAGE_ALLOCATOR.usable_pages() } / 2) * PAGE_SIZE
+    }
+}
+
+impl FileSystem for Tmpfs {
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+
+    fn name(&self) -> &'static str {
+        "tmpfs"
+    }
+}
+
+enum Content {
+    Directory(SpinLock<BTreeMap<String, Arc<TmpInode>>>),
+    /// Physical addresses of the pages of data, by index in the file.
+    Regular(Mutex<BTreeMap<usize, usize>>),
+    Symlink(String),
+}
+
+impl Content {
+    fn dir() -> Self {
+        Self::Directory(SpinLock::new(BTreeMap::new()))
+    }
+
+    fn kind(&self) -> FileType {
+        match self {
+            Self::Directory(_) => FileType::Directory,
+            Self::Regular(_) => FileType::Regular,
+            Self::Symlink(_) => FileType::Symlink,
+        }
+    }
+}
+
+struct Attributes {
+    mode: u32,
+    nlink: u32,
+    size: u64,
+    /// How many pages of data there are, which holes have none of.
+    pages: usize,
+    atime: u64,
+    mtime: u64,
+    ctime: u64,
+}
+
+struct TmpInode {
+    fs: Arc<Shared>,
+    ino: Ino,
+    content: Content,
+    attributes: SpinLock<Attributes>,
+}
+
+impl TmpInode {
+    fn new(fs: &Arc<Shared>, content: Content, mode: u32) -> SysResult<Arc<Self>> {
+        Shared::charge(&fs.inode_count, fs.max_inodes)?;
+        let ino = fs.next_ino.fetch_add(1, Ordering::Relaxed);
+        let now = sched::now();
+        let (nlink, size) = match &content {
+            Content::Directory(_) => (2, 0),
+            Content::Symlink(target) => (1, target.len() as u64),
+            Content::Regular(_) => (1, 0),
+        };
+        let attributes = Attributes { mode, nlink, size, pages: 0, atime: now, mtime: now, ctime: now };
+        let inode = Arc::new(Self { fs: fs.clone(), ino, content, attributes: SpinLock::new(attributes) });
+
+        let mut inodes = fs.inodes.lock();
+        inodes.retain(|_, i| i.strong_count() > 0);
+        inodes.insert(ino, Arc::downgrade(&inode));
+        Ok(inode)
+    }
+
+    fn entries(&self) -> SysResult<&SpinLock<BTreeMap<String, Arc<TmpInode>>>> {
+        match &self.content {
+            Content::Directory(entries) => Ok(entries),
+            _ => Err(Errno::ENOTDIR),
+        }
+    }
+
+    fn pages(&self) -> &Mutex<BTreeMap<usize, usize>> {
+        match &self.content {
+            Content::Regular(pages) => pages,
+            // Only regular files are ever opened.
+            _ => unreachable!(),
+        }
+    }
+
+    /// Puts `inode` in this directory as `name`, unless there is something.
+    fn insert(&self, name: &str, inode: Arc<TmpInode>) -> SysResult<()> {
+        let mut entries = self.entries()?.lock();
+        if entries.contains_key(name) {
+            return Err(Errno::EEXIST);
+        }
+        let is_dir = inode.content.kind() == FileType::Directory;
+        entries.insert(String::from(name), inode);
+        drop(entries);
+        self.touch(|a| a.nlink += is_dir as u32);
+        Ok(())
+    }
+
+    /// Changes the attributes with `f`, as a change of the content.
+    fn touch(&self, f: impl FnOnce(&mut Attributes)) {
+        let mut attributes = self.attributes.lock();
+        f(&mut attributes);
+        let now = sched::now();
+        attributes.mtime = now;
+        attributes.ctime = now;
+    }
+
+    /// Frees the pages at `from` and past it.
+    fn free_pages(&self, pages: &mut BTreeMap<usize, usize>, from: usize) {
+        let freed = pages.split_off(&from);
+        for &frame in freed.values() {
+            unsafe { PAGE_ALLOCATOR.release(frame as *const u8) };
+        }
+        self.fs.pages.fetch_sub(freed.len(), Ordering::AcqRel);
+        self.attributes.lock().pages -= freed.len();
+    }
+}
+
+impl Drop for TmpInode {
+    fn drop(&mut self) {
+        if let Content::Regular(pages) = &self.content {
+            self.free_pages(&mut pages.lock(), 0);
+        }
+        self.fs.inode_count.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl Inode for TmpInode {
+    fn metadata(&self) -> Metadata {
+        let a = self.attributes.lock();
+        Metadata {
+            dev: self.fs.dev,
+            ino: self.ino,
+            kind: self.content.kind(),
+            mode: a.mode,
+            nlink: a.nlink,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            size: a.size,
+            blocks: (a.pages * PAGE_SIZE / 512) as u64,
+            atime: a.atime,
+            mtime: a.mtime,
+            ctime: a.ctime,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> SysResult<Arc<dyn Inode>> {
+        let entries = self.entries()?.lock();
+        entries.get(name).cloned().map(|i| i as Arc<dyn Inode>).ok_or(Errno::ENOENT)
+    }
+
+    fn create(&self, name: &str, kind: FileType, mode: u32) -> SysResult<Arc<dyn Inode>> {
+        let content = match kind {
+            FileType::Directory => Content::dir(),
+            FileType::Regular => Content::Regular(Mutex::new(BTreeMap::new())),
+            _ => return Err(Errno::EINVAL),
+        };
+        self.entries()?;
+        let inode = TmpInode::new(&self.fs, content, mode)?;
+        self.insert(name, inode.clone())?;
+        Ok(inode)
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> SysResult<Arc<dyn Inode>> {
+        self.entries()?;
+        if target.len() >= PATH_MAX {
+            return Err(Errno::ENAMETOOLONG);
+        }
+        let inode = TmpInode::new(&self.fs, Content::Symlink(String::from(target)), 0o777)?;
+        self.insert(name, inode.clone())?;
+        Ok(inode)
+    }
+
+    fn link(&self, name: &str, inode: &Arc<dyn Inode>) -> SysResult<()> {
+        self.entries()?;
+        let metadata = inode.metadata();
+        if metadata.dev != self.fs.dev {
+            return Err(Errno::EXDEV);
+        }
+        if metadata.kind == FileType::Directory {
+            return Err(Errno::EPERM);
+        }
+        let inode = self.fs.inodes.lock().get(&metadata.ino).and_then(Weak::upgrade).ok_or(Errno::ENOENT)?;
+        self.insert(name, inode.clone())?;
+        inode.touch(|a| a.nlink += 1);
+        Ok(())
+    }
+
+    fn unlink(&self, name: &str) -> SysResult<()> {
+        let mut entries = self.entries()?.lock();
+        let inode = entries.get(name).ok_or(Errno::ENOENT)?;
+        let is_dir = match &inode.content {
+            Content::Directory(children) if !children.lock().is_empty() => return Err(Errno::ENOTEMPTY),
+            Content::Directory(_) => true,
+            _ => false,
+        };
+        let inode = entries.remove(name).ok_or(Errno::ENOENT)?;
+        drop(entries);
+        inode.touch(|a| a.nlink = if is_dir { 0 } else { a.nlink - 1 });
+        self.touch(|a| a.nlink -= is_dir as u32);
+        // Open files keep the inode, and its data, until they are closed.
+        Ok(())
+    }
+
+    fn readdir(&self, index: usize) -> SysResult<Option<DirEntry>> {
+        let entries = self.entries()?.lock();
+        self.attributes.lock().atime = sched::now();
+        Ok(entries.iter().nth(index).map(|(name, inode)| DirEntry {
+            name: name.clone(),
+            ino: inode.ino,
+            kind: inode.content.kind(),
+        }))
+    }
+
+    fn read_link(&self) -> SysResult<String> {
+        match &self.content {
+            Content::Symlink(target) => Ok(target.clone()),
+            _ => Err(Errno::EINVAL),
+        }
+    }
+
+    fn set_mode(&self, mode: u32) -> SysResult<()> {
+        let mut attributes = self.attributes.lock();
+        attributes.mode = mode;
+        attributes.ctime = sched::now();
+        Ok(())
+    }
+
+    fn open(self: Arc<Self>) -> SysResult<Arc<dyn FileOps>> {
+        match &self.content {
+            Content::Regular(_) => Ok(self),
+            Content::Directory(_) => Err(Errno::EISDIR),
+            Content::Symlink(_) => Err(Errno::ELOOP),
+        }
+    }
+}
+
+impl FileOps for TmpInode {
+    fn read(&self, offset: u64, buf: &mut [u8]) -> SysResult<usize> {
+        let pages = self.pages().lock();
+        let size = self.attributes.lock().size;
+        let start = offset.min(size) as usize;
+        let end = start + buf.len().min(size as usize - start);
+        let mut pos = start;
+        while pos < end {
+            let (index, in_page) = (pos / PAGE_SIZE, pos % PAGE_SIZE);
+            let n = (PAGE_SIZE - in_page).min(end - pos);
+            let out = &mut buf[pos - start..pos - start + n];
+            match pages.get(&index) {
+                Some(&frame) => out.copy_from_slice(unsafe {
+                    core::slice::from_raw_parts((frame + in_page) as *const u8, n)
+                }),
+                None => out.fill(0),
+            }
+            pos += n;
+        }
+        self.attributes.lock().atime = sched::now();
+        Ok(end - start)
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> SysResult<usize> {
+        let start = usize::try_from(offset).map_err(|_| Errno::EFBIG)?;
+        let end = start.checked_add(data.len()).ok_or(Errno::EFBIG)?;
+        let mut pages = self.pages().lock();
+        let mut pos = start;
+        while pos < end {
+            let (index, in_page) = (pos / PAGE_SIZE, pos % PAGE_SIZE);
+            let n = (PAGE_SIZE - in_page).min(end - pos);
+            let frame = match pages.get(&index) {
+                Some(&frame) => frame,
+                None => {
+                    let allocated = Shared::charge(&self.fs.pages, self.fs.max_pages).and_then(|()| {
+                        let frame = unsafe { PAGE_ALLOCATOR.zalloc(1) };
+                        frame.ok_or_else(|| {
+                            self.fs.pages.fetch_sub(1, Ordering::AcqRel);
+                            Errno::ENOSPC
+                        })
+                    });
+                    match allocated {
+                        Ok(frame) => {
+                            self.attributes.lock().pages += 1;
+                            *pages.entry(index).or_insert(frame as usize)
+                        }
+                        // Whatever was written so far stays.
+                        Err(_) if pos > start => break,
+                        Err(e) => return Err(e),
+                    }
+                }
+            };
+            let page = unsafe { core::slice::from_raw_parts_mut((frame + in_page) as *mut u8, n) };
+            page.copy_from_slice(&data[pos - start..pos - start + n]);
+            pos += n;
+        }
+        self.touch(|a| a.size = a.size.max(pos as u64));
+        Ok(pos - start)
+    }
+
+    fn truncate(&self, size: u64) -> SysResult<()> {
+        let size = usize::try_from(size).map_err(|_| Errno::EFBIG)?;
+        let mut pages = self.pages().lock();
+        self.free_pages(&mut pages, size.div_ceil(PAGE_SIZE));
+        // What is left of the last page reads as zeroes if the file grows again.
+        if let Some(&frame) = pages.get(&(size / PAGE_SIZE)) {
+            let in_page = size % PAGE_SIZE;
+            unsafe { core::ptr::write_bytes((frame + in_page) as *mut u8, 0, PAGE_SIZE - in_page) };
+        }
+        self.touch(|a| a.size = size as u64);
+        Ok(())
+    }
+}
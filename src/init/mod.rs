@@ -4,27 +4,43 @@
 #[macro_use]
 pub mod log;
 
-use crate::cpu::{
-    csr::ControlStatusRegister, delegate_traps, mode::Mode, save_hartid, transition, util::my_hart,
+#[cfg(not(feature = "opensbi"))]
+use crate::{
+    cpu::{csr::ControlStatusRegister, save_hartid, util::my_hart},
+    firmware,
 };
 
+#[cfg(not(feature = "opensbi"))]
+extern "C" {
+    fn _supervisor_entry();
+}
+
+/// First Rust code to run on every hart, in M-mode.
+///
+/// This sets up the firmware, then either enters the kernel
+/// or waits for it to start us (see `firmware::boot`).
+///
+/// When booted by OpenSBI we never run in M-mode, and
+/// the kernel is entered directly at `_supervisor_entry`.
+#[cfg(not(feature = "opensbi"))]
 #[no_mangle]
-extern "C" fn kinit() -> ! {
+extern "C" fn kinit(hartid: usize, dtb: usize) -> ! {
     save_hartid();
     info!("Initializing Hardware Thread {}", my_hart());
 
     // Disable paging (for now)
     ControlStatusRegister::Satp.write(0);
 
-    delegate_traps();
+    firmware::init_hart();
 
     // configure PMP (Physical Memory Protection)
     // so supervisor mode can access all of physical memory
-    ControlStatusRegister::Pmpaddr0.write(0x3fffffffffffff);
-    ControlStatusRegister::Pmpcfg0.write(0xf);
+    // but the firmware, and cannot write to its own code.
+    if let Err(e) = firmware::protect_memory() {
+        panic!("Unable to configure physical memory protection: {}", e);
+    }
 
-    // TODO: ask for interrupts
     // TODO: why does xv6 keep hartid in tp reg for cpuid?
 
-    unsafe { transition(Mode::Supervisor) }
+    firmware::boot(hartid, _supervisor_entry as *const () as usize, dtb)
 }
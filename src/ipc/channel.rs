@@ -0,0 +1,186 @@
+//! Channels: two endpoints passing messages, each made of bytes and
+//! kernel objects, to one another.
+
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{
+    process::handle::{Capability, Object},
+    sync::{spinlock::SpinLock, WaitQueue},
+    syscall::{errno::Errno, SysResult},
+};
+
+/// Largest message, in bytes.
+pub const MAX_MESSAGE_SIZE: usize = 4096;
+
+/// Most kernel objects a message can carry.
+pub const MAX_MESSAGE_HANDLES: usize = 16;
+
+/// How many messages can wait for an endpoint before senders have to.
+pub const CHANNEL_CAPACITY: usize = 64;
+
+pub struct Message {
+    pub data: Vec<u8>,
+    /// Objects moving to whoever receives the message, with the rights
+    /// the sender held them with.
+    pub handles: Vec<Capability>,
+}
+
+/// One end of a channel, and the messages sent to it.
+struct Side {
+    queue: SpinLock<VecDeque<Message>>,
+    /// How many [`Endpoint`]s of this side are open.
+    open: AtomicUsize,
+    /// Receivers waiting for a message.
+    readable: WaitQueue,
+    /// Senders waiting for room.
+    writable: WaitQueue,
+}
+
+impl Side {
+    fn new() -> Self {
+        Self {
+            queue: SpinLock::new(VecDeque::new()),
+            open: AtomicUsize::new(1),
+            readable: WaitQueue::new(),
+            writable: WaitQueue::new(),
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        self.open.load(Ordering::Acquire) != 0
+    }
+}
+
+struct Channel {
+    sides: [Side; 2],
+}
+
+/// Creates a channel, returning its two endpoints. Cloning an endpoint
+/// opens its side once more, and a side is closed once all are dropped.
+pub fn channel() -> (Endpoint, Endpoint) {
+    let channel = Arc::new(Channel { sides: [Side::new(), Side::new()] });
+    (Endpoint { channel: channel.clone(), side: 0 }, Endpoint { channel, side: 1 })
+}
+
+pub struct Endpoint {
+    channel: Arc<Channel>,
+    side: usize,
+}
+
+impl Endpoint {
+    fn this(&self) -> &Side {
+        &self.channel.sides[self.side]
+    }
+
+    fn peer(&self) -> &Side {
+        &self.channel.sides[1 - self.side]
+    }
+
+    /// Whether `other` is an end of the same channel.
+    pub fn same_channel(&self, other: &Endpoint) -> bool {
+        Arc::ptr_eq(&self.channel, &other.channel)
+    }
+
+    /// Checks that `msg` may be sent. An endpoint of this very channel in
+    /// it would keep the channel alive for as long as it is queued.
+    fn check(&self, msg: &Message) -> SysResult<()> {
+        if msg.data.len() > MAX_MESSAGE_SIZE || msg.handles.len() > MAX_MESSAGE_HANDLES {
+            return Err(Errno::EMSGSIZE);
+        }
+        let carries_self = msg.handles.iter().any(|c| matches!(&c.object, Object::Channel(e) if self.same_channel(e)));
+        if carries_self {
+            return Err(Errno::EINVAL);
+        }
+        Ok(())
+    }
+
+    fn send_with(&self, msg: Message, wait: bool) -> SysResult<()> {
+        self.check(&msg)?;
+        let peer = self.peer();
+        let mut msg = Some(msg);
+        peer.writable.wait_interruptible(|| {
+            if !peer.is_open() {
+                return Some(Err(Errno::EPIPE));
+            }
+            let mut queue = peer.queue.lock();
+            if queue.len() < CHANNEL_CAPACITY {
+                queue.push_back(msg.take().expect("Message sent twice"));
+                Some(Ok(()))
+            } else if !wait {
+                Some(Err(Errno::EAGAIN))
+            } else {
+                None
+            }
+        })??;
+        peer.readable.wake_all();
+        Ok(())
+    }
+
+    /// Sends `msg` to the other end, waiting for room in its queue.
+    /// Fails with `EPIPE` once the other end is closed.
+    pub fn send(&self, msg: Message) -> SysResult<()> {
+        self.send_with(msg, true)
+    }
+
+    /// Sends `msg` to the other end if there is room, or fails with `EAGAIN`.
+    pub fn try_send(&self, msg: Message) -> SysResult<()> {
+        self.send_with(msg, false)
+    }
+
+    /// Takes the oldest message sent to this end, if it has at most
+    /// `max_data` bytes and `max_handles` objects, or fails with
+    /// `EMSGSIZE` and leaves it queued. Waits for one unless told not to,
+    /// and fails with `EPIPE` once none are left and the other end is closed.
+    pub fn recv_max(&self, max_data: usize, max_handles: usize, wait: bool) -> SysResult<Message> {
+        let this = self.this();
+        let msg = this.readable.wait_interruptible(|| {
+            let mut queue = this.queue.lock();
+            match queue.front() {
+                Some(m) if m.data.len() > max_data || m.handles.len() > max_handles => Some(Err(Errno::EMSGSIZE)),
+                Some(_) => queue.pop_front().map(Ok),
+                None if !self.peer().is_open() => Some(Err(Errno::EPIPE)),
+                None if !wait => Some(Err(Errno::EAGAIN)),
+                None => None,
+            }
+        })??;
+        this.writable.wake_all();
+        Ok(msg)
+    }
+
+    /// Takes the oldest message sent to this end, waiting for one.
+    pub fn recv(&self) -> SysResult<Message> {
+        self.recv_max(usize::MAX, usize::MAX, true)
+    }
+
+    /// Takes the oldest message sent to this end, failing with `EAGAIN` if there is none.
+    pub fn try_recv(&self) -> SysResult<Message> {
+        self.recv_max(usize::MAX, usize::MAX, false)
+    }
+
+    /// The size in bytes and objects of the next message to receive, if any.
+    pub fn peek_size(&self) -> Option<(usize, usize)> {
+        self.this().queue.lock().front().map(|m| (m.data.len(), m.handles.len()))
+    }
+}
+
+impl Clone for Endpoint {
+    fn clone(&self) -> Self {
+        self.this().open.fetch_add(1, Ordering::AcqRel);
+        Self { channel: self.channel.clone(), side: self.side }
+    }
+}
+
+impl Drop for Endpoint {
+    fn drop(&mut self) {
+        let this = self.this();
+        if this.open.fetch_sub(1, Ordering::AcqRel) != 1 {
+            return;
+        }
+        // Nobody is going to receive what was sent here.
+        let unread = core::mem::take(&mut *this.queue.lock());
+        drop(unread);
+        this.writable.wake_all();
+        self.peer().readable.wake_all();
+    }
+}
@@ -0,0 +1,14 @@
+//! Inter-process communication.
+//!
+//! [`pipe`]s carry streams of bytes, [`channel`]s carry messages, which
+//! may also move kernel objects from one process to another. Both are
+//! plain kernel objects that kernel threads can use as they are, and that
+//! processes reach through their handle table (see
+//! [`crate::process::handle`]). Either side blocks while there is nothing
+//! to read or no room to write, and learns when the other side is gone.
+
+pub mod channel;
+pub mod pipe;
+
+pub use channel::{channel, Endpoint, Message};
+pub use pipe::{pipe, PipeReader, PipeWriter};
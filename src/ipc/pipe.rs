@@ -0,0 +1,133 @@
+//! Pipes: a byte stream from writers to readers, through a bounded buffer.
+
+use alloc::{collections::VecDeque, sync::Arc};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{
+    sync::{spinlock::SpinLock, WaitQueue},
+    syscall::{errno::Errno, SysResult},
+};
+
+/// How many bytes a pipe holds before writers have to wait.
+pub const PIPE_CAPACITY: usize = 16 * 1024;
+
+struct Pipe {
+    buf: SpinLock<VecDeque<u8>>,
+    /// How many read ends are open.
+    readers: AtomicUsize,
+    /// How many write ends are open.
+    writers: AtomicUsize,
+    /// Readers waiting for data.
+    readable: WaitQueue,
+    /// Writers waiting for room.
+    writable: WaitQueue,
+}
+
+/// Creates a pipe, returning its read and write ends. Cloning an end
+/// opens it once more, and the pipe is closed on one side once every
+/// end for that side is dropped.
+pub fn pipe() -> (PipeReader, PipeWriter) {
+    let pipe = Arc::new(Pipe {
+        buf: SpinLock::new(VecDeque::new()),
+        readers: AtomicUsize::new(1),
+        writers: AtomicUsize::new(1),
+        readable: WaitQueue::new(),
+        writable: WaitQueue::new(),
+    });
+    (PipeReader(pipe.clone()), PipeWriter(pipe))
+}
+
+pub struct PipeReader(Arc<Pipe>);
+
+pub struct PipeWriter(Arc<Pipe>);
+
+impl PipeReader {
+    /// Reads up to `buf.len()` bytes, waiting until there are some.
+    /// Returns 0 once the pipe is empty and every writer is gone, and
+    /// fails with `EINTR` if a signal comes first.
+    pub fn read(&self, buf: &mut [u8]) -> SysResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let pipe = &self.0;
+        let n = pipe.readable.wait_interruptible(|| {
+            let mut data = pipe.buf.lock();
+            if data.is_empty() {
+                return (pipe.writers.load(Ordering::Acquire) == 0).then_some(0);
+            }
+            let n = buf.len().min(data.len());
+            for (dst, src) in buf.iter_mut().zip(data.drain(..n)) {
+                *dst = src;
+            }
+            Some(n)
+        })?;
+        if n > 0 {
+            pipe.writable.wake_all();
+        }
+        Ok(n)
+    }
+}
+
+impl PipeWriter {
+    /// Writes all of `data`, waiting for room as needed. Fails with `EPIPE`
+    /// if every reader is gone, or `EINTR` if a signal comes, before
+    /// anything could be written, and otherwise returns how much was.
+    pub fn write(&self, data: &[u8]) -> SysResult<usize> {
+        let pipe = &self.0;
+        let mut done = 0;
+        while done < data.len() {
+            let written = pipe.writable.wait_interruptible(|| {
+                if pipe.readers.load(Ordering::Acquire) == 0 {
+                    return Some(Err(Errno::EPIPE));
+                }
+                let mut buf = pipe.buf.lock();
+                let n = (PIPE_CAPACITY - buf.len()).min(data.len() - done);
+                if n == 0 {
+                    return None;
+                }
+                buf.extend(&data[done..done + n]);
+                Some(Ok(n))
+            })
+            .and_then(|written| written);
+            match written {
+                Ok(n) => {
+                    done += n;
+                    pipe.readable.wake_all();
+                }
+                Err(_) if done > 0 => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(done)
+    }
+}
+
+impl Clone for PipeReader {
+    fn clone(&self) -> Self {
+        self.0.readers.fetch_add(1, Ordering::AcqRel);
+        Self(self.0.clone())
+    }
+}
+
+impl Clone for PipeWriter {
+    fn clone(&self) -> Self {
+        self.0.writers.fetch_add(1, Ordering::AcqRel);
+        Self(self.0.clone())
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        if self.0.readers.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.writable.wake_all();
+        }
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        if self.0.writers.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.readable.wake_all();
+        }
+    }
+}
@@ -11,11 +11,17 @@ pub use util::Result;
 pub mod asm;
 pub mod cpu;
 pub mod drivers;
+#[cfg(not(feature = "opensbi"))]
+pub mod firmware;
+pub mod fs;
 pub mod graphics;
 pub mod init;
+pub mod ipc;
 pub mod mem;
 pub mod process;
+pub mod sbi;
 pub mod sync;
+pub mod syscall;
 pub mod util;
 
 extern "C" {
@@ -24,45 +30,113 @@ extern "C" {
     static KERNEL_STACK_SIZE: usize;
     static KERNEL_STACK_END: usize;
     static KERNEL_STACK_START: usize;
+    static FIRMWARE_START: usize;
+    static FIRMWARE_END: usize;
     static TEXT_START: usize;
     static TEXT_END: usize;
+    static RODATA_START: usize;
+    static DATA_START: usize;
 fn kernelvec();
+    fn _supervisor_entry();
 }
 
 #[macro_use]
 extern crate alloc;
 
+/// Kernel entry point in S-mode, reached from `_supervisor_entry`
+/// with the hart id and, on the main hart, the device tree address.
 #[no_mangle]
-fn kmain() {
+extern "C" fn kmain(hartid: usize, dtb: usize) -> ! {
+    // `_supervisor_entry` turned away harts past `cpu::MAX_HARTS`, a
+    // boot hart among them handed the device tree over to this one.
+    cpu::claim_boot_hart(hartid);
+
     main_thread_only!({
         info!("Welcome to Walnut!");
+        info!("Booted on hart {} with device tree at {:#0x}", hartid, dtb);
         // TODO handle this instead of unwrap
-        if let Err(e) = main_hart_initialization() {
+        if let Err(e) = main_hart_initialization(dtb) {
             panic!("Error initializing OS components in main hart: {}", e);
         }
+        start_secondary_harts();
     });
 
 
 
-    hart_initialization();
+    let stack_top = hart_initialization();
 
+    // Leave the boot stack behind for one with a guard page.
+    unsafe { mem::stack::run_on_stack(stack_top, hart_main) }
+}
 
-    loop {
-        core::hint::spin_loop();
-    }
+/// Runs on every hart once it is set up, as its idle thread.
+extern "C" fn hart_main() -> ! {
+    process::sched::init_hart(cpu::hart::current().stack_bottom());
+    main_thread_only!({
+        if let Err(e) = process::sched::start_stats() {
+            warn!("Unable to start logging scheduler statistics: {}", e);
+        }
+        fs::initramfs::start();
+    });
+    process::sched::idle_loop()
 }
 
-fn main_hart_initialization() -> Result<()> {
+fn main_hart_initialization(dtb: usize) -> Result<()> {
     info!("We have a kernel heap size of {:#0x} ", HEAP_SIZE);
     unsafe {
         pages::PAGE_ALLOCATOR.init();
+        fs::initramfs::reserve(dtb);
         ALLOCATOR.init()?;
-        //mem::table::initialize();
     }
+    mem::table::initialize()?;
     Ok(())
 }
 
-fn hart_initialization() {
+/// Discovers the other harts through the SBI HSM extension,
+/// and asks the firmware to bring them up in the kernel.
+fn start_secondary_harts() {
+    if !sbi::probe_extension(sbi::EID_HSM) {
+        warn!("SBI implementation has no HSM extension, running on the boot hart only");
+        return;
+    }
 
+    for hart in 0..cpu::MAX_HARTS {
+        match sbi::hart_get_status(hart) {
+            Ok(sbi::HartState::Stopped) => {}
+            // Either us, or a hart that does not exist
+            _ => continue,
+        }
+        match unsafe { sbi::hart_start(hart, _supervisor_entry as *const () as usize, 0) } {
+            Ok(()) => debug!("Started hart {}", hart),
+            Err(e) => warn!("Unable to start hart {}: {}", hart, e),
+        }
+    }
+}
+
+/// Per-hart setup, returns the top of the stack the hart should move to.
+fn hart_initialization() -> usize {
+
+    let hartid = unsafe { cpu::util::my_hart() };
+    cpu::hart::init(hartid);
+    mem::table::init_hart();
     ControlStatusRegister::Stvec.write(kernelvec as *const u8 as usize);
+    ControlStatusRegister::Sie.set_bits(
+          1 << 9 // external interrupts
+        | 1 << 5 // timer    interrupts
+        | 1 << 1, // software interrupts
+    );
+
+    let (stack, emergency) = match (mem::stack::KernelStack::new(), mem::stack::KernelStack::new()) {
+        (Ok(stack), Ok(emergency)) => (stack, emergency),
+        _ => panic!("Unable to allocate kernel stacks for hart {}", hartid),
+    };
+    let hart = cpu::hart::current();
+    hart.set_stack_bottom(stack.bottom());
+    hart.set_emergency_sp(emergency.top());
+    let top = stack.top();
+
+    // These stacks are used for as long as the hart runs.
+    core::mem::forget(stack);
+    core::mem::forget(emergency);
+    top
 }
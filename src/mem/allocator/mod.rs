@@ -1,7 +1,7 @@
 use core::{alloc::GlobalAlloc, cell::UnsafeCell, ptr::{null, null_mut}};
 use block::{BlockPtr, Block};
 
-use crate::{debug, error, info, mem::allocator::block::{BlockPtrMut, BLOCK_SIZE}};
+use crate::{debug, error, mem::allocator::block::{BlockPtrMut, BLOCK_SIZE}, sync::spinlock::SpinLock};
 
 use super::pages::{PAGE_ALLOCATOR, PAGE_SIZE};
 use core::error::Error;
@@ -19,7 +19,8 @@ pub static mut ALLOCATOR: AllocGuard = AllocGuard {
     allocator: UnsafeCell::new(Allocator {
     block_cnt: 0,
     free_list_head: null()
-    })
+    }),
+    lock: SpinLock::new(()),
 };
 
 
@@ -32,32 +33,28 @@ pub struct Allocator {
 impl Allocator {
     /// Initialize Walnut's Allocator
     pub fn init(&mut self) -> AllocResult<()> {
+        self.free_list_head = null();
+        self.grow(INITIAL_KMEM_PAGE_COUNT)
+    }
 
-        // allocate pages
-        unsafe {
-            self.free_list_head = PAGE_ALLOCATOR.zalloc(INITIAL_KMEM_PAGE_COUNT)
-                                .ok_or(AllocationError::new ("Was not able to allocate 
-                                pages for the kernel memory"))? as *const Block;
-        }
-
-        // Create the linked list to track out-of-use blocks
-        self.block_cnt = (INITIAL_KMEM_PAGE_COUNT * PAGE_SIZE) / block::BLOCK_SIZE;
+    /// Hands `page_cnt` more pages to the allocator, as a single free block.
+    fn grow(&mut self, page_cnt: usize) -> AllocResult<()> {
+        let pages = unsafe {
+            PAGE_ALLOCATOR.zalloc(page_cnt)
+                .ok_or(AllocationError::new("Was not able to allocate pages for the kernel memory"))?
+        };
 
-        for i in 0..self.block_cnt {
-            unsafe {
-                let b: &mut Block = Block::at_offset(self.free_list_head, i * block::BLOCK_SIZE);
-                b.size = block::BLOCK_SIZE;
-                b.next = self.free_list_head.add((i+1) * block::BLOCK_SIZE);
-            }
-        }
+        let block_cnt = (page_cnt * PAGE_SIZE) / BLOCK_SIZE;
+        self.block_cnt += block_cnt;
 
+        let b = pages as BlockPtrMut;
         unsafe {
-            Block::at_offset(self.free_list_head,
-                    (self.block_cnt -1) * block::BLOCK_SIZE).next = null();
+            (*b).size = block_cnt;
+            // The free list is kept in address order, so that
+            // neighbouring blocks can be coalesced when freed.
+            self.insert_free(b);
         }
-        
         Ok(())
-
     }
 
     pub fn sub_block_alloc(&mut self, byte_cnt: usize) -> AllocResult<*const u8> {
@@ -68,141 +65,127 @@ impl Allocator {
         self.block_dealloc(p)
     }
 
+    /// Allocates `n` blocks, returning a pointer aligned to [`BLOCK_SIZE`].
+    ///
+    /// Every allocation is preceded by a header block
+    /// recording its size, which `block_dealloc` relies on.
     pub fn block_alloc(&mut self, n: usize) -> AllocResult<*const u8> {
-        if self.free_list_head.is_null() {
-            error!("No available blocks. Unable to allocate!");
-            return Err(AllocationError::new("No non-taken blocks found to allocate with"));
-        }
+        let n = n.max(1) + 1;
 
-
-        let mut current = self.free_list_head;
+        let mut current = self.free_list_head as BlockPtrMut;
         let mut prev = null_mut::<Block>();
 
         unsafe {
-
-        while !current.is_null() && (*current).size < n {
-            prev = current as BlockPtrMut;
-            current = (*current).next;
-        }
+            while !current.is_null() && (*current).size < n {
+                prev = current;
+                current = (*current).next as BlockPtrMut;
+            }
         }
 
-
         if current.is_null() {
-            
-            debug!("No blocks big enough to hold {:#0x} found. Allocating {} pages.", n, Self::pages_for_block_cnt(n));
-            unsafe {
-            return Ok(PAGE_ALLOCATOR.alloc(Self::pages_for_block_cnt(n)).expect("Unable to allocate pages for alloc!") as *const u8);
-            }
+            let pages = Self::pages_for_block_cnt(n);
+            debug!("No blocks big enough to hold {:#0x} found. Allocating {} pages.", n, pages);
+            self.grow(pages.max(INITIAL_KMEM_PAGE_COUNT / 4))?;
+            return self.block_alloc(n - 1);
         }
 
         unsafe {
-
-        if (*current).size > n {
-
-                info!("Block found was too large. Splitting into blocks of {} and {}", (*current).size - n, n);
-                let leftover_block = current.byte_add(n * BLOCK_SIZE) as BlockPtrMut;
+            let next = if (*current).size > n {
+                // Split the block, keeping the part we don't use in the free list.
+                let leftover_block = current.byte_add(n * BLOCK_SIZE);
                 (*leftover_block).size = (*current).size - n;
                 (*leftover_block).next = (*current).next;
+                (*current).size = n;
+                leftover_block as BlockPtr
+            } else {
+                (*current).next
+            };
 
-                (*(current as BlockPtrMut)).size = n;
-
-                // Make the new split block that we 
-                // wont use available to the list
-                // depending on how we found `current`
-                if !prev.is_null() {
-                    // this also removes `current`
-                    // from the free list
-                    (*prev).next = leftover_block as BlockPtr;
-                } else {
-                    self.free_list_head = leftover_block;
-                }
-
-        } else {
-              if !prev.is_null() {
-                    (*prev).next = (*current).next;
-                } else {
-                    self.free_list_head = (*current).next;
-
-                }
+            if !prev.is_null() {
+                (*prev).next = next;
+            } else {
+                self.free_list_head = next;
             }
 
-        }
-        unsafe {
-            assert!(current != (*current).next);
+            (*current).next = null();
             assert!((*current).size > 0);
+            Ok(current.byte_add(BLOCK_SIZE) as *const u8)
         }
-        Ok(current as *const u8)
     }
 
-
-
     pub fn block_dealloc<T>(&mut self, ptr: *const T ) {
+        unsafe {
+            let fb = (ptr as BlockPtrMut).byte_sub(BLOCK_SIZE);
+            assert!((*fb).size > 0, "Freeing {:#0p}, which was not allocated", ptr);
+            self.insert_free(fb);
+        }
+    }
 
-        let fb = ptr as BlockPtrMut;
-
+    /// Puts `fb` back in the free list, merging it with its neighbours.
+    unsafe fn insert_free(&mut self, fb: BlockPtrMut) {
         let mut current = self.free_list_head as BlockPtrMut;
         let mut prev = null_mut::<Block>();
 
-        unsafe {
-            while !current.is_null() && current < fb {
-                prev = current;
-                current = (*current).next as BlockPtrMut;
-            }
-
-            if !prev.is_null() && (prev.byte_add((*prev).size * BLOCK_SIZE)) as BlockPtr == fb {
-
-                info!("Coalescing backwards from {:#0p} -> {:#0p}", prev, (*prev).next);
-            } else {
-                (*fb).next = self.free_list_head;
-                self.free_list_head = fb as BlockPtr;
-            }
+        while !current.is_null() && current < fb {
+            prev = current;
+            current = (*current).next as BlockPtrMut;
+        }
+        assert!(current != fb, "Double free of block at {:#0p}", fb);
 
-            current = fb;
+        (*fb).next = current;
+        if prev.is_null() {
+            self.free_list_head = fb;
+        } else {
+            (*prev).next = fb;
+        }
 
-            while !current.is_null() && current != (*current).next as BlockPtrMut && (current.byte_add((*current).size * BLOCK_SIZE)) == (*current).next as BlockPtrMut {
-                assert!((*current).size != 0);
-                (*current).size += (*(*current).next).size;
-                (*current).next = (*(*current).next).next;
-                info!("Coalescing forwards from {:#0p} -> {:#0p}", current, (*current).next);
-            }
+        Self::coalesce(fb);
+        if !prev.is_null() {
+            Self::coalesce(prev);
         }
-        info!("Freed block(s) at {:0p}", ptr);
+    }
 
+    /// Merges `b` with the block after it in the free list, if they touch.
+    unsafe fn coalesce(b: BlockPtrMut) {
+        let next = (*b).next as BlockPtrMut;
+        if !next.is_null() && b.byte_add((*b).size * BLOCK_SIZE) == next {
+            (*b).size += (*next).size;
+            (*b).next = (*next).next;
+        }
     }
 
     pub fn print_blocklist(&self) {
-        let mut b = unsafe {Block::at_offset(self.free_list_head, 0) as BlockPtr };
-            while unsafe { !(*b).next.is_null()} {
-                crate::println!("{:#x?}", (*b));
-                unsafe { 
-                b = (*b).next;
-                }
+        let mut b = self.free_list_head;
+        while !b.is_null() {
+            let block = unsafe { *b };
+            crate::println!("{:#x?}", block);
+            b = block.next;
         }
     }
 
     fn pages_for_block_cnt(n: usize) -> usize {
-        // Calculate the number of blocks required
-        let blocks = (n + BLOCK_SIZE - 1) / BLOCK_SIZE;
-
-        // Calculate the number of pages required, rounding up
-        (blocks + PAGE_SIZE - 1) / PAGE_SIZE
+        (n * BLOCK_SIZE).div_ceil(PAGE_SIZE)
     }
 
     fn blocks_for_byte_sz(n: usize) -> usize {
-
-        (n + (BLOCK_SIZE - 1)) / BLOCK_SIZE
+        n.div_ceil(BLOCK_SIZE)
     }
+
     fn align(n: usize) -> usize {
-    (n + core::mem::size_of::<usize>() - 1) & !(core::mem::size_of::<usize>() - 1)
-}
+        (n + core::mem::size_of::<usize>() - 1) & !(core::mem::size_of::<usize>() - 1)
+    }
 }
 
 pub struct AllocGuard {
-    allocator: UnsafeCell<Allocator>
+    allocator: UnsafeCell<Allocator>,
+    lock: SpinLock<()>,
 }
 
+unsafe impl Sync for AllocGuard {}
+
 impl AllocGuard {
     pub fn init(&self) -> AllocResult<()> {
+        let _guard = self.lock.lock();
         unsafe {
             (&mut *self.allocator.get()).init()
         }
@@ -216,9 +199,21 @@ impl AllocGuard {
 
 unsafe impl GlobalAlloc for AllocGuard {
     unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
-        (&mut *self.allocator.get()).sub_block_alloc(layout.size()).unwrap() as *mut u8
+        // Blocks are only ever aligned to their own size
+        if layout.align() > BLOCK_SIZE {
+            return null_mut();
+        }
+        let _guard = self.lock.lock();
+        match (&mut *self.allocator.get()).sub_block_alloc(layout.size()) {
+            Ok(p) => p as *mut u8,
+            Err(e) => {
+                error!("Kernel allocation of {} bytes failed: {}", layout.size(), e);
+                null_mut()
+            }
+        }
     }
     unsafe fn dealloc(&self, ptr: *mut u8, _layout: core::alloc::Layout) {
+        let _guard = self.lock.lock();
         (&mut *self.allocator.get()).sub_block_dealloc(ptr);
     }
 }
@@ -233,6 +228,10 @@ impl AllocationError {
     pub fn new(msg: &'static str) -> AllocationError {
         AllocationError { details: msg }
     }
+
+    pub fn details(&self) -> &'static str {
+        self.details
+    }
 }
 
 impl core::fmt::Display for AllocationError {
@@ -2,3 +2,7 @@ pub mod pages;
 pub mod addr;
 pub mod table;
 pub mod allocator;
+pub mod shm;
+pub mod space;
+pub mod stack;
+pub mod vma;
@@ -1,6 +1,8 @@
+use core::sync::atomic::{AtomicU16, Ordering};
+
 use mycelium_bitfield::bitfield;
 
-use crate::{info, println, HEAP_START};
+use crate::{info, println, sync::spinlock::SpinLock, HEAP_START, HEAP_SIZE};
 
 pub const PAGE_SIZE: usize = 4096;
 
@@ -16,10 +18,19 @@ bitfield! {
     }
 }
 
-pub static mut PAGE_ALLOCATOR: PageAllocator = PageAllocator { alloc_start: 0 };
+pub static mut PAGE_ALLOCATOR: PageAllocator =
+    PageAllocator { alloc_start: 0, lock: SpinLock::new(()), refs: core::ptr::null_mut() };
 
 pub struct PageAllocator {
     pub alloc_start: usize,
+
+    /// Serializes access to the page descriptors,
+    /// as every hart allocates pages.
+    lock: SpinLock<()>,
+
+    /// How many more owners than one every single page has, so pages
+    /// shared copy-on-write are only freed once nobody uses them anymore.
+    refs: *mut AtomicU16,
 }
 
 impl PageAllocator {
@@ -39,6 +50,9 @@ impl PageAllocator {
 
         self.alloc_start = align(HEAP_START + page_count(), PAGE_ORDER);
         crate::info!("Allocation start set to {:#0x}", self.alloc_start);
+
+        let ref_pages = (self.usable_pages() * size_of::<AtomicU16>()).div_ceil(PAGE_SIZE);
+        self.refs = self.zalloc(ref_pages).expect("No memory for page reference counts") as *mut AtomicU16;
     }
 
     pub fn zalloc(&self, n: usize) -> Option<*const Page> {
@@ -63,8 +77,9 @@ impl PageAllocator {
     pub fn alloc(&self, n: usize) -> Option<*const Page> {
         assert!(self.alloc_start != 0);
 
+        let _guard = self.lock.lock();
         let node = unsafe { HEAP_START } as *mut PageListNode;
-        for i in 0..page_count() - n {
+        for i in 0..(self.usable_pages() + 1).saturating_sub(n) {
             if unsafe { !node_is_taken(node.add(i)) } && self.has_contig_space(node, n, i) {
                 for pg_idx in i..i+n {
                     unsafe {
@@ -79,7 +94,8 @@ impl PageAllocator {
         None
     }
 
-    pub fn dealloc<T>(&mut self, p: *const T) {
+    pub fn dealloc<T>(&self, p: *const T) {
+        let _guard = self.lock.lock();
 
         // we assume that the given pointer is at the base
         // of the page allocation. if it is not, then there 
@@ -95,6 +111,73 @@ impl PageAllocator {
         unsafe { (*node).set(PageListNode::TAKEN, false).set(PageListNode::LAST, false); }
     }
 
+    fn refs_for<T>(&self, p: *const T) -> &AtomicU16 {
+        unsafe { &*self.refs.add((p as usize - self.alloc_start) / PAGE_SIZE) }
+    }
+
+    /// How many owners the single page at `p` has.
+    pub fn owners<T>(&self, p: *const T) -> usize {
+        self.refs_for(p).load(Ordering::Acquire) as usize + 1
+    }
+
+    /// Adds an owner to the single page at `p`, which
+    /// [`release`](Self::release) has to be called for.
+    pub fn share<T>(&self, p: *const T) {
+        let refs = self.refs_for(p);
+        assert!(refs.fetch_add(1, Ordering::AcqRel) != u16::MAX, "Too many owners for page {:?}", p);
+    }
+
+    /// Drops an owner of the single page at `p`, freeing it if that was
+    /// the last one. Returns whether the page was freed.
+    pub fn release<T>(&self, p: *const T) -> bool {
+        let refs = self.refs_for(p);
+        let mut current = refs.load(Ordering::Acquire);
+        loop {
+            if current == 0 {
+                self.dealloc(p);
+                return true;
+            }
+            match refs.compare_exchange(current, current - 1, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return false,
+                Err(now) => current = now,
+            }
+        }
+    }
+
+    /// The number of pages that fit between the start of
+    /// allocatable memory and the end of the heap.
+    ///
+    /// This is a little less than `page_count()`, as the page descriptors
+    /// themselves live at the start of the heap.
+    pub fn usable_pages(&self) -> usize {
+        (unsafe { HEAP_START + HEAP_SIZE } - self.alloc_start) / PAGE_SIZE
+    }
+
+    /// Takes the pages covering `[start, end)` out of the allocator, for
+    /// something put in memory before it was set up. Each page is then a
+    /// single page allocation, freed on its own with [`dealloc`](Self::dealloc).
+    /// Returns whether they are all in allocatable memory and were free.
+    pub fn reserve(&self, start: usize, end: usize) -> bool {
+        let start = start & !(PAGE_SIZE - 1);
+        let end = align(end, 12);
+        if start < self.alloc_start || end > unsafe { HEAP_START + HEAP_SIZE } || start >= end {
+            return false;
+        }
+
+        let _guard = self.lock.lock();
+        let node = unsafe { HEAP_START } as *mut PageListNode;
+        let (first, n) = ((start - self.alloc_start) / PAGE_SIZE, (end - start) / PAGE_SIZE);
+        if !self.has_contig_space(node, n, first) {
+            return false;
+        }
+        for pg_idx in first..first + n {
+            unsafe {
+                (*node.add(pg_idx)).set(PageListNode::TAKEN, true).set(PageListNode::LAST, true);
+            }
+        }
+        true
+    }
+
     fn has_contig_space(&self, start_node: *mut PageListNode, n: usize, i: usize) -> bool {
         // Now we look to see if we have
         // contiguous non-taken pages from this page
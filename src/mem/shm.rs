@@ -0,0 +1,73 @@
+//! Shared memory objects.
+//!
+//! A [`SharedMemory`] is a set of pages that can be mapped into any
+//! number of address spaces at once, each with its own permissions (see
+//! [`super::vma::Backing::Shared`]). The object and every page table
+//! entry mapping one of its pages each own that page, so the pages are
+//! freed once the object and the last mapping are gone, in whatever order.
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+
+use crate::util::error::WalnutError;
+
+use super::{
+    pages::{PAGE_ALLOCATOR, PAGE_SIZE},
+    vma::Mappable,
+};
+
+pub struct SharedMemory {
+    /// Physical addresses of the pages, in order.
+    frames: Vec<usize>,
+}
+
+impl SharedMemory {
+    /// Creates an object of `size` bytes, rounded up to pages, of zeroes.
+    pub fn new(size: usize) -> crate::Result<Arc<Self>> {
+        if size == 0 {
+            return Err(WalnutError::new("Shared memory can not be empty"));
+        }
+        let mut shm = Self { frames: Vec::with_capacity(size.div_ceil(PAGE_SIZE)) };
+        for _ in 0..size.div_ceil(PAGE_SIZE) {
+            // Pages taken so far are freed when `shm` is dropped.
+            let frame = unsafe { PAGE_ALLOCATOR.zalloc(1) }.ok_or(WalnutError::new("Out of memory for shared memory"))?;
+            shm.frames.push(frame as usize);
+        }
+        Ok(Arc::new(shm))
+    }
+
+    /// Size in bytes, a whole number of pages.
+    pub fn size(&self) -> usize {
+        self.frames.len() * PAGE_SIZE
+    }
+
+    /// The physical address of the page at `offset`, if within the object.
+    pub fn frame(&self, offset: usize) -> Option<usize> {
+        self.frames.get(offset / PAGE_SIZE).copied()
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        for &frame in &self.frames {
+            unsafe { PAGE_ALLOCATOR.release(frame as *const u8) };
+        }
+    }
+}
+
+/// Private mappings of the object get a copy of its contents.
+impl Mappable for SharedMemory {
+    fn read_page(&self, offset: usize, buf: &mut [u8]) -> crate::Result<()> {
+        match self.frame(offset) {
+            Some(frame) => {
+                let page = unsafe { core::slice::from_raw_parts(frame as *const u8, PAGE_SIZE) };
+                buf.copy_from_slice(&page[..buf.len()]);
+            }
+            None => buf.fill(0),
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> String {
+        String::from("[shm]")
+    }
+}
@@ -0,0 +1,476 @@
+//! User address spaces.
+//!
+//! Every process gets its own Sv39 page table. The kernel's root entries
+//! are copied into it (see [`table::share_kernel_mappings`]), so traps
+//! can be handled without switching tables, and user memory lives in
+//! between them, from [`USER_START`] to [`USER_END`].
+//!
+//! What user memory holds is described by the regions in a [`VmaMap`],
+//! and the page table only catches up as pages are used. The pages in
+//! place are counted, and can be limited (see [`crate::process::limits`]).
+
+use alloc::string::String;
+use core::{fmt::Write, ops::Range};
+
+use crate::util::error::WalnutError;
+
+use super::{
+    addr::VirtAddr,
+    pages::{PAGE_ALLOCATOR, PAGE_SIZE},
+    table::{self, PageTable, PTE_COW, PTE_EXEC, PTE_READ, PTE_USER, PTE_WRITE},
+    vma::{Backing, Vma, VmaMap},
+};
+
+/// Lowest user address. The root entries below it
+/// cover the kernel image and MMIO.
+pub const USER_START: usize = 0x1_0000_0000;
+
+/// End of user memory, the root entries above it belong to the kernel.
+pub const USER_END: usize = 0x40_0000_0000;
+
+/// Where the stack of the first thread of a process ends.
+pub const USER_STACK_TOP: usize = USER_END - PAGE_SIZE;
+
+/// The page above the stack, holding the code signal handlers return through.
+pub const USER_SIGPAGE: usize = USER_STACK_TOP;
+
+/// Initial size of the user stack.
+pub const USER_STACK_SIZE: usize = 16 * PAGE_SIZE;
+
+/// How far the user stack may grow.
+pub const USER_STACK_MAX: usize = 8 * 1024 * 1024;
+
+/// Anonymous mappings are placed downwards from here, below the stack.
+pub const USER_MMAP_TOP: usize = USER_STACK_TOP - USER_STACK_MAX - PAGE_SIZE;
+
+/// Root entries user memory is mapped through.
+const USER_ROOT_ENTRIES: Range<usize> = (USER_START >> 30)..(USER_END >> 30);
+
+/// The kind of access that caused a page fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+impl Access {
+    /// The permission the access needs.
+    fn flag(self) -> usize {
+        match self {
+            Self::Read => PTE_READ,
+            Self::Write => PTE_WRITE,
+            Self::Execute => PTE_EXEC,
+        }
+    }
+}
+
+pub struct AddressSpace {
+    root: *mut PageTable,
+    areas: VmaMap,
+    /// Pages of user memory mapped.
+    resident: usize,
+    /// Most pages there ever were mapped at once.
+    peak: usize,
+    /// How many pages may be mapped at once.
+    page_limit: usize,
+}
+
+unsafe impl Send for AddressSpace {}
+
+impl AddressSpace {
+    /// Creates an address space with nothing but the kernel mapped.
+    pub fn new() -> crate::Result<Self> {
+        let root = PageTable::new()?;
+        table::share_kernel_mappings(root);
+        Ok(Self { root, areas: VmaMap::new(), resident: 0, peak: 0, page_limit: usize::MAX })
+    }
+
+    /// Pages of user memory in place.
+    pub fn resident_pages(&self) -> usize {
+        self.resident
+    }
+
+    /// Most pages of user memory there ever were in place at once.
+    pub fn peak_pages(&self) -> usize {
+        self.peak
+    }
+
+    /// Limits user memory to `pages` pages in place. Pages already in
+    /// place stay, but no more are put in place past the limit.
+    pub fn set_page_limit(&mut self, pages: usize) {
+        self.page_limit = pages;
+    }
+
+    fn charge(&mut self) {
+        self.resident += 1;
+        self.peak = self.peak.max(self.resident);
+    }
+
+    fn table(&mut self) -> &mut PageTable {
+        unsafe { &mut *self.root }
+    }
+
+    /// The value to write to `satp` to switch to this address space.
+    pub fn satp(&self) -> usize {
+        unsafe { (*self.root).satp() }
+    }
+
+    /// The regions of user memory.
+    pub fn areas(&self) -> &VmaMap {
+        &self.areas
+    }
+
+    /// The region `va` lies in.
+    pub fn area(&self, va: usize) -> Option<&Vma> {
+        self.areas.find(va)
+    }
+
+    /// Maps the page at `va` to the frame at `pa`, accessible from U-mode.
+    fn map(&mut self, va: usize, pa: usize, flags: usize) -> crate::Result<()> {
+        if !is_user_range(va, PAGE_SIZE) {
+            return Err(WalnutError::new("Mapping outside of user memory"));
+        }
+        self.table().map(VirtAddr::from_bits(va), pa, flags | PTE_USER, 0)
+    }
+
+    /// Adds a region of user memory, which gets pages as they are used.
+    pub fn map_area(&mut self, vma: Vma) -> crate::Result<()> {
+        if !is_user_range(vma.start, vma.len()) {
+            return Err(WalnutError::new("Mapping outside of user memory"));
+        }
+        self.areas.insert(vma)
+    }
+
+    /// Adds a region of user memory, with all its pages in place right away.
+    pub fn alloc_area(&mut self, vma: Vma) -> crate::Result<()> {
+        let range = vma.range();
+        self.map_area(vma)?;
+        self.populate(range.start, range.len())
+    }
+
+    /// Maps zeroed memory over `[start, start + len)`, rounded out to pages.
+    pub fn alloc(&mut self, start: usize, len: usize, flags: usize) -> crate::Result<()> {
+        let first = start & !(PAGE_SIZE - 1);
+        let end = (start + len).next_multiple_of(PAGE_SIZE);
+        self.alloc_area(Vma::anonymous(first, end, flags))
+    }
+
+    /// Puts in place the pages of `[start, start + len)` that are not yet.
+    pub fn populate(&mut self, start: usize, len: usize) -> crate::Result<()> {
+        for page in (start & !(PAGE_SIZE - 1)..start + len).step_by(PAGE_SIZE) {
+            if self.table().walk(VirtAddr::from_bits(page)).is_none() {
+                self.fault_in(page)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Gives the page at `page` the memory its region says it starts with.
+    fn fault_in(&mut self, page: usize) -> crate::Result<()> {
+        let vma = self.areas.find(page).ok_or(WalnutError::new("No memory area for the page"))?;
+        if vma.perms == 0 {
+            return Err(WalnutError::new("Memory area is not accessible"));
+        }
+        let perms = vma.perms;
+        if self.resident >= self.page_limit {
+            return Err(WalnutError::new("Over the limit of user pages"));
+        }
+
+        if let Backing::Shared { .. } = vma.backing {
+            let frame = vma.shared_frame(page).ok_or(WalnutError::new("Page is past the end of shared memory"))?;
+            unsafe { PAGE_ALLOCATOR.share(frame as *const u8) };
+            if let Err(e) = self.map(page, frame, perms) {
+                unsafe { PAGE_ALLOCATOR.release(frame as *const u8) };
+                return Err(e);
+            }
+            self.charge();
+            return Ok(());
+        }
+
+        let frame = unsafe { PAGE_ALLOCATOR.alloc(1) }.ok_or(WalnutError::new("Out of memory for user pages"))?;
+        let buf = unsafe { core::slice::from_raw_parts_mut(frame as *mut u8, PAGE_SIZE) };
+        let filled = vma.fill(page, buf);
+
+        if let Err(e) = filled.and_then(|_| self.map(page, frame as usize, perms)) {
+            unsafe { PAGE_ALLOCATOR.dealloc(frame) };
+            return Err(e);
+        }
+        self.charge();
+        Ok(())
+    }
+
+    /// Unmaps `[start, start + len)`, splitting regions that are only partly
+    /// in it and freeing the pages no other address space shares.
+    /// The caller has to flush the TLB.
+    pub fn free(&mut self, start: usize, len: usize) {
+        let first = start & !(PAGE_SIZE - 1);
+        let end = (start + len).next_multiple_of(PAGE_SIZE);
+        self.areas.remove(first, end);
+
+        let mut freed = 0;
+        for va in (first..end).step_by(PAGE_SIZE) {
+            if let Some(entry) = self.table().page_entry(VirtAddr::from_bits(va)) {
+                if entry.is_valid() {
+                    unsafe { PAGE_ALLOCATOR.release(entry.addr() as *const u8) };
+                    freed += 1;
+                }
+                entry.set_bits(0);
+            }
+        }
+        self.resident -= freed;
+    }
+
+    /// Changes the permissions of `[start, start + len)`, which has to be
+    /// mapped throughout. The caller has to flush the TLB.
+    pub fn protect(&mut self, start: usize, len: usize, flags: usize) -> crate::Result<()> {
+        let first = start & !(PAGE_SIZE - 1);
+        let end = (start + len).next_multiple_of(PAGE_SIZE);
+        self.areas.protect(first, end, flags)?;
+        for va in (first..end).step_by(PAGE_SIZE) {
+            self.set_flags(va, flags);
+        }
+        Ok(())
+    }
+
+    /// Creates a copy of this address space, with the same page limit.
+    /// Pages are shared rather than copied, and the writable ones of
+    /// private regions are copied by whichever side writes to them first.
+    /// The caller has to flush the TLB, as this address space loses write
+    /// access to its pages.
+    pub fn fork(&mut self) -> crate::Result<AddressSpace> {
+        let mut child = AddressSpace::new()?;
+        child.areas = self.areas.clone();
+        child.page_limit = self.page_limit;
+
+        let areas = &self.areas;
+        let table = unsafe { &mut *self.root };
+        let mut result = Ok(());
+        table.for_each_page(USER_ROOT_ENTRIES, &mut |va, entry| {
+            if result.is_err() {
+                return;
+            }
+            let child_entry = match child.table().page_entry_or_create(VirtAddr::from_bits(va)) {
+                Ok(e) => e,
+                Err(e) => {
+                    result = Err(e);
+                    return;
+                }
+            };
+
+            let pa = entry.addr();
+            let shared = areas.find(va).is_some_and(|v| v.is_shared());
+            if !shared && entry.flags() & PTE_WRITE != 0 {
+                entry.set_bits((pa >> 2) | (entry.flags() & !PTE_WRITE) | PTE_COW);
+            }
+            unsafe { PAGE_ALLOCATOR.share(pa as *const u8) };
+            child_entry.set_bits((pa >> 2) | entry.flags());
+            child.charge();
+        });
+        result.map(|_| child)
+    }
+
+    /// Unmaps and frees all of user memory, keeping the kernel mapped.
+    /// The caller has to flush the TLB.
+    pub fn clear(&mut self) {
+        self.areas.clear();
+        self.resident = 0;
+        self.table().free_subtables(USER_ROOT_ENTRIES, &mut |pa| unsafe {
+            PAGE_ALLOCATOR.release(pa as *const u8);
+        });
+    }
+
+    /// Changes the permissions of the page at `va`, if it is mapped.
+    /// Pages of private regions shared with other address spaces stay
+    /// read-only, and are copied on the first write if `flags` allows it.
+    /// Pages without any permissions are kept mapped
+    /// for the kernel only, so they keep their contents.
+    fn set_flags(&mut self, va: usize, flags: usize) -> bool {
+        let shared_area = self.areas.find(va).is_some_and(|v| v.is_shared());
+        let Some(entry) = self.table().page_entry(VirtAddr::from_bits(va)) else {
+            return false;
+        };
+        if !entry.is_valid() {
+            return false;
+        }
+
+        let pa = entry.addr();
+        let mut flags = flags;
+        // Pages of shared regions are meant to be written by all their owners.
+        let cow = entry.flags() & PTE_COW != 0 || unsafe { PAGE_ALLOCATOR.owners(pa as *const u8) } > 1;
+        if cow && !shared_area && flags & PTE_WRITE != 0 {
+            flags = (flags & !PTE_WRITE) | PTE_COW;
+        }
+        flags = match flags {
+            0 => PTE_READ,
+            _ => flags | PTE_USER,
+        };
+        let kept = entry.flags() & !(PTE_READ | PTE_WRITE | PTE_EXEC | PTE_COW | PTE_USER);
+        entry.set_bits((pa >> 2) | kept | flags);
+        true
+    }
+
+    /// Translates a user address, if it is mapped with all of `flags`.
+    pub fn translate(&mut self, va: usize, flags: usize) -> Option<usize> {
+        if !is_user_range(va, 1) {
+            return None;
+        }
+        let (entry, _) = self.table().walk(VirtAddr::from_bits(va))?;
+        if entry.flags() & (flags | PTE_USER) != flags | PTE_USER {
+            return None;
+        }
+        self.table().translate(va)
+    }
+
+    /// Resolves a page fault on `va` from an `access` the hardware refused,
+    /// by putting in place a page of a region that was not used yet, copying
+    /// a page shared copy-on-write, or growing the stack. Returns whether
+    /// the access can be retried, and `false` if it is not allowed.
+    pub fn handle_fault(&mut self, va: usize, access: Access) -> bool {
+        if !is_user_range(va, 1) {
+            return false;
+        }
+        let page = va & !(PAGE_SIZE - 1);
+
+        let perms = match self.areas.find(page).map(|v| v.perms) {
+            Some(perms) => perms,
+            None if self.grow_stack(page) => self.areas.find(page).map_or(0, |v| v.perms),
+            None => return false,
+        };
+        if perms & access.flag() == 0 {
+            return false;
+        }
+
+        let Some((entry, _)) = self.table().walk(VirtAddr::from_bits(page)) else {
+            return self.fault_in(page).is_ok();
+        };
+        if access == Access::Write && entry.flags() & PTE_COW != 0 {
+            return self.break_cow(page).is_ok();
+        }
+
+        // Someone else fixed this up already, but our TLB did not notice.
+        if entry.flags() & access.flag() != 0 {
+            table::flush_tlb_page(page);
+            return true;
+        }
+        false
+    }
+
+    /// Gives the address space its own copy of the copy-on-write page at
+    /// `page`, or takes over the page if nobody else shares it anymore.
+    fn break_cow(&mut self, page: usize) -> crate::Result<()> {
+        let entry = self
+            .table()
+            .page_entry(VirtAddr::from_bits(page))
+            .ok_or(WalnutError::new("Copy-on-write page is not mapped"))?;
+        let old = entry.addr();
+        let flags = (entry.flags() & !PTE_COW) | PTE_WRITE;
+
+        if unsafe { PAGE_ALLOCATOR.owners(old as *const u8) } > 1 {
+            let new = unsafe { PAGE_ALLOCATOR.alloc(1) }.ok_or(WalnutError::new("Out of memory for user pages"))?;
+            unsafe {
+                core::ptr::copy_nonoverlapping(old as *const u8, new as *mut u8, PAGE_SIZE);
+                PAGE_ALLOCATOR.release(old as *const u8);
+            }
+            entry.set_bits((new as usize >> 2) | flags);
+        } else {
+            entry.set_bits((old >> 2) | flags);
+        }
+        table::flush_tlb_page(page);
+        Ok(())
+    }
+
+    /// Extends a stack region down to `page`, if there is one right above
+    /// it with room to grow that far.
+    fn grow_stack(&mut self, page: usize) -> bool {
+        let Some(stack) = self.areas.next_after(page) else {
+            return false;
+        };
+        if !stack.grows_down() || stack.end - page > USER_STACK_MAX {
+            return false;
+        }
+        let start = stack.start;
+        self.areas.extend_down(start, page);
+        true
+    }
+
+    /// Faults in the page at `va` unless it is mapped already.
+    fn make_present(&mut self, va: usize) -> crate::Result<()> {
+        match self.translate(va, 0) {
+            Some(_) => Ok(()),
+            None => self.fault_in(va & !(PAGE_SIZE - 1)),
+        }
+    }
+
+    /// Copies `data` to user memory at `va`, even where the process can
+    /// only read or execute, faulting in pages that are not there yet.
+    /// Memory the process has no access to at all, `PROT_NONE` areas and
+    /// addresses outside any area, can not be written.
+    pub fn write(&mut self, va: usize, data: &[u8]) -> crate::Result<()> {
+        let mut done = 0;
+        while done < data.len() {
+            let addr = va + done;
+            self.make_present(addr)?;
+            // The kernel writes where the process could not, but never to
+            // pages it shares with other processes.
+            if self.translate(addr, PTE_COW).is_some() {
+                self.break_cow(addr & !(PAGE_SIZE - 1))?;
+            }
+            let pa = self
+                .translate(addr, 0)
+                .ok_or(WalnutError::new("Writing to unmapped user memory"))?;
+            let n = (PAGE_SIZE - addr % PAGE_SIZE).min(data.len() - done);
+            unsafe { core::ptr::copy_nonoverlapping(data[done..].as_ptr(), pa as *mut u8, n) };
+            done += n;
+        }
+        Ok(())
+    }
+
+    /// Copies user memory at `va` to `buf`, like [`write`](Self::write)
+    /// whatever access short of none the process has.
+    pub fn read(&mut self, va: usize, buf: &mut [u8]) -> crate::Result<()> {
+        let mut done = 0;
+        while done < buf.len() {
+            let addr = va + done;
+            self.make_present(addr)?;
+            let pa = self
+                .translate(addr, 0)
+                .ok_or(WalnutError::new("Reading from unmapped user memory"))?;
+            let n = (PAGE_SIZE - addr % PAGE_SIZE).min(buf.len() - done);
+            unsafe { core::ptr::copy_nonoverlapping(pa as *const u8, buf[done..].as_mut_ptr(), n) };
+            done += n;
+        }
+        Ok(())
+    }
+
+    /// Finds room for `len` bytes of new mappings, below [`USER_MMAP_TOP`].
+    pub fn find_free(&self, len: usize) -> Option<usize> {
+        self.areas.find_free(len, USER_START, USER_MMAP_TOP)
+    }
+
+    /// The memory map, one region per line the way `/proc/<pid>/maps` has it.
+    pub fn memory_map(&self) -> String {
+        let mut map = String::new();
+        for vma in self.areas.iter() {
+            let _ = writeln!(map, "{}", vma);
+        }
+        map
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        self.clear();
+        unsafe { PAGE_ALLOCATOR.dealloc(self.root) };
+    }
+}
+
+/// Whether `[va, va + len)` is within user memory.
+pub fn is_user_range(va: usize, len: usize) -> bool {
+    va >= USER_START && va.checked_add(len).is_some_and(|end| end <= USER_END)
+}
+
+/// Page table flags for user memory with the given permissions.
+pub fn user_flags(read: bool, write: bool, exec: bool) -> usize {
+    (if read { PTE_READ } else { 0 }) | (if write { PTE_WRITE } else { 0 }) | (if exec { PTE_EXEC } else { 0 })
+}
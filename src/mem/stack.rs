@@ -0,0 +1,122 @@
+//! Kernel stacks.
+//!
+//! Unlike the rest of the kernel, stacks are not identity mapped. Each one
+//! gets a slot in the upper half of the address space, starting at
+//! [`KERNEL_STACKS_BASE`], and the lowest page of every slot is left
+//! unmapped. Running off the bottom of a stack then faults on that guard
+//! page, instead of silently corrupting whatever lies below it.
+
+use core::arch::asm;
+
+use crate::{mem::allocator::AllocationError, sync::spinlock::SpinLock};
+
+use super::{
+    addr::VirtAddr,
+    pages::{PAGE_ALLOCATOR, PAGE_SIZE},
+    table::{self, PTE_READ, PTE_WRITE},
+};
+
+/// Start of the region kernel stacks are mapped in.
+pub const KERNEL_STACKS_BASE: usize = 0xffff_ffc0_0000_0000;
+
+/// Usable pages in every kernel stack, on top of the guard page.
+pub const KERNEL_STACK_PAGES: usize = 4;
+
+const SLOT_SIZE: usize = (KERNEL_STACK_PAGES + 1) * PAGE_SIZE;
+const MAX_KERNEL_STACKS: usize = 1024;
+
+/// Which slots are in use, one bit per slot.
+static SLOTS: SpinLock<[u64; MAX_KERNEL_STACKS / 64]> = SpinLock::new([0; MAX_KERNEL_STACKS / 64]);
+
+pub struct KernelStack {
+    slot: usize,
+    phys: usize,
+}
+
+impl KernelStack {
+    /// Allocates and maps a new kernel stack.
+    pub fn new() -> crate::Result<Self> {
+        let slot = alloc_slot().ok_or(AllocationError::new("Out of kernel stack slots"))?;
+
+        let Some(phys) = (unsafe { PAGE_ALLOCATOR.zalloc(KERNEL_STACK_PAGES) }) else {
+            free_slot(slot);
+            return Err(AllocationError::new("Unable to allocate pages for a kernel stack").into());
+        };
+
+        let stack = Self { slot, phys: phys as usize };
+        table::with_kernel_table(|tbl| {
+            tbl.map_range(stack.bottom(), stack.phys, KERNEL_STACK_PAGES * PAGE_SIZE, PTE_READ | PTE_WRITE)
+        })?;
+
+        Ok(stack)
+    }
+
+    /// Lowest usable address of the stack.
+    pub fn bottom(&self) -> usize {
+        self.guard_page() + PAGE_SIZE
+    }
+
+    /// Initial stack pointer, the stack grows down from here.
+    pub fn top(&self) -> usize {
+        self.bottom() + KERNEL_STACK_PAGES * PAGE_SIZE
+    }
+
+    /// The unmapped page right below the stack.
+    pub fn guard_page(&self) -> usize {
+        KERNEL_STACKS_BASE + self.slot * SLOT_SIZE
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        table::with_kernel_table(|tbl| {
+            for page in (self.bottom()..self.top()).step_by(PAGE_SIZE) {
+                tbl.unmap(VirtAddr::from_bits(page));
+            }
+        });
+
+        // Other harts may have touched this stack, e.g. if a thread migrated.
+        table::shootdown(self.bottom(), KERNEL_STACK_PAGES * PAGE_SIZE);
+
+        unsafe { PAGE_ALLOCATOR.dealloc(self.phys as *const u8) };
+        free_slot(self.slot);
+    }
+}
+
+fn alloc_slot() -> Option<usize> {
+    let mut slots = SLOTS.lock();
+    let (word, bits) = slots.iter_mut().enumerate().find(|(_, bits)| **bits != u64::MAX)?;
+    let bit = bits.trailing_ones() as usize;
+    *bits |= 1 << bit;
+    Some(word * 64 + bit)
+}
+
+fn free_slot(slot: usize) {
+    SLOTS.lock()[slot / 64] &= !(1 << (slot % 64));
+}
+
+/// If `addr` is in the guard page of a kernel stack, returns
+/// the bottom of the stack it guards.
+pub fn guarded_stack(addr: usize) -> Option<usize> {
+    let offset = addr.checked_sub(KERNEL_STACKS_BASE)?;
+    if offset >= MAX_KERNEL_STACKS * SLOT_SIZE || offset % SLOT_SIZE >= PAGE_SIZE {
+        return None;
+    }
+    Some(addr - offset % SLOT_SIZE + PAGE_SIZE)
+}
+
+/// Moves the calling hart onto the stack whose top is `sp` and calls `f`,
+/// leaving whatever was on the current stack behind.
+///
+/// # Safety
+///
+/// `sp` must be the top of a mapped stack that nothing else uses.
+pub unsafe fn run_on_stack(sp: usize, f: extern "C" fn() -> !) -> ! {
+    asm!(
+        "mv sp, {sp}",
+        "jr {f}",
+        sp = in(reg) sp,
+        f = in(reg) f,
+        options(noreturn)
+    );
+}
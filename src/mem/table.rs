@@ -1,27 +1,63 @@
 //! Mapping of physical memory to virtual memory.
 //!
-//! # TODOs
-//!
-//! sfence VMA functions
-//! make satp in init fn 
-//! map fn 
-//! unmap fn 
-//! perform id mappings
+//! Walnut uses Sv39 paging. The kernel's page table identity maps the
+//! kernel image, the heap and the MMIO regions of the devices we drive,
+//! so physical addresses handed out by the page allocator can be used
+//! as-is. Kernel stacks are the exception, see [`super::stack`].
 
 use mycelium_bitfield::bitfield;
 
-use crate::{cpu::csr::ControlStatusRegister, info, mem::allocator::ALLOCATOR, HEAP_SIZE, HEAP_START, KERNEL_STACK_END, KERNEL_STACK_SIZE, KERNEL_STACK_START, TEXT_END, TEXT_START};
-
-use super::{addr::VirtAddr, pages::{self, PAGE_ALLOCATOR}};
+use crate::{
+    cpu::csr::ControlStatusRegister, info, mem::allocator::{AllocResult, AllocationError},
+    sync::spinlock::SpinLock, util::error::WalnutError, DATA_START, HEAP_SIZE, HEAP_START,
+    KERNEL_STACK_END, KERNEL_STACK_START, RODATA_START, TEXT_END, TEXT_START,
+};
+
+use super::{addr::VirtAddr, pages::{self, PAGE_ALLOCATOR, PAGE_SIZE}, stack::KERNEL_STACKS_BASE};
+
+pub const PTE_VALID: usize = 1 << 0;
+pub const PTE_READ: usize = 1 << 1;
+pub const PTE_WRITE: usize = 1 << 2;
+pub const PTE_EXEC: usize = 1 << 3;
+pub const PTE_USER: usize = 1 << 4;
+pub const PTE_GLOBAL: usize = 1 << 5;
+pub const PTE_ACCESSED: usize = 1 << 6;
+pub const PTE_DIRTY: usize = 1 << 7;
+/// Software bit: a read-only page shared with another address space
+/// that gets copied on the first write to it.
+pub const PTE_COW: usize = 1 << 8;
+
+/// Size of the pages mapped by a leaf entry at each level.
+pub const LEVEL_PAGE_SIZE: [usize; 3] = [1 << 12, 1 << 21, 1 << 30];
+
+const SATP_SV39: usize = 8 << 60;
+
+/// MMIO regions the kernel drives directly: the PLIC,
+/// the 16550 UART and the virtio-mmio slots.
+const MMIO_REGIONS: [(usize, usize); 3] = [
+    (0x0c00_0000, 0x0c40_0000),
+    (0x1000_0000, 0x1000_1000),
+    (0x1000_1000, 0x1000_9000),
+];
 
 static mut KERNEL_PAGE_TABLE: *mut PageTable = core::ptr::null_mut();
 
+/// Serializes changes to the kernel page table after boot,
+/// as every hart maps and unmaps kernel stacks.
+static KERNEL_TABLE_LOCK: SpinLock<()> = SpinLock::new(());
+
 fn k_pgtable() -> &'static mut PageTable {
     unsafe {
         &mut *KERNEL_PAGE_TABLE
     }
 }
 
+/// Runs `f` with exclusive access to the kernel page table.
+pub fn with_kernel_table<R>(f: impl FnOnce(&mut PageTable) -> R) -> R {
+    let _guard = KERNEL_TABLE_LOCK.lock();
+    f(k_pgtable())
+}
+
 
 #[repr(C)]
 pub struct PageTable {
@@ -47,133 +83,312 @@ impl PageTableEntry {
     pub fn set_bits(&mut self, bits: usize) {
         self.0 = bits;
     }
-}
 
+    pub fn is_valid(&self) -> bool {
+        self.get(Self::VALID)
+    }
 
-pub fn init_hart() {
-    todo!();
+    /// Leaf entries map memory, the others point to the next level table.
+    pub fn is_leaf(&self) -> bool {
+        self.0 & (PTE_READ | PTE_WRITE | PTE_EXEC) != 0
+    }
+
+    /// The physical address this entry points to.
+    pub fn addr(&self) -> usize {
+        self.get(Self::PPN) << 12
+    }
+
+    /// The `PTE_*` bits of this entry, including the software ones.
+    pub fn flags(&self) -> usize {
+        self.0 & 0x3ff
+    }
+
+
+    fn table(&self) -> *mut PageTable {
+        self.addr() as *mut PageTable
+    }
 }
 
+impl PageTable {
+    /// Allocates a new, empty, page table.
+    pub fn new() -> AllocResult<&'static mut PageTable> {
+        let page = unsafe { PAGE_ALLOCATOR.zalloc(1) }
+            .ok_or(AllocationError::new("Unable to allocate a page for a page table"))?;
+        Ok(unsafe { &mut *(page as *mut PageTable) })
+    }
 
-/// Currently this doesnt work 
-/// we did ID map the the text section and stack section
-/// All seemed fine, but i we attempt to 
-/// print, we error on some operations on `a0`
-/// so we disabled it
-pub fn initialize() {
+    /// The value to write to `satp` to switch to this table.
+    pub fn satp(&self) -> usize {
+        SATP_SV39 | (self as *const PageTable as usize >> 12)
+    }
 
-    unsafe {
-        KERNEL_PAGE_TABLE = PAGE_ALLOCATOR.zalloc(1).expect("Unable to allocate page for kernel page table.") as *mut PageTable;
+    pub fn entry(&self, idx: usize) -> &PageTableEntry {
+        &self.entries[idx]
     }
 
+    pub fn entry_mut(&mut self, idx: usize) -> &mut PageTableEntry {
+        &mut self.entries[idx]
+    }
 
-    unsafe {
-        id_map_range(
-                    HEAP_START,
-                    HEAP_START + ALLOCATOR.alloc_cnt() * 4096,
-                    3 << 1);
-
-        id_map_range(
-                    TEXT_START,
-                    TEXT_END,
-                    7 << 1);
-
-        id_map_range(
-                    KERNEL_STACK_START,
-                    KERNEL_STACK_END,
-                    3 << 1);
-
-        map(VirtAddr::from_bits(0x1000_0000), 0x1000_0000, 3 << 1, 0);
-
-    }
-
-        info!("ID Mapped heap from {:#0x} to {:#0x}", 
-				HEAP_START,
-                    HEAP_START + ALLOCATOR.alloc_cnt() * 4096,
-        );
-        info!("ID Mapped kernel stack from {:#0x} to {:#0x}", 
-                    KERNEL_STACK_START,
-                    KERNEL_STACK_END
-        );
-        info!("ID Mapped .text from {:#0x} to {:#0x}. PC at: {}", 
-                    TEXT_START,
-                    TEXT_END, ControlStatusRegister::Sepc.read()
-        );
-    unsafe {
-        ControlStatusRegister::Satp.write((8 << 60) | KERNEL_PAGE_TABLE as usize >> 12);
+    /// Maps `va` to `pa` with a leaf entry at level `lvl`,
+    /// so 4 KiB pages for level 0, 2 MiB for level 1 and 1 GiB for level 2.
+    ///
+    /// Intermediate tables are allocated as needed.
+    pub fn map(&mut self, va: VirtAddr, pa: usize, flags: usize, lvl: usize) -> crate::Result<()> {
+        assert!(flags & (PTE_READ | PTE_WRITE | PTE_EXEC) != 0);
+        assert!(pa.is_multiple_of(LEVEL_PAGE_SIZE[lvl]));
+
+        let mut v = &mut self.entries[va.lvl_idx(2)];
+
+        for i in (lvl..2).rev() {
+            if !v.is_valid() {
+                let page = PageTable::new()?;
+                v.set_bits((page as *mut PageTable as usize >> 2) | PTE_VALID);
+            } else if v.is_leaf() {
+                return Err(WalnutError::new("Address is already mapped by a larger page"));
+            }
+            v = unsafe { &mut (*v.table()).entries[va.lvl_idx(i)] };
+        }
+
+        if v.is_valid() {
+            return Err(WalnutError::new("Address is already mapped"));
+        }
+
+        // We never rely on the hardware to track accesses,
+        // so set the A and D bits right away.
+        v.set_bits((pa >> 2) | flags | PTE_ACCESSED | PTE_DIRTY | PTE_VALID);
+        Ok(())
     }
 
-}
+    /// Maps `size` bytes from `va` to `pa`, using 2 MiB pages wherever
+    /// both addresses are suitably aligned.
+    pub fn map_range(&mut self, va: usize, pa: usize, size: usize, flags: usize) -> crate::Result<()> {
+        let mut offset = 0;
+        while offset < size {
+            let lvl = if (va + offset).is_multiple_of(LEVEL_PAGE_SIZE[1])
+                && (pa + offset).is_multiple_of(LEVEL_PAGE_SIZE[1])
+                && size - offset >= LEVEL_PAGE_SIZE[1]
+            {
+                1
+            } else {
+                0
+            };
+            self.map(VirtAddr::from_bits(va + offset), pa + offset, flags, lvl)?;
+            offset += LEVEL_PAGE_SIZE[lvl];
+        }
+        Ok(())
+    }
 
-pub fn id_map_range(
-	start: usize,
-	end: usize,
-	bits: isize)
+    /// Get the leaf PTE that corresponds to the
+    /// virtual address, and the level it is at.
+    pub fn walk(&mut self, va: VirtAddr) -> Option<(&mut PageTableEntry, usize)> {
+        let mut v = &mut self.entries[va.lvl_idx(2)];
+
+        for i in (0..=2).rev() {
+            if !v.is_valid() {
+                return None;
+            }
+            if v.is_leaf() {
+                return Some((v, i));
+            }
+            if i == 0 {
+                return None;
+            }
+            v = unsafe { &mut (*v.table()).entries[va.lvl_idx(i - 1)] };
+        }
+        None
+    }
 
-{
-	let mut memaddr = start & !(pages::PAGE_SIZE - 1);
-	let num_kb_pages = (pages::align(end, 12)
-		- memaddr)
-		/ pages::PAGE_SIZE;
-
-    info!("Mapping {} pages", num_kb_pages);
-
-	// I named this num_kb_pages for future expansion when
-	// I decide to allow for GiB (2^30) and 2MiB (2^21) page
-	// sizes. However, the overlapping memory regions are causing
-	// nightmares.
-	for _ in 0..num_kb_pages {
-        unsafe {
-            map(VirtAddr::from_bits(memaddr as usize), memaddr as usize, bits, 0);
+    /// The level 0 entry for `va`, valid or not, if there is a table for it.
+    pub fn page_entry(&mut self, va: VirtAddr) -> Option<&mut PageTableEntry> {
+        let mut v = &mut self.entries[va.lvl_idx(2)];
+        for i in (0..2).rev() {
+            if !v.is_valid() || v.is_leaf() {
+                return None;
+            }
+            v = unsafe { &mut (*v.table()).entries[va.lvl_idx(i)] };
         }
-        memaddr += 1 << 12;
-	}
+        Some(v)
+    }
+
+    /// The level 0 entry for `va`, allocating the tables leading to it.
+    pub fn page_entry_or_create(&mut self, va: VirtAddr) -> crate::Result<&mut PageTableEntry> {
+        let mut v = &mut self.entries[va.lvl_idx(2)];
+        for i in (0..2).rev() {
+            if !v.is_valid() {
+                let page = PageTable::new()?;
+                v.set_bits((page as *mut PageTable as usize >> 2) | PTE_VALID);
+            } else if v.is_leaf() {
+                return Err(WalnutError::new("Address is already mapped by a larger page"));
+            }
+            v = unsafe { &mut (*v.table()).entries[va.lvl_idx(i)] };
+        }
+        Ok(v)
+    }
+
+    /// Translates a virtual address to the physical address it maps to.
+    pub fn translate(&mut self, va: usize) -> Option<usize> {
+        let (entry, lvl) = self.walk(VirtAddr::from_bits(va))?;
+        Some(entry.addr() + (va % LEVEL_PAGE_SIZE[lvl]))
+    }
+
+    /// Frees every table below this one reachable through root entries
+    /// `entries`, handing the address of every 4 KiB leaf to `free_page`.
+    /// Larger leaves are left alone. This table itself is kept.
+    pub fn free_subtables(&mut self, entries: core::ops::Range<usize>, free_page: &mut impl FnMut(usize)) {
+        for idx in entries {
+            let entry = &mut self.entries[idx];
+            if entry.is_valid() && !entry.is_leaf() {
+                unsafe { free_table(entry.table(), 1, free_page) };
+            }
+            entry.set_bits(0);
+        }
+    }
+
+    /// Calls `f` with the virtual address and entry of every
+    /// 4 KiB leaf reachable through root entries `entries`.
+    pub fn for_each_page(&mut self, entries: core::ops::Range<usize>, f: &mut impl FnMut(usize, &mut PageTableEntry)) {
+        for idx in entries {
+            let entry = &mut self.entries[idx];
+            if entry.is_valid() && !entry.is_leaf() {
+                unsafe { for_each_page(entry.table(), 1, idx << 30, f) };
+            }
+        }
+    }
+
+    /// Removes the mapping for `va`, returning the physical
+    /// address it pointed to.
+    ///
+    /// Intermediate tables are kept around, and
+    /// flushing the TLB is up to the caller.
+    pub fn unmap(&mut self, va: VirtAddr) -> Option<usize> {
+        let (entry, _) = self.walk(va)?;
+        let pa = entry.addr();
+        entry.set_bits(0);
+        Some(pa)
+    }
 }
 
+unsafe fn free_table(table: *mut PageTable, lvl: usize, free_page: &mut impl FnMut(usize)) {
+    for entry in (*table).entries.iter() {
+        if !entry.is_valid() {
+            continue;
+        }
+        if !entry.is_leaf() {
+            free_table(entry.table(), lvl - 1, free_page);
+        } else if lvl == 0 {
+            free_page(entry.addr());
+        }
+    }
+    PAGE_ALLOCATOR.dealloc(table);
+}
 
-pub unsafe fn map(va: VirtAddr, pa: usize, flags: isize, lvl: usize) {
+unsafe fn for_each_page(
+    table: *mut PageTable,
+    lvl: usize,
+    base: usize,
+    f: &mut impl FnMut(usize, &mut PageTableEntry),
+) {
+    for (idx, entry) in (*table).entries.iter_mut().enumerate() {
+        let va = base + idx * LEVEL_PAGE_SIZE[lvl];
+        if !entry.is_valid() {
+            continue;
+        }
+        if !entry.is_leaf() {
+            for_each_page(entry.table(), lvl - 1, va, f);
+        } else if lvl == 0 {
+            f(va, entry);
+        }
+    }
+}
 
-    assert!(flags & 0xe != 0);
+/// The value to write to `satp` to run with only the kernel mapped.
+pub fn kernel_satp() -> usize {
+    k_pgtable().satp()
+}
 
-    let tbl = k_pgtable();
+/// Copies the root entries of the kernel page table into `table`, so
+/// that the kernel stays mapped while running with it. User memory
+/// must stay out of the ranges these entries cover.
+pub fn share_kernel_mappings(table: &mut PageTable) {
+    let _guard = KERNEL_TABLE_LOCK.lock();
+    for (idx, entry) in k_pgtable().entries.iter().enumerate() {
+        if entry.is_valid() {
+            table.entries[idx].set_bits(entry.0);
+        }
+    }
+}
 
-    	let ppn = [
-				// PPN[0] = paddr[20:12]
-				(pa >> 12) & 0x1ff,
-				// PPN[1] = paddr[29:21]
-				(pa >> 21) & 0x1ff,
-				// PPN[2] = paddr[55:30]
-				(pa >> 30) & 0x3ff_ffff,
-	];
+/// Sets up the kernel page table, every hart then
+/// enables it for itself in [`init_hart`].
+pub fn initialize() -> crate::Result<()> {
 
-    let mut v = &mut tbl.entries[va.lvl_idx(2)];
+    unsafe {
+        KERNEL_PAGE_TABLE = PageTable::new()? as *mut PageTable;
+    }
 
-    for i in (lvl..2).rev() {
+    unsafe {
+        let heap_start = pages::align(HEAP_START, 12);
 
+        id_map_range(TEXT_START, TEXT_END, PTE_READ | PTE_EXEC)?;
+        id_map_range(RODATA_START, DATA_START, PTE_READ)?;
+        id_map_range(DATA_START, heap_start, PTE_READ | PTE_WRITE)?;
+        id_map_range(heap_start, HEAP_START + HEAP_SIZE, PTE_READ | PTE_WRITE)?;
+        id_map_range(KERNEL_STACK_START, KERNEL_STACK_END, PTE_READ | PTE_WRITE)?;
 
-        if !v.get(PageTableEntry::VALID) {
-            // TODO handle this error properly
-            let page = PAGE_ALLOCATOR.zalloc(1).unwrap();
-            v.set_bits((page as i64 >> 2) as usize);
-            v.set(PageTableEntry::VALID, true);
+        for (start, end) in MMIO_REGIONS {
+            id_map_range(start, end, PTE_READ | PTE_WRITE)?;
         }
-        let e = ((v.bits()as isize & !0x3ff) << 2) as *mut PageTableEntry;
-        v = e.add(va.lvl_idx(i)).as_mut().unwrap();
+
+        // Kernel stacks are mapped after processes copied the kernel's
+        // root entries, so the table they go in has to exist up front.
+        let root = &mut k_pgtable().entries[VirtAddr::from_bits(KERNEL_STACKS_BASE).lvl_idx(2)];
+        let table = PageTable::new()?;
+        root.set_bits((table as *mut PageTable as usize >> 2) | PTE_VALID);
+
+        info!("ID Mapped .text from {:#0x} to {:#0x}", TEXT_START, TEXT_END);
+        info!("ID Mapped kernel data from {:#0x} to {:#0x}", RODATA_START, heap_start);
+        info!("ID Mapped heap from {:#0x} to {:#0x}", heap_start, HEAP_START + HEAP_SIZE);
+        info!("ID Mapped boot stacks from {:#0x} to {:#0x}", KERNEL_STACK_START, KERNEL_STACK_END);
     }
 
-    let entry = (ppn[2] << 28) as isize |   // PPN[2] = [53:28]
-			(ppn[1] << 19) as isize |   // PPN[1] = [27:19]
-			(ppn[0] << 10) as isize |   // PPN[0] = [18:10]
-			flags | 1;
-    v.set_bits(entry as usize);
+    Ok(())
 }
 
+/// Turns on paging with the kernel page table on the calling hart.
+pub fn init_hart() {
+    ControlStatusRegister::Satp.write(k_pgtable().satp());
+    flush_tlb();
+}
 
-/// Get the PTE that corresponds 
-/// to the virtual address.
-pub fn walk(va: VirtAddr) -> Option<&'static PageTableEntry> {
+/// Identity maps `[start, end)` in the kernel page table,
+/// rounding out to page boundaries.
+pub fn id_map_range(
+	start: usize,
+	end: usize,
+	bits: usize) -> crate::Result<()>
+{
+	let memaddr = start & !(pages::PAGE_SIZE - 1);
+	let size = pages::align(end, 12) - memaddr;
 
+    info!("Mapping {} pages", size / PAGE_SIZE);
 
+    with_kernel_table(|tbl| tbl.map_range(memaddr, memaddr, size, bits))
+}
+
+/// Flushes every TLB entry of the calling hart.
+pub fn flush_tlb() {
+    unsafe { core::arch::asm!("sfence.vma") };
+}
+
+/// Flushes the calling hart's TLB entries for the page containing `va`.
+pub fn flush_tlb_page(va: usize) {
+    unsafe { core::arch::asm!("sfence.vma {0}, zero", in(reg) va) };
+}
 
-    None
+/// Flushes the TLB entries for `[start, start + len)` on every hart,
+/// after a mapping was removed or made more restrictive.
+pub fn shootdown(start: usize, len: usize) {
+    flush_tlb();
+    let _ = crate::sbi::remote_sfence_vma(0, usize::MAX, start, len);
 }
@@ -0,0 +1,340 @@
+//! Virtual memory areas.
+//!
+//! An address space keeps track of what its user memory is meant to hold
+//! as an ordered map of non-overlapping regions ([`Vma`]s), with their
+//! permissions and what backs them. Pages are only put into the page table
+//! once they are used (see [`super::space::AddressSpace::handle_fault`]),
+//! so a region can be far larger than the memory it ends up using.
+
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use core::{fmt, ops::Range};
+
+use mycelium_bitfield::bitfield;
+
+use crate::util::error::WalnutError;
+
+use super::{
+    pages::PAGE_SIZE,
+    shm::SharedMemory,
+    table::{PTE_EXEC, PTE_READ, PTE_WRITE},
+};
+
+/// Something whose contents can be mapped into memory, like a file.
+pub trait Mappable: Send + Sync {
+    /// Fills `buf` with the contents at `offset`,
+    /// with zeroes for whatever lies past the end.
+    fn read_page(&self, offset: usize, buf: &mut [u8]) -> crate::Result<()>;
+
+    /// What to call the mapping in memory maps.
+    fn name(&self) -> String;
+}
+
+/// What the pages of a region are filled with when first used.
+#[derive(Clone)]
+pub enum Backing {
+    /// Zeroes.
+    Anonymous,
+    /// Contents of `file`, starting at `offset` for the first page.
+    File { file: Arc<dyn Mappable>, offset: usize },
+    /// The pages of `shm` themselves from `offset` on, rather than copies.
+    Shared { shm: Arc<SharedMemory>, offset: usize },
+}
+
+impl Backing {
+    /// Where the first page of the region is in what backs it.
+    fn offset_mut(&mut self) -> Option<&mut usize> {
+        match self {
+            Self::Anonymous => None,
+            Self::File { offset, .. } | Self::Shared { offset, .. } => Some(offset),
+        }
+    }
+}
+
+bitfield! {
+    #[derive(PartialEq, Eq)]
+    pub struct VmaFlags<u8> {
+        /// Pages are shared with forked children rather than copied.
+        pub const SHARED: bool;
+        /// The region extends downwards when touched right below its start.
+        pub const GROWS_DOWN: bool;
+    }
+}
+
+/// A region of user memory.
+#[derive(Clone)]
+pub struct Vma {
+    pub start: usize,
+    pub end: usize,
+    /// `PTE_READ`, `PTE_WRITE` and `PTE_EXEC` bits.
+    pub perms: usize,
+    pub backing: Backing,
+    pub flags: VmaFlags,
+    /// Shown in memory maps for regions without a file, like `[heap]`.
+    pub label: Option<&'static str>,
+}
+
+impl Vma {
+    /// A private region of zero-filled memory over `[start, end)`.
+    pub fn anonymous(start: usize, end: usize, perms: usize) -> Self {
+        Self { start, end, perms, backing: Backing::Anonymous, flags: VmaFlags::new(), label: None }
+    }
+
+    /// A private region over `[start, end)` mapping `file` from `offset`.
+    pub fn file(start: usize, end: usize, perms: usize, file: Arc<dyn Mappable>, offset: usize) -> Self {
+        Self { start, end, perms, backing: Backing::File { file, offset }, flags: VmaFlags::new(), label: None }
+    }
+
+    /// A region over `[start, end)` mapping the pages of `shm` from `offset`,
+    /// shared with forked children too.
+    pub fn shared_memory(start: usize, end: usize, perms: usize, shm: Arc<SharedMemory>, offset: usize) -> Self {
+        Self { start, end, perms, backing: Backing::Shared { shm, offset }, flags: VmaFlags::new(), label: None }.shared()
+    }
+
+    /// Marks the region as shared with forked children.
+    pub fn shared(mut self) -> Self {
+        self.flags.set(VmaFlags::SHARED, true);
+        self
+    }
+
+    /// Marks the region as a stack, growing down when touched below its start.
+    pub fn growing_down(mut self) -> Self {
+        self.flags.set(VmaFlags::GROWS_DOWN, true);
+        self
+    }
+
+    pub fn with_label(mut self, label: &'static str) -> Self {
+        self.label = Some(label);
+        self
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn is_shared(&self) -> bool {
+        self.flags.get(VmaFlags::SHARED)
+    }
+
+    pub fn grows_down(&self) -> bool {
+        self.flags.get(VmaFlags::GROWS_DOWN)
+    }
+
+    /// Fills `buf` with what the page at `page` starts out with.
+    pub fn fill(&self, page: usize, buf: &mut [u8]) -> crate::Result<()> {
+        match &self.backing {
+            Backing::Anonymous => {
+                buf.fill(0);
+                Ok(())
+            }
+            Backing::File { file, offset } => file.read_page(offset + (page - self.start), buf),
+            Backing::Shared { shm, offset } => shm.read_page(offset + (page - self.start), buf),
+        }
+    }
+
+    /// The page of shared memory to map at `page` as it is, if the region has one.
+    pub fn shared_frame(&self, page: usize) -> Option<usize> {
+        match &self.backing {
+            Backing::Shared { shm, offset } => shm.frame(offset + (page - self.start)),
+            _ => None,
+        }
+    }
+
+    /// Splits off the part of the region from `at` on.
+    fn split_off(&mut self, at: usize) -> Vma {
+        let mut tail = self.clone();
+        tail.start = at;
+        if let Some(offset) = tail.backing.offset_mut() {
+            *offset += at - self.start;
+        }
+        self.end = at;
+        tail
+    }
+
+    /// Whether `next`, which starts where this region ends, could be part of it.
+    fn can_merge(&self, next: &Vma) -> bool {
+        let backing = match (&self.backing, &next.backing) {
+            (Backing::Anonymous, Backing::Anonymous) => true,
+            (Backing::File { file: a, offset: a_off }, Backing::File { file: b, offset: b_off }) => {
+                Arc::ptr_eq(a, b) && a_off + self.len() == *b_off
+            }
+            (Backing::Shared { shm: a, offset: a_off }, Backing::Shared { shm: b, offset: b_off }) => {
+                Arc::ptr_eq(a, b) && a_off + self.len() == *b_off
+            }
+            _ => false,
+        };
+        backing
+            && self.end == next.start
+            && self.perms == next.perms
+            && self.flags == next.flags
+            && self.label == next.label
+    }
+}
+
+/// The way `/proc/<pid>/maps` on Linux shows a region.
+impl fmt::Display for Vma {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let perm = |bit, c| if self.perms & bit != 0 { c } else { '-' };
+        let (offset, name) = match &self.backing {
+            Backing::Anonymous => (0, String::from(self.label.unwrap_or(""))),
+            Backing::File { file, offset } => (*offset, file.name()),
+            Backing::Shared { shm, offset } => (*offset, shm.name()),
+        };
+        write!(
+            f,
+            "{:08x}-{:08x} {}{}{}{} {:08x} 00:00 0 {}",
+            self.start,
+            self.end,
+            perm(PTE_READ, 'r'),
+            perm(PTE_WRITE, 'w'),
+            perm(PTE_EXEC, 'x'),
+            if self.is_shared() { 's' } else { 'p' },
+            offset,
+            name
+        )
+    }
+}
+
+/// The regions of an address space, keyed by where they start.
+#[derive(Clone, Default)]
+pub struct VmaMap {
+    areas: BTreeMap<usize, Vma>,
+}
+
+impl VmaMap {
+    pub const fn new() -> Self {
+        Self { areas: BTreeMap::new() }
+    }
+
+    /// The region `va` lies in.
+    pub fn find(&self, va: usize) -> Option<&Vma> {
+        self.areas.range(..=va).next_back().map(|(_, v)| v).filter(|v| va < v.end)
+    }
+
+    /// The first region ending after `va`.
+    pub fn next_after(&self, va: usize) -> Option<&Vma> {
+        self.find(va).or_else(|| self.areas.range(va..).next().map(|(_, v)| v))
+    }
+
+    /// Whether no region overlaps `[start, end)`.
+    pub fn is_free(&self, start: usize, end: usize) -> bool {
+        self.areas.range(..end).next_back().is_none_or(|(_, v)| v.end <= start)
+    }
+
+    /// Adds a region, which must not overlap any other, merging it with
+    /// its neighbours where possible.
+    pub fn insert(&mut self, vma: Vma) -> crate::Result<()> {
+        if vma.is_empty() || !vma.start.is_multiple_of(PAGE_SIZE) || !vma.end.is_multiple_of(PAGE_SIZE) {
+            return Err(WalnutError::new("Memory area is not made of whole pages"));
+        }
+        if !self.is_free(vma.start, vma.end) {
+            return Err(WalnutError::new("Memory area overlaps another one"));
+        }
+        let (start, end) = (vma.start, vma.end);
+        self.areas.insert(start, vma);
+        self.merge_at(end);
+        self.merge_at(start);
+        Ok(())
+    }
+
+    /// Makes sure no region crosses `at`.
+    fn split(&mut self, at: usize) {
+        let Some((_, vma)) = self.areas.range_mut(..at).next_back() else {
+            return;
+        };
+        if vma.end > at {
+            let tail = vma.split_off(at);
+            self.areas.insert(at, tail);
+        }
+    }
+
+    /// Joins the region ending at `at` with the one starting there, if they
+    /// only differ in where they are.
+    fn merge_at(&mut self, at: usize) {
+        let Some(next) = self.areas.get(&at) else {
+            return;
+        };
+        let Some((_, prev)) = self.areas.range(..at).next_back() else {
+            return;
+        };
+        if prev.can_merge(next) {
+            let next = self.areas.remove(&at).expect("Region vanished");
+            let prev = self.areas.range_mut(..at).next_back().map(|(_, v)| v).expect("Region vanished");
+            prev.end = next.end;
+        }
+    }
+
+    /// Removes `[start, end)` from whatever regions cover it,
+    /// splitting the ones only partly covered.
+    pub fn remove(&mut self, start: usize, end: usize) {
+        self.split(start);
+        self.split(end);
+        let starts: Vec<usize> = self.areas.range(start..end).map(|(s, _)| *s).collect();
+        for s in starts {
+            self.areas.remove(&s);
+        }
+    }
+
+    /// Changes the permissions of `[start, end)`, which has to be covered by
+    /// regions without gaps, splitting the ones only partly covered.
+    pub fn protect(&mut self, start: usize, end: usize, perms: usize) -> crate::Result<()> {
+        let mut at = start;
+        while at < end {
+            match self.find(at) {
+                Some(vma) => at = vma.end,
+                None => return Err(WalnutError::new("Protecting unmapped memory")),
+            }
+        }
+
+        self.split(start);
+        self.split(end);
+        for (_, vma) in self.areas.range_mut(start..end) {
+            vma.perms = perms;
+        }
+
+        let starts: Vec<usize> = self.areas.range(start..=end).map(|(s, _)| *s).collect();
+        for s in starts.into_iter().rev() {
+            self.merge_at(s);
+        }
+        Ok(())
+    }
+
+    /// Moves the start of the region starting at `start` down to `new_start`.
+    pub fn extend_down(&mut self, start: usize, new_start: usize) {
+        if let Some(mut vma) = self.areas.remove(&start) {
+            if let Some(offset) = vma.backing.offset_mut() {
+                *offset -= start - new_start;
+            }
+            vma.start = new_start;
+            self.areas.insert(new_start, vma);
+        }
+    }
+
+    /// Finds the highest `len` bytes in `[lowest, highest)` no region covers.
+    pub fn find_free(&self, len: usize, lowest: usize, highest: usize) -> Option<usize> {
+        let mut top = highest;
+        for (_, vma) in self.areas.range(..highest).rev() {
+            if vma.end <= top && top - vma.end >= len {
+                break;
+            }
+            top = top.min(vma.start);
+        }
+        let start = top.checked_sub(len)?;
+        (start >= lowest).then_some(start)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Vma> {
+        self.areas.values()
+    }
+
+    pub fn clear(&mut self) {
+        self.areas.clear();
+    }
+}
@@ -0,0 +1,93 @@
+//! Earliest deadline first class, for periodic tasks.
+//!
+//! A deadline thread gets `runtime` ticks every `period`. Once it used
+//! them up it is throttled until its deadline, where a new period starts.
+
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::sync::atomic::Ordering;
+
+use super::{ClassQueue, Rank, SchedClass};
+use crate::process::thread::{Thread, Tid};
+
+pub struct EdfQueue {
+    threads: BTreeMap<(u64, Tid), Arc<Thread>>,
+    /// Threads out of runtime, and when they get it back.
+    throttled: Vec<(u64, Arc<Thread>)>,
+}
+
+impl EdfQueue {
+    pub(crate) const fn new() -> Self {
+        Self { threads: BTreeMap::new(), throttled: Vec::new() }
+    }
+
+    fn insert(&mut self, thread: Arc<Thread>) {
+        let deadline = thread.entity().deadline.load(Ordering::Relaxed);
+        self.threads.insert((deadline, thread.tid()), thread);
+    }
+
+    /// Starts a new period for throttled threads whose time has come.
+    fn release(&mut self, now: u64) {
+        let mut i = 0;
+        while i < self.throttled.len() {
+            if self.throttled[i].0 <= now {
+                let (_, thread) = self.throttled.swap_remove(i);
+                replenish(&thread, now);
+                self.insert(thread);
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
+
+fn params(thread: &Thread) -> (u64, u64) {
+    match thread.entity().effective_class() {
+        SchedClass::Deadline { runtime, period } => (runtime, period),
+        _ => (0, 0),
+    }
+}
+
+fn replenish(thread: &Thread, now: u64) {
+    let (runtime, period) = params(thread);
+    let entity = thread.entity();
+    entity.deadline.store(now + period, Ordering::Relaxed);
+    entity.budget.store(runtime, Ordering::Relaxed);
+}
+
+impl ClassQueue for EdfQueue {
+    fn enqueue(&mut self, thread: Arc<Thread>, now: u64) {
+        let entity = thread.entity();
+        let deadline = entity.deadline.load(Ordering::Relaxed);
+        let budget = entity.budget.load(Ordering::Relaxed);
+
+        if now >= deadline {
+            replenish(&thread, now);
+        } else if budget == 0 {
+            self.throttled.push((deadline, thread));
+            return;
+        }
+        self.insert(thread);
+    }
+
+    fn peek(&mut self, now: u64) -> Option<Rank> {
+        self.release(now);
+        self.threads.first_key_value().map(|(_, t)| t.entity().rank())
+    }
+
+    fn pop(&mut self, now: u64) -> Option<Arc<Thread>> {
+        self.release(now);
+        self.threads.pop_first().map(|(_, t)| t)
+    }
+
+    fn remove(&mut self, tid: Tid) -> Option<Arc<Thread>> {
+        if let Some(idx) = self.throttled.iter().position(|(_, t)| t.tid() == tid) {
+            return Some(self.throttled.swap_remove(idx).1);
+        }
+        let key = *self.threads.keys().find(|(_, t)| *t == tid)?;
+        self.threads.remove(&key)
+    }
+
+    fn len(&self) -> usize {
+        self.threads.len() + self.throttled.len()
+    }
+}
@@ -0,0 +1,49 @@
+//! Fair-share class: the thread that ran the least, relative to its
+//! weight, runs next.
+
+use alloc::{collections::BTreeMap, sync::Arc};
+use core::sync::atomic::Ordering;
+
+use super::{ClassQueue, Rank};
+use crate::process::thread::{Thread, Tid};
+
+pub struct FairQueue {
+    threads: BTreeMap<(u64, Tid), Arc<Thread>>,
+    /// Virtual run time of the last thread we picked. Threads that were
+    /// asleep start from here, so they can not hog the hart to catch up.
+    min_vruntime: u64,
+}
+
+impl FairQueue {
+    pub(crate) const fn new() -> Self {
+        Self { threads: BTreeMap::new(), min_vruntime: 0 }
+    }
+}
+
+impl ClassQueue for FairQueue {
+    fn enqueue(&mut self, thread: Arc<Thread>, _now: u64) {
+        let vruntime = &thread.entity().vruntime;
+        let v = vruntime.load(Ordering::Relaxed).max(self.min_vruntime);
+        vruntime.store(v, Ordering::Relaxed);
+        self.threads.insert((v, thread.tid()), thread);
+    }
+
+    fn peek(&mut self, _now: u64) -> Option<Rank> {
+        self.threads.first_key_value().map(|(_, t)| t.entity().rank())
+    }
+
+    fn pop(&mut self, _now: u64) -> Option<Arc<Thread>> {
+        let ((v, _), thread) = self.threads.pop_first()?;
+        self.min_vruntime = self.min_vruntime.max(v);
+        Some(thread)
+    }
+
+    fn remove(&mut self, tid: Tid) -> Option<Arc<Thread>> {
+        let key = *self.threads.keys().find(|(_, t)| *t == tid)?;
+        self.threads.remove(&key)
+    }
+
+    fn len(&self) -> usize {
+        self.threads.len()
+    }
+}
@@ -0,0 +1,240 @@
+//! Scheduling classes.
+//!
+//! Every thread belongs to a [`SchedClass`], which decides which of the
+//! per-hart queues it waits in and how it competes with the threads next
+//! to it. Classes are strictly ordered: a runnable deadline thread always
+//! runs before a real-time one, which always runs before a fair one.
+
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+    cmp::Ordering as CmpOrdering,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::sync::spinlock::SpinLock;
+
+use super::thread::{Thread, Tid};
+
+pub mod edf;
+pub mod fair;
+pub mod rt;
+
+/// Highest priority of the real-time class.
+pub const MAX_RT_PRIORITY: u8 = 99;
+
+/// Weight of a fair thread that asked for nothing in particular.
+pub const DEFAULT_WEIGHT: u32 = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedClass {
+    /// A periodic task that needs `runtime` timer ticks of CPU time
+    /// every `period` ticks, scheduled earliest deadline first.
+    Deadline { runtime: u64, period: u64 },
+    /// A fixed priority, up to [`MAX_RT_PRIORITY`], higher runs first.
+    /// Threads of equal priority take turns.
+    RealTime { priority: u8 },
+    /// Shares the hart with other fair threads in proportion to `weight`.
+    Fair { weight: u32 },
+}
+
+impl Default for SchedClass {
+    fn default() -> Self {
+        Self::Fair { weight: DEFAULT_WEIGHT }
+    }
+}
+
+impl SchedClass {
+    /// How urgent this class is, for priority inheritance.
+    fn urgency(&self) -> (u8, u64) {
+        match *self {
+            Self::Deadline { period, .. } => (2, u64::MAX - period),
+            Self::RealTime { priority } => (1, priority as u64),
+            Self::Fair { weight } => (0, weight as u64),
+        }
+    }
+
+    /// Whether a thread of this class should run before one of `other`.
+    pub fn outranks(&self, other: &SchedClass) -> bool {
+        self.urgency() > other.urgency()
+    }
+}
+
+/// Where a runnable thread stands, a greater `Rank` runs first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rank {
+    /// Which class the thread is in, see [`SchedClass::urgency`].
+    level: u8,
+    /// Position within the class, lower runs first.
+    key: u64,
+}
+
+impl Ord for Rank {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        self.level.cmp(&other.level).then(other.key.cmp(&self.key))
+    }
+}
+
+impl PartialOrd for Rank {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Per-thread scheduling state.
+pub struct SchedEntity {
+    class: SpinLock<SchedClass>,
+    /// Classes lent to us by threads waiting on mutexes we hold,
+    /// by mutex address.
+    boosts: SpinLock<Vec<(usize, SchedClass)>>,
+    /// Weighted run time of a fair thread, in timer ticks.
+    vruntime: AtomicU64,
+    /// Absolute deadline of a deadline thread.
+    deadline: AtomicU64,
+    /// What is left of a deadline thread's runtime for this period.
+    budget: AtomicU64,
+}
+
+impl SchedEntity {
+    pub fn new(class: SchedClass) -> Self {
+        Self {
+            class: SpinLock::new(class),
+            boosts: SpinLock::new(Vec::new()),
+            vruntime: AtomicU64::new(0),
+            deadline: AtomicU64::new(0),
+            budget: AtomicU64::new(0),
+        }
+    }
+
+    pub fn class(&self) -> SchedClass {
+        *self.class.lock()
+    }
+
+    pub fn set_class(&self, class: SchedClass) {
+        *self.class.lock() = class;
+    }
+
+    /// The class the thread is scheduled with, taking inheritance into account.
+    pub fn effective_class(&self) -> SchedClass {
+        let class = self.class();
+        self.boosts
+            .lock()
+            .iter()
+            .map(|(_, c)| *c)
+            .fold(class, |best, c| if c.outranks(&best) { c } else { best })
+    }
+
+    /// Lends `class` to this thread while it holds the mutex at `mutex`.
+    /// Returns whether this changed the effective class.
+    pub fn boost(&self, mutex: usize, class: SchedClass) -> bool {
+        if !class.outranks(&self.effective_class()) {
+            return false;
+        }
+        let mut boosts = self.boosts.lock();
+        boosts.retain(|(m, _)| *m != mutex);
+        boosts.push((mutex, class));
+        true
+    }
+
+    /// Drops whatever was lent to us for the mutex at `mutex`.
+    pub fn unboost(&self, mutex: usize) {
+        self.boosts.lock().retain(|(m, _)| *m != mutex);
+    }
+
+    pub fn rank(&self) -> Rank {
+        match self.effective_class() {
+            SchedClass::Deadline { .. } => Rank { level: 2, key: self.deadline.load(Ordering::Relaxed) },
+            SchedClass::RealTime { priority } => Rank { level: 1, key: (MAX_RT_PRIORITY - priority.min(MAX_RT_PRIORITY)) as u64 },
+            SchedClass::Fair { .. } => Rank { level: 0, key: self.vruntime.load(Ordering::Relaxed) },
+        }
+    }
+
+    /// Accounts for `ran` ticks spent running.
+    pub fn charge(&self, ran: u64) {
+        match self.effective_class() {
+            SchedClass::Fair { weight } => {
+                let weighted = ran * DEFAULT_WEIGHT as u64 / weight.max(1) as u64;
+                self.vruntime.fetch_add(weighted, Ordering::Relaxed);
+            }
+            SchedClass::Deadline { .. } => {
+                let budget = self.budget.load(Ordering::Relaxed);
+                self.budget.store(budget.saturating_sub(ran), Ordering::Relaxed);
+            }
+            SchedClass::RealTime { .. } => {}
+        }
+    }
+
+    /// Whether the thread may keep running, which is not the case
+    /// for a deadline thread that used up its runtime.
+    pub fn eligible(&self) -> bool {
+        !matches!(self.effective_class(), SchedClass::Deadline { .. })
+            || self.budget.load(Ordering::Relaxed) > 0
+    }
+}
+
+/// A queue of runnable threads of one scheduling class.
+pub trait ClassQueue {
+    fn enqueue(&mut self, thread: Arc<Thread>, now: u64);
+
+    /// The rank of the thread [`ClassQueue::pop`] would return.
+    fn peek(&mut self, now: u64) -> Option<Rank>;
+
+    fn pop(&mut self, now: u64) -> Option<Arc<Thread>>;
+
+    fn remove(&mut self, tid: Tid) -> Option<Arc<Thread>>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The run queue of a hart, with a queue per class.
+pub struct RunQueue {
+    edf: edf::EdfQueue,
+    rt: rt::RtQueue,
+    fair: fair::FairQueue,
+}
+
+impl RunQueue {
+    pub(crate) const fn new() -> Self {
+        Self {
+            edf: edf::EdfQueue::new(),
+            rt: rt::RtQueue::new(),
+            fair: fair::FairQueue::new(),
+        }
+    }
+
+    /// The class queues, most urgent first.
+    fn classes(&mut self) -> [&mut dyn ClassQueue; 3] {
+        [&mut self.edf, &mut self.rt, &mut self.fair]
+    }
+
+    pub fn enqueue(&mut self, thread: Arc<Thread>, now: u64) {
+        match thread.entity().effective_class() {
+            SchedClass::Deadline { .. } => self.edf.enqueue(thread, now),
+            SchedClass::RealTime { .. } => self.rt.enqueue(thread, now),
+            SchedClass::Fair { .. } => self.fair.enqueue(thread, now),
+        }
+    }
+
+    pub fn peek(&mut self, now: u64) -> Option<Rank> {
+        self.classes().into_iter().find_map(|q| q.peek(now))
+    }
+
+    pub fn pop(&mut self, now: u64) -> Option<Arc<Thread>> {
+        self.classes().into_iter().find_map(|q| q.pop(now))
+    }
+
+    pub fn remove(&mut self, tid: Tid) -> Option<Arc<Thread>> {
+        self.classes().into_iter().find_map(|q| q.remove(tid))
+    }
+
+    pub fn len(&mut self) -> usize {
+        self.classes().iter().map(|q| q.len()).sum()
+    }
+
+    pub fn is_empty(&mut self) -> bool {
+        self.len() == 0
+    }
+}
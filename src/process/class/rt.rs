@@ -0,0 +1,55 @@
+//! Fixed priority real-time class, round-robin within a priority.
+
+use alloc::{collections::{BTreeMap, VecDeque}, sync::Arc};
+
+use super::{ClassQueue, Rank};
+use crate::process::thread::{Thread, Tid};
+
+pub struct RtQueue {
+    queues: BTreeMap<u8, VecDeque<Arc<Thread>>>,
+}
+
+impl RtQueue {
+    pub(crate) const fn new() -> Self {
+        Self { queues: BTreeMap::new() }
+    }
+}
+
+impl ClassQueue for RtQueue {
+    fn enqueue(&mut self, thread: Arc<Thread>, _now: u64) {
+        let priority = match thread.entity().effective_class() {
+            super::SchedClass::RealTime { priority } => priority,
+            _ => 0,
+        };
+        self.queues.entry(priority).or_default().push_back(thread);
+    }
+
+    fn peek(&mut self, _now: u64) -> Option<Rank> {
+        let (_, queue) = self.queues.last_key_value()?;
+        queue.front().map(|t| t.entity().rank())
+    }
+
+    fn pop(&mut self, _now: u64) -> Option<Arc<Thread>> {
+        let mut entry = self.queues.last_entry()?;
+        let thread = entry.get_mut().pop_front();
+        if entry.get().is_empty() {
+            entry.remove();
+        }
+        thread
+    }
+
+    fn remove(&mut self, tid: Tid) -> Option<Arc<Thread>> {
+        for queue in self.queues.values_mut() {
+            if let Some(idx) = queue.iter().position(|t| t.tid() == tid) {
+                let thread = queue.remove(idx);
+                self.queues.retain(|_, q| !q.is_empty());
+                return thread;
+            }
+        }
+        None
+    }
+
+    fn len(&self) -> usize {
+        self.queues.values().map(|q| q.len()).sum()
+    }
+}
@@ -0,0 +1,346 @@
+//! Loading of ELF64 RISC-V executables into a process.
+//!
+//! Only statically linked executables (`ET_EXEC`) are supported, linked
+//! somewhere within user memory (see [`crate::mem::space`]).
+
+use alloc::{sync::Arc, vec::Vec};
+use core::error::Error;
+
+use crate::{
+    cpu,
+    mem::{
+        pages::PAGE_SIZE,
+        space::{self, AddressSpace, USER_STACK_SIZE, USER_STACK_TOP},
+    },
+    util::error::WalnutError,
+};
+
+use super::Process;
+
+const ELF_MAGIC: [u8; 4] = *b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+const EM_RISCV: u16 = 243;
+
+const EF_RISCV_RVC: u32 = 0x1;
+const EF_RISCV_FLOAT_ABI: u32 = 0x6;
+const EF_RISCV_RVE: u32 = 0x8;
+
+const PT_LOAD: u32 = 1;
+const PT_INTERP: u32 = 3;
+const PT_PHDR: u32 = 6;
+
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+/// Auxiliary vector entries we hand to programs.
+const AT_NULL: usize = 0;
+const AT_PHDR: usize = 3;
+const AT_PHENT: usize = 4;
+const AT_PHNUM: usize = 5;
+const AT_PAGESZ: usize = 6;
+const AT_ENTRY: usize = 9;
+const AT_RANDOM: usize = 25;
+
+/// How much of the stack `argv`, `envp` and the rest may take up.
+const MAX_ARGS_SIZE: usize = USER_STACK_SIZE / 4;
+
+pub type ElfResult<T> = core::result::Result<T, ElfError>;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Header {
+    pub elf_type: u16,
+    pub machine: u16,
+    pub entry: usize,
+    pub phoff: usize,
+    pub flags: u32,
+    pub phentsize: usize,
+    pub phnum: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ProgramHeader {
+    pub p_type: u32,
+    pub flags: u32,
+    pub offset: usize,
+    pub vaddr: usize,
+    pub filesz: usize,
+    pub memsz: usize,
+}
+
+impl ProgramHeader {
+    fn page_flags(&self) -> usize {
+        space::user_flags(self.flags & PF_R != 0, self.flags & PF_W != 0, self.flags & PF_X != 0)
+    }
+}
+
+/// An executable whose headers were checked, ready to be loaded.
+pub struct Elf<'a> {
+    data: &'a [u8],
+    pub header: Header,
+    pub segments: Vec<ProgramHeader>,
+}
+
+fn u16_at(data: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes([data[off], data[off + 1]])
+}
+
+fn u32_at(data: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes(data[off..off + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], off: usize) -> usize {
+    u64::from_le_bytes(data[off..off + 8].try_into().unwrap()) as usize
+}
+
+impl<'a> Elf<'a> {
+    /// Parses and validates the headers of the executable in `data`.
+    pub fn parse(data: &'a [u8]) -> ElfResult<Self> {
+        if data.len() < HEADER_SIZE || data[..4] != ELF_MAGIC {
+            return Err(ElfError::new("Not an ELF file"));
+        }
+        if data[4] != ELFCLASS64 {
+            return Err(ElfError::new("Only 64-bit ELF files are supported"));
+        }
+        if data[5] != ELFDATA2LSB {
+            return Err(ElfError::new("Only little endian ELF files are supported"));
+        }
+        if data[6] != EV_CURRENT {
+            return Err(ElfError::new("Unknown ELF version"));
+        }
+
+        let header = Header {
+            elf_type: u16_at(data, 16),
+            machine: u16_at(data, 18),
+            entry: u64_at(data, 24),
+            phoff: u64_at(data, 32),
+            flags: u32_at(data, 48),
+            phentsize: u16_at(data, 54) as usize,
+            phnum: u16_at(data, 56) as usize,
+        };
+
+        match header.elf_type {
+            ET_EXEC => {}
+            ET_DYN => return Err(ElfError::new("Position independent executables are not supported")),
+            _ => return Err(ElfError::new("ELF file is not an executable")),
+        }
+        if header.machine != EM_RISCV {
+            return Err(ElfError::new("ELF file is not for RISC-V"));
+        }
+        if header.flags & EF_RISCV_RVE != 0 {
+            return Err(ElfError::new("RV64E executables are not supported"));
+        }
+        if header.flags & EF_RISCV_RVC != 0 && !cpu::has_extension('c') {
+            return Err(ElfError::new("Executable uses compressed instructions, which this CPU lacks"));
+        }
+        if header.flags & EF_RISCV_FLOAT_ABI != 0 {
+            // We do not save floating point registers for user threads.
+            return Err(ElfError::new("Hardware floating point ABIs are not supported"));
+        }
+        if header.phentsize != PROGRAM_HEADER_SIZE {
+            return Err(ElfError::new("Unexpected program header size"));
+        }
+        let table_end = header
+            .phnum
+            .checked_mul(PROGRAM_HEADER_SIZE)
+            .and_then(|len| len.checked_add(header.phoff));
+        if table_end.is_none_or(|end| end > data.len()) {
+            return Err(ElfError::new("Program headers are out of the file"));
+        }
+
+        let mut segments = Vec::with_capacity(header.phnum);
+        for i in 0..header.phnum {
+            let off = header.phoff + i * PROGRAM_HEADER_SIZE;
+            let ph = ProgramHeader {
+                p_type: u32_at(data, off),
+                flags: u32_at(data, off + 4),
+                offset: u64_at(data, off + 8),
+                vaddr: u64_at(data, off + 16),
+                filesz: u64_at(data, off + 32),
+                memsz: u64_at(data, off + 40),
+            };
+
+            match ph.p_type {
+                PT_INTERP => return Err(ElfError::new("Dynamically linked executables are not supported")),
+                PT_LOAD => {
+                    if ph.filesz > ph.memsz {
+                        return Err(ElfError::new("Segment is larger in the file than in memory"));
+                    }
+                    if ph.offset.checked_add(ph.filesz).is_none_or(|end| end > data.len()) {
+                        return Err(ElfError::new("Segment is out of the file"));
+                    }
+                    if !space::is_user_range(ph.vaddr, ph.memsz) {
+                        return Err(ElfError::new("Segment is outside of user memory"));
+                    }
+                    if ph.vaddr + ph.memsz > USER_STACK_TOP - USER_STACK_SIZE {
+                        return Err(ElfError::new("Segment overlaps the user stack"));
+                    }
+                }
+                _ => {}
+            }
+            segments.push(ph);
+        }
+
+        let elf = Self { data, header, segments };
+        let entry_ok = elf.loads().any(|ph| {
+            ph.flags & PF_X != 0 && (ph.vaddr..ph.vaddr + ph.memsz).contains(&header.entry)
+        });
+        if !entry_ok {
+            return Err(ElfError::new("Entry point is not in an executable segment"));
+        }
+
+        Ok(elf)
+    }
+
+    fn loads(&self) -> impl Iterator<Item = &ProgramHeader> {
+        self.segments.iter().filter(|ph| ph.p_type == PT_LOAD)
+    }
+
+    /// Where the program headers end up in memory, if they are loaded at all.
+    fn phdr_addr(&self) -> Option<usize> {
+        if let Some(ph) = self.segments.iter().find(|ph| ph.p_type == PT_PHDR) {
+            return Some(ph.vaddr);
+        }
+        let phoff = self.header.phoff;
+        self.loads()
+            .find(|ph| (ph.offset..ph.offset + ph.filesz).contains(&phoff))
+            .map(|ph| ph.vaddr + (phoff - ph.offset))
+    }
+
+    /// Maps the loadable segments into `space`, returning
+    /// the end of the highest one.
+    pub fn load(&self, space: &mut AddressSpace) -> crate::Result<usize> {
+        let mut end = 0;
+        for ph in self.loads() {
+            let flags = ph.page_flags();
+            let first = ph.vaddr & !(PAGE_SIZE - 1);
+            let last = (ph.vaddr + ph.memsz).next_multiple_of(PAGE_SIZE);
+
+            for va in (first..last).step_by(PAGE_SIZE) {
+                match space.area(va).map(|v| v.perms) {
+                    // Segments may share a page at their edges,
+                    // which then gets the permissions of both.
+                    Some(current) => space.protect(va, PAGE_SIZE, current | flags)?,
+                    None => space.alloc(va, PAGE_SIZE, flags)?,
+                }
+            }
+
+            space.write(ph.vaddr, &self.data[ph.offset..ph.offset + ph.filesz])?;
+            end = end.max(last);
+        }
+        Ok(end)
+    }
+}
+
+/// Lays out `argv`, `envp` and the auxiliary vector on the user stack the
+/// way the RISC-V psABI wants them, returning the initial stack pointer.
+pub fn setup_stack(space: &mut AddressSpace, elf: &Elf, argv: &[&str], envp: &[&str]) -> crate::Result<usize> {
+    let mut sp = USER_STACK_TOP;
+
+    let mut push_str = |space: &mut AddressSpace, s: &str| -> crate::Result<usize> {
+        sp -= s.len() + 1;
+        space.write(sp, s.as_bytes())?;
+        space.write(sp + s.len(), &[0])?;
+        Ok(sp)
+    };
+
+    let argv_ptrs = argv.iter().map(|s| push_str(space, s)).collect::<crate::Result<Vec<_>>>()?;
+    let envp_ptrs = envp.iter().map(|s| push_str(space, s)).collect::<crate::Result<Vec<_>>>()?;
+
+    // Not random at all, but this is only meant to seed user space hashing.
+    let random = cpu::csr::ControlStatusRegister::Time.read().to_le_bytes().repeat(2);
+    sp -= random.len();
+    let random_addr = sp;
+    space.write(random_addr, &random)?;
+
+    let mut auxv = vec![
+        (AT_PAGESZ, PAGE_SIZE),
+        (AT_ENTRY, elf.header.entry),
+        (AT_PHENT, PROGRAM_HEADER_SIZE),
+        (AT_PHNUM, elf.header.phnum),
+        (AT_RANDOM, random_addr),
+    ];
+    if let Some(phdr) = elf.phdr_addr() {
+        auxv.push((AT_PHDR, phdr));
+    }
+    auxv.push((AT_NULL, 0));
+
+    let mut words = Vec::with_capacity(3 + argv_ptrs.len() + envp_ptrs.len() + auxv.len() * 2);
+    words.push(argv_ptrs.len());
+    words.extend(&argv_ptrs);
+    words.push(0);
+    words.extend(&envp_ptrs);
+    words.push(0);
+    for (key, value) in auxv {
+        words.push(key);
+        words.push(value);
+    }
+
+    sp = (sp - words.len() * 8) & !0xf;
+    if USER_STACK_TOP - sp > MAX_ARGS_SIZE {
+        return Err(ElfError::new("Arguments and environment do not fit on the stack").into());
+    }
+
+    let bytes: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+    space.write(sp, &bytes)?;
+    Ok(sp)
+}
+
+/// Starts the executable in `data` as a new process.
+pub fn spawn(name: &str, data: &[u8], argv: &[&str], envp: &[&str]) -> crate::Result<Arc<Process>> {
+    let elf = Elf::parse(data)?;
+    let process = Process::new(name)?;
+    let sp = load_image(&process, &elf, argv, envp)?;
+    process.spawn_thread(elf.header.entry, sp, 0)?;
+    Ok(process)
+}
+
+/// Loads `elf` into the address space of `process`, which has nothing but
+/// its stack mapped, and sets up the stack. Returns the stack pointer.
+pub(super) fn load_image(process: &Process, elf: &Elf, argv: &[&str], envp: &[&str]) -> crate::Result<usize> {
+    let mut space = process.space();
+    let end = elf.load(&mut space)?;
+    process.set_heap_start(end);
+    setup_stack(&mut space, elf, argv, envp)
+}
+
+#[derive(Debug)]
+pub struct ElfError {
+    details: &'static str,
+}
+
+impl ElfError {
+    pub fn new(msg: &'static str) -> ElfError {
+        ElfError { details: msg }
+    }
+
+    pub fn details(&self) -> &'static str {
+        self.details
+    }
+}
+
+impl core::fmt::Display for ElfError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for ElfError {
+    fn description(&self) -> &str {
+        self.details
+    }
+}
+
+impl From<ElfError> for WalnutError {
+    fn from(value: ElfError) -> Self {
+        Self::new(value.details())
+    }
+}
@@ -0,0 +1,254 @@
+//! Futexes, the kernel side of user space locks.
+//!
+//! A futex is just a 32-bit word of user memory. User code takes locks
+//! with atomics on it, and only asks the kernel to [`wait`] until someone
+//! [`wake`]s it up when there is contention. Waiters are queued by the
+//! physical address of the word, so a futex in memory shared between
+//! processes works even where they map it at different addresses. Each
+//! waiter owns a reference on the page its futex is in, which keeps the
+//! page from being freed, and its address from being reused for another
+//! futex, if it is unmapped in the meantime.
+
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+use crate::{
+    mem::{
+        pages::{PAGE_ALLOCATOR, PAGE_SIZE},
+        space::Access,
+        table::{PTE_READ, PTE_WRITE},
+    },
+    sync::spinlock::SpinLock,
+    syscall::{errno::Errno, SysResult},
+};
+
+use super::{current_process, sched, signal, thread::{self, Thread}};
+
+/// Bitset that matches every waiter.
+pub const BITSET_MATCH_ANY: u32 = u32::MAX;
+
+/// A thread waiting on a futex.
+struct Waiter {
+    thread: Arc<Thread>,
+    /// Only wake ups with a bit in common wake the thread.
+    bitset: u32,
+    /// The futex it waits on, which requeueing changes.
+    key: AtomicUsize,
+    woken: AtomicBool,
+}
+
+type Queues = BTreeMap<usize, VecDeque<Arc<Waiter>>>;
+
+/// Threads waiting on each futex, by physical address.
+static FUTEXES: SpinLock<Queues> = SpinLock::new(BTreeMap::new());
+
+/// The physical address of the futex word at `uaddr` in the calling process,
+/// bringing the page in first. Pages shared copy-on-write are copied, as
+/// the word would otherwise move from under the waiters once written.
+///
+/// This takes a reference on the page, which [`put`] has to be called
+/// for, so that it stays there once the address space is unlocked.
+fn key(uaddr: usize) -> SysResult<usize> {
+    if !uaddr.is_multiple_of(size_of::<u32>()) {
+        return Err(Errno::EINVAL);
+    }
+    let process = current_process().ok_or(Errno::EFAULT)?;
+    let mut space = process.space();
+    let writable = space.area(uaddr).is_some_and(|vma| vma.perms & PTE_WRITE != 0);
+    let (flags, access) = match writable {
+        true => (PTE_READ | PTE_WRITE, Access::Write),
+        false => (PTE_READ, Access::Read),
+    };
+
+    let pa = match space.translate(uaddr, flags) {
+        Some(pa) => pa,
+        None if space.handle_fault(uaddr, access) => space.translate(uaddr, flags).ok_or(Errno::EFAULT)?,
+        None => return Err(Errno::EFAULT),
+    };
+    get(pa);
+    Ok(pa)
+}
+
+/// Takes another reference on the page of `key`.
+fn get(key: usize) {
+    unsafe { PAGE_ALLOCATOR.share((key & !(PAGE_SIZE - 1)) as *const u8) };
+}
+
+/// Drops a reference on the page of `key`, which [`key`] or [`get`] took.
+fn put(key: usize) {
+    unsafe { PAGE_ALLOCATOR.release((key & !(PAGE_SIZE - 1)) as *const u8) };
+}
+
+/// The value of the futex word at physical address `key`, whose page the
+/// caller holds a reference on.
+fn load(key: usize) -> u32 {
+    unsafe { (*(key as *const AtomicU32)).load(Ordering::SeqCst) }
+}
+
+/// Puts the calling thread to sleep on the futex at `uaddr`, as long as it
+/// holds `expected`, until a wake up matching `bitset` or until `deadline`
+/// (see [`sched::now`]). Fails with `EINTR` once the thread is
+/// [`signal::interrupted`].
+pub fn wait(uaddr: usize, expected: u32, bitset: u32, deadline: Option<u64>) -> SysResult<()> {
+    if bitset == 0 {
+        return Err(Errno::EINVAL);
+    }
+    let key = key(uaddr)?;
+    let waiter = Arc::new(Waiter {
+        thread: thread::current(),
+        bitset,
+        key: AtomicUsize::new(key),
+        woken: AtomicBool::new(false),
+    });
+    let result = sleep(&waiter, expected, deadline);
+    // The reference `key` took moved along if the waiter was requeued.
+    put(waiter.key.load(Ordering::Relaxed));
+    result
+}
+
+/// Queues `waiter` on its futex and sleeps, see [`wait`].
+fn sleep(waiter: &Arc<Waiter>, expected: u32, deadline: Option<u64>) -> SysResult<()> {
+    let key = waiter.key.load(Ordering::Relaxed);
+    // Checking the value under the lock makes sure a wake up
+    // right after the caller last looked at it is not missed.
+    let mut futexes = FUTEXES.lock();
+    if load(key) != expected {
+        return Err(Errno::EAGAIN);
+    }
+    futexes.entry(key).or_default().push_back(waiter.clone());
+
+    loop {
+        match deadline {
+            Some(deadline) => sched::prepare_block_until(deadline),
+            None => sched::prepare_block(),
+        }
+        if signal::interrupted() {
+            match deadline {
+                Some(deadline) => sched::cancel_block_until(deadline),
+                None => sched::cancel_block(),
+            }
+            remove(&mut futexes, waiter);
+            return Err(Errno::EINTR);
+        }
+        drop(futexes);
+
+        let timed_out = match deadline {
+            Some(deadline) => sched::block_until(deadline),
+            None => {
+                sched::block();
+                false
+            }
+        };
+
+        futexes = FUTEXES.lock();
+        if waiter.woken.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        if timed_out {
+            remove(&mut futexes, waiter);
+            return Err(Errno::ETIMEDOUT);
+        }
+    }
+}
+
+fn remove(futexes: &mut Queues, waiter: &Arc<Waiter>) {
+    let key = waiter.key.load(Ordering::Relaxed);
+    if let Some(queue) = futexes.get_mut(&key) {
+        queue.retain(|w| !Arc::ptr_eq(w, waiter));
+        if queue.is_empty() {
+            futexes.remove(&key);
+        }
+    }
+}
+
+/// Wakes up to `count` of the threads waiting on `key` with a bit of
+/// `bitset`, oldest first. Returns how many it woke up.
+fn wake_queued(futexes: &mut Queues, key: usize, count: usize, bitset: u32) -> usize {
+    let Some(queue) = futexes.get_mut(&key) else {
+        return 0;
+    };
+    let mut woken = 0;
+    queue.retain(|w| {
+        if woken == count || w.bitset & bitset == 0 {
+            return true;
+        }
+        w.woken.store(true, Ordering::Release);
+        sched::wake(&w.thread);
+        woken += 1;
+        false
+    });
+    if queue.is_empty() {
+        futexes.remove(&key);
+    }
+    woken
+}
+
+/// Wakes up to `count` threads waiting on the futex at `uaddr`
+/// with a bit of `bitset`. Returns how many it woke up.
+pub fn wake(uaddr: usize, count: usize, bitset: u32) -> SysResult<usize> {
+    if bitset == 0 {
+        return Err(Errno::EINVAL);
+    }
+    let key = key(uaddr)?;
+    let woken = wake_queued(&mut FUTEXES.lock(), key, count, bitset);
+    put(key);
+    Ok(woken)
+}
+
+/// Wakes up to `count` threads waiting on the futex at `uaddr`, and moves
+/// up to `requeue` of the others to wait on the one at `uaddr2` instead,
+/// if the futex at `uaddr` holds `expected` (when given). This is how a
+/// condition variable hands its waiters to its mutex without waking them
+/// all up at once. Returns how many threads were woken up or moved.
+pub fn requeue(uaddr: usize, uaddr2: usize, count: usize, requeue: usize, expected: Option<u32>) -> SysResult<usize> {
+    let from = key(uaddr)?;
+    let to = match key(uaddr2) {
+        Ok(to) => to,
+        Err(e) => {
+            put(from);
+            return Err(e);
+        }
+    };
+    let result = requeue_queued(&mut FUTEXES.lock(), from, to, count, requeue, expected);
+    put(from);
+    put(to);
+    result
+}
+
+fn requeue_queued(
+    futexes: &mut Queues,
+    from: usize,
+    to: usize,
+    count: usize,
+    requeue: usize,
+    expected: Option<u32>,
+) -> SysResult<usize> {
+    if expected.is_some_and(|expected| load(from) != expected) {
+        return Err(Errno::EAGAIN);
+    }
+
+    let woken = wake_queued(futexes, from, count, BITSET_MATCH_ANY);
+    if from == to {
+        return Ok(woken);
+    }
+    let Some(mut queue) = futexes.remove(&from) else {
+        return Ok(woken);
+    };
+    let moved: Vec<_> = queue.drain(..requeue.min(queue.len())).collect();
+    if !queue.is_empty() {
+        futexes.insert(from, queue);
+    }
+    for w in &moved {
+        // The waiter's reference goes along, we still hold one on `from`.
+        get(to);
+        put(from);
+        w.key.store(to, Ordering::Relaxed);
+    }
+    let requeued = moved.len();
+    futexes.entry(to).or_default().extend(moved);
+    Ok(woken + requeued)
+}
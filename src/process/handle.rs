@@ -0,0 +1,229 @@
+//! Handles: how a process refers to the kernel objects it holds.
+//!
+//! Every process has a table of the objects it opened, like files, pipes,
+//! channels and shared memory, under small numbers it passes to system
+//! calls, which double as its file descriptors. There are no other names
+//! for them: a process can only use what it was handed, and only in the
+//! ways the [`Rights`] of its handle allow. Forked children get a copy of
+//! the table, referring to the same objects, and the objects held by a
+//! process are let go of as soon as it exits.
+
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::{fmt, ops::BitOr};
+
+use crate::{
+    drivers::Device,
+    fs::File,
+    ipc::{Endpoint, PipeReader, PipeWriter},
+    mem::shm::SharedMemory,
+};
+
+use super::{timer::Timer, Process};
+
+pub type Handle = usize;
+
+/// Handles of the console every process started by the kernel gets, the
+/// way file descriptors 0 to 2 are.
+const CONSOLE_HANDLES: [Handle; 3] = [0, 1, 2];
+
+/// What a handle allows its holder to do with the object behind it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rights(u32);
+
+impl Rights {
+    pub const NONE: Self = Self(0);
+    /// Read from the object, receive from it, or wait on it.
+    pub const READ: Self = Self(1 << 0);
+    /// Write to the object, send to it, or change it.
+    pub const WRITE: Self = Self(1 << 1);
+    /// Make another handle to the object.
+    pub const DUPLICATE: Self = Self(1 << 2);
+    /// Send the handle to another process over a channel.
+    pub const TRANSFER: Self = Self(1 << 3);
+    pub const ALL: Self = Self(0b1111);
+
+    /// The rights in `bits`, unless it has bits that are none.
+    pub const fn from_bits(bits: u32) -> Option<Self> {
+        if bits & !Self::ALL.0 != 0 {
+            return None;
+        }
+        Some(Self(bits))
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Whether these rights include every one of `other`.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Rights {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl fmt::Display for Rights {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (right, c) in [(Self::READ, 'r'), (Self::WRITE, 'w'), (Self::DUPLICATE, 'd'), (Self::TRANSFER, 't')] {
+            write!(f, "{}", if self.contains(right) { c } else { '-' })?;
+        }
+        Ok(())
+    }
+}
+
+/// A kernel object a process may hold.
+#[derive(Clone)]
+pub enum Object {
+    File(Arc<File>),
+    PipeReader(PipeReader),
+    PipeWriter(PipeWriter),
+    Channel(Endpoint),
+    SharedMemory(Arc<SharedMemory>),
+    Process(Arc<Process>),
+    Timer(Arc<Timer>),
+    Device(Device),
+}
+
+impl Object {
+    /// The rights a new handle to the object comes with, which are those
+    /// that make sense for it.
+    pub fn default_rights(&self) -> Rights {
+        let moves = Rights::DUPLICATE | Rights::TRANSFER;
+        match self {
+            Self::PipeReader(_) => Rights::READ | moves,
+            Self::PipeWriter(_) => Rights::WRITE | moves,
+            _ => Rights::ALL,
+        }
+    }
+
+    /// What the object is, for listings.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::File(_) => "file",
+            Self::PipeReader(_) => "pipe (read)",
+            Self::PipeWriter(_) => "pipe (write)",
+            Self::Channel(_) => "channel",
+            Self::SharedMemory(_) => "shm",
+            Self::Process(_) => "process",
+            Self::Timer(_) => "timer",
+            Self::Device(_) => "device",
+        }
+    }
+}
+
+/// An object, and what the handle it is held by allows.
+#[derive(Clone)]
+pub struct Capability {
+    pub object: Object,
+    pub rights: Rights,
+}
+
+impl Capability {
+    /// A capability for `object` with its default rights.
+    pub fn new(object: Object) -> Self {
+        let rights = object.default_rights();
+        Self { object, rights }
+    }
+}
+
+#[derive(Clone)]
+pub struct HandleTable {
+    capabilities: BTreeMap<Handle, Capability>,
+    /// How many handles the table may hold.
+    limit: usize,
+}
+
+impl HandleTable {
+    pub const fn new() -> Self {
+        Self { capabilities: BTreeMap::new(), limit: usize::MAX }
+    }
+
+    /// A table holding just the console, as standard input and outputs.
+    pub fn with_console() -> Self {
+        let mut table = Self::new();
+        for handle in CONSOLE_HANDLES {
+            table.capabilities.insert(handle, Capability::new(Object::Device(Device::Console)));
+        }
+        table
+    }
+
+    /// Adds `capability` under the lowest free handle, which it returns,
+    /// unless the table is full.
+    pub fn insert(&mut self, capability: Capability) -> Option<Handle> {
+        (self.len() < self.limit).then(|| self.add(capability))
+    }
+
+    /// Adds `capabilities` under the lowest free handles, which it returns,
+    /// or gives them back if they do not all fit.
+    pub fn insert_all(&mut self, capabilities: Vec<Capability>) -> Result<Vec<Handle>, Vec<Capability>> {
+        if self.limit.saturating_sub(self.len()) < capabilities.len() {
+            return Err(capabilities);
+        }
+        Ok(capabilities.into_iter().map(|c| self.add(c)).collect())
+    }
+
+    /// Puts `capability` under `handle`, returning what was there, or gives
+    /// it back if that would take the table past its limit.
+    pub fn insert_at(&mut self, handle: Handle, capability: Capability) -> Result<Option<Capability>, Capability> {
+        if !self.capabilities.contains_key(&handle) && self.len() >= self.limit {
+            return Err(capability);
+        }
+        Ok(self.capabilities.insert(handle, capability))
+    }
+
+    fn add(&mut self, capability: Capability) -> Handle {
+        let mut handle = 0;
+        for &h in self.capabilities.keys() {
+            if h != handle {
+                break;
+            }
+            handle += 1;
+        }
+        self.capabilities.insert(handle, capability);
+        handle
+    }
+
+    pub fn get(&self, handle: Handle) -> Option<&Capability> {
+        self.capabilities.get(&handle)
+    }
+
+    pub fn remove(&mut self, handle: Handle) -> Option<Capability> {
+        self.capabilities.remove(&handle)
+    }
+
+    pub fn len(&self) -> usize {
+        self.capabilities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.capabilities.is_empty()
+    }
+
+    /// Limits the table to `limit` handles. Those it holds already stay,
+    /// but no more are added past the limit.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+}
+
+impl Default for HandleTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// One line per handle, with its rights and what it refers to.
+impl fmt::Display for HandleTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (handle, cap) in &self.capabilities {
+            writeln!(f, "{:>5} {} {}", handle, cap.rights, cap.object.kind())?;
+        }
+        Ok(())
+    }
+}
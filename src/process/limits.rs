@@ -0,0 +1,106 @@
+//! Resource limits, and accounting of what processes use.
+//!
+//! Every process has a soft and a hard limit on each resource, the way
+//! `setrlimit` has them, which forked children inherit. The soft limit is
+//! the one enforced, and can be raised up to the hard one, which can only
+//! be lowered. Linux numbers the resources, and of those these are enforced:
+//!
+//! - [`RLIMIT_RSS`], bytes of user memory in place, counted by the address
+//!   space as it maps pages (see [`AddressSpace`](crate::mem::space::AddressSpace)).
+//! - [`RLIMIT_NPROC`], threads of the process, as there are no users to
+//!   count processes of.
+//! - [`RLIMIT_NOFILE`], handles the process holds.
+//! - [`RLIMIT_CPU`], seconds spent running. Past the soft limit the process
+//!   gets a `SIGXCPU` every second, and at the hard one a `SIGKILL`.
+
+use crate::{
+    sync::spinlock::SpinLock,
+    syscall::{errno::Errno, SysResult},
+};
+
+pub type Resource = usize;
+
+pub const RLIMIT_CPU: Resource = 0;
+pub const RLIMIT_RSS: Resource = 5;
+pub const RLIMIT_NPROC: Resource = 6;
+pub const RLIMIT_NOFILE: Resource = 7;
+
+/// How many resources there are, most of which are not enforced.
+pub const RLIM_NLIMITS: usize = 16;
+
+pub const RLIM_INFINITY: u64 = u64::MAX;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rlimit {
+    /// The soft limit.
+    pub cur: u64,
+    /// The hard limit.
+    pub max: u64,
+}
+
+impl Rlimit {
+    pub const INFINITE: Self = Self { cur: RLIM_INFINITY, max: RLIM_INFINITY };
+
+    /// The soft limit as a count.
+    pub fn soft(&self) -> usize {
+        usize::try_from(self.cur).unwrap_or(usize::MAX)
+    }
+}
+
+pub struct Limits {
+    limits: SpinLock<[Rlimit; RLIM_NLIMITS]>,
+}
+
+impl Limits {
+    /// No limits at all.
+    pub const fn new() -> Self {
+        Self { limits: SpinLock::new([Rlimit::INFINITE; RLIM_NLIMITS]) }
+    }
+
+    pub fn get(&self, resource: Resource) -> SysResult<Rlimit> {
+        self.limits.lock().get(resource).copied().ok_or(Errno::EINVAL)
+    }
+
+    /// Changes the limits on `resource`, failing with `EINVAL` if the soft
+    /// one is above the hard one, and `EPERM` if the hard one would go up.
+    pub fn set(&self, resource: Resource, limit: Rlimit) -> SysResult<()> {
+        let mut limits = self.limits.lock();
+        let old = limits.get_mut(resource).ok_or(Errno::EINVAL)?;
+        if limit.cur > limit.max {
+            return Err(Errno::EINVAL);
+        }
+        if limit.max > old.max {
+            return Err(Errno::EPERM);
+        }
+        *old = limit;
+        Ok(())
+    }
+
+    /// Takes over the limits of `parent`, for a child it forked.
+    pub fn inherit(&self, parent: &Limits) {
+        let limits = *parent.limits.lock();
+        *self.limits.lock() = limits;
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What a process uses of each resource.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Usage {
+    /// Pages of user memory in place.
+    pub pages: usize,
+    /// Most pages of user memory there ever were in place at once.
+    pub peak_pages: usize,
+    pub threads: usize,
+    pub handles: usize,
+    /// Time spent running, in timer ticks.
+    pub cpu_time: u64,
+    /// Time the children it reaped spent running, in timer ticks.
+    pub children_cpu_time: u64,
+}
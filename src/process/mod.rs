@@ -1 +1,631 @@
+//! Threads of execution, and the processes they belong to.
+//!
+//! Processes follow the Unix lifecycle: a process is created by [`fork`]ing
+//! another (or by the kernel, see [`elf::spawn`] and [`spawn_init`]), may
+//! replace its program with [`exec`], and once its last thread is gone it
+//! stays around as a zombie holding its exit status, until its parent
+//! collects it with [`wait`], having been told with a `SIGCHLD` (see
+//! [`signal`]). Orphans are adopted by init, pid 1, and processes started
+//! by the kernel itself have no parent and are reaped as soon as they exit.
 
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering},
+};
+
+use crate::{
+    cpu::trap::TrapFrame,
+    debug,
+    fs::Dentry,
+    info,
+    mem::{
+        pages::PAGE_SIZE,
+        space::{self, Access, AddressSpace, USER_END, USER_STACK_SIZE, USER_STACK_TOP, USER_START},
+        table,
+        vma::Vma,
+    },
+    sync::{
+        spinlock::{Guard, SpinLock},
+        WaitQueue,
+    },
+    syscall::SysResult,
+    util::error::WalnutError,
+    warn,
+};
+
+pub mod class;
+pub mod elf;
+pub mod futex;
+pub mod handle;
+pub mod limits;
+pub mod sched;
+pub mod signal;
+pub mod thread;
+pub mod timer;
+
+pub use thread::{exit, spawn, yield_now, JoinHandle};
+
+use self::{
+    handle::HandleTable,
+    limits::{Limits, Resource, Rlimit, Usage, RLIMIT_CPU, RLIMIT_NOFILE, RLIMIT_NPROC, RLIMIT_RSS, RLIM_INFINITY},
+    signal::{ProcessSignals, SigSet, Signal},
+};
+
+pub type Pid = usize;
+
+/// The pid of init, which adopts orphaned processes.
+pub const INIT_PID: Pid = 1;
+
+/// Parent of processes nobody waits for.
+const NO_PARENT: Pid = 0;
+
+static NEXT_PID: AtomicUsize = AtomicUsize::new(INIT_PID + 1);
+
+/// Every process that has not been reaped yet.
+static PROCESSES: SpinLock<BTreeMap<Pid, Arc<Process>>> = SpinLock::new(BTreeMap::new());
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    /// At least one of its threads is still around.
+    Running,
+    /// All of its threads exited, waiting for its parent to collect its exit status.
+    Zombie,
+}
+
+impl ProcessState {
+    fn from_bits(bits: u8) -> Self {
+        match bits {
+            0 => Self::Running,
+            _ => Self::Zombie,
+        }
+    }
+}
+
+/// How a process ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// It exited by itself with this code.
+    Exited(i32),
+    /// A signal ended it, which may have asked for a core dump.
+    Signaled { signal: Signal, core: bool },
+}
+
+impl ExitStatus {
+    /// The status the way `wait4` reports it.
+    pub fn wait_status(self) -> i32 {
+        match self {
+            Self::Exited(code) => (code & 0xff) << 8,
+            Self::Signaled { signal, core } => signal as i32 | if core { 0x80 } else { 0 },
+        }
+    }
+}
+
+/// A program running in U-mode, in its own address space.
+pub struct Process {
+    pid: Pid,
+    /// Pid of the process to tell when this one exits.
+    parent: AtomicUsize,
+    name: SpinLock<String>,
+    /// `satp` value for the address space, which never moves.
+    satp: usize,
+    space: SpinLock<AddressSpace>,
+    /// The heap, from the end of the program to the current break.
+    heap: SpinLock<Range<usize>>,
+    state: AtomicU8,
+    /// Threads that have not exited yet.
+    threads: AtomicUsize,
+    /// Set once the process is done, with why.
+    exit_status: SpinLock<Option<ExitStatus>>,
+    signals: ProcessSignals,
+    /// Threads waiting for a child to exit.
+    child_exited: WaitQueue,
+    handles: SpinLock<HandleTable>,
+    /// The working directory, `None` for the root.
+    cwd: SpinLock<Option<Arc<Dentry>>>,
+    limits: Limits,
+    /// Time spent running by all threads, in timer ticks.
+    cpu_time: AtomicU64,
+    /// Time spent running by the children that were reaped, in timer ticks.
+    children_cpu_time: AtomicU64,
+    /// The last second of CPU time `SIGXCPU` was sent for.
+    xcpu_second: AtomicU64,
+}
+
+impl Process {
+    /// Creates a process with an empty address space, except for its stack,
+    /// holding nothing but the console. It is a child of the calling
+    /// process, if there is one.
+    pub fn new(name: &str) -> crate::Result<Arc<Self>> {
+        let parent = current_process().map_or(NO_PARENT, |p| p.pid);
+        Self::with_pid(NEXT_PID.fetch_add(1, Ordering::Relaxed), name, parent)
+    }
+
+    fn with_pid(pid: Pid, name: &str, parent: Pid) -> crate::Result<Arc<Self>> {
+        let mut space = AddressSpace::new()?;
+        init_space(&mut space)?;
+        let process = Self::create(pid, name, parent, space);
+        *process.handles.lock() = HandleTable::with_console();
+        Ok(process)
+    }
+
+    fn create(pid: Pid, name: &str, parent: Pid, space: AddressSpace) -> Arc<Self> {
+        Arc::new(Self {
+            pid,
+            parent: AtomicUsize::new(parent),
+            name: SpinLock::new(String::from(name)),
+            satp: space.satp(),
+            space: SpinLock::new(space),
+            heap: SpinLock::new(0..0),
+            state: AtomicU8::new(ProcessState::Running as u8),
+            threads: AtomicUsize::new(0),
+            exit_status: SpinLock::new(None),
+            signals: ProcessSignals::new(),
+            child_exited: WaitQueue::new(),
+            handles: SpinLock::new(HandleTable::new()),
+            cwd: SpinLock::new(None),
+            limits: Limits::new(),
+            cpu_time: AtomicU64::new(0),
+            children_cpu_time: AtomicU64::new(0),
+            xcpu_second: AtomicU64::new(u64::MAX),
+        })
+    }
+
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    /// Pid of the parent process, 0 if there is none.
+    pub fn parent(&self) -> Pid {
+        self.parent.load(Ordering::Acquire)
+    }
+
+    pub fn name(&self) -> String {
+        self.name.lock().clone()
+    }
+
+    pub fn satp(&self) -> usize {
+        self.satp
+    }
+
+    pub fn space(&self) -> Guard<'_, AddressSpace> {
+        self.space.lock()
+    }
+
+    pub fn state(&self) -> ProcessState {
+        ProcessState::from_bits(self.state.load(Ordering::Acquire))
+    }
+
+    /// How many of the process' threads have not exited yet.
+    pub fn threads(&self) -> usize {
+        self.threads.load(Ordering::Acquire)
+    }
+
+    /// The heap, from its start to the current break.
+    pub fn heap(&self) -> Range<usize> {
+        self.heap.lock().clone()
+    }
+
+    /// Where the heap starts, normally right after the program.
+    pub fn set_heap_start(&self, start: usize) {
+        *self.heap.lock() = start..start;
+    }
+
+    /// Moves the break to `brk`, as long as it stays above the start of
+    /// the heap and clear of other mappings. Returns the new break.
+    pub fn set_break(&self, brk: usize) -> crate::Result<usize> {
+        let mut heap = self.heap.lock();
+        if brk < heap.start {
+            return Err(WalnutError::new("Break below the start of the heap"));
+        }
+
+        let old_end = heap.end.next_multiple_of(PAGE_SIZE);
+        let new_end = brk.next_multiple_of(PAGE_SIZE);
+        let mut space = self.space.lock();
+        if new_end > old_end {
+            let rw = space::user_flags(true, true, false);
+            space.map_area(Vma::anonymous(old_end, new_end, rw).with_label("[heap]"))?;
+        } else if new_end < old_end {
+            space.free(new_end, old_end - new_end);
+            drop(space);
+            table::shootdown(new_end, old_end - new_end);
+        }
+        heap.end = brk;
+        Ok(brk)
+    }
+
+    /// The memory map of the process, the way `/proc/<pid>/maps` shows it.
+    pub fn memory_map(&self) -> String {
+        self.space.lock().memory_map()
+    }
+
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        *self.exit_status.lock()
+    }
+
+    /// Ends the process with `status`, unless it is already ending.
+    /// Its threads exit the next time they return to U-mode.
+    pub fn set_exit_status(&self, status: ExitStatus) {
+        let mut exit_status = self.exit_status.lock();
+        if exit_status.is_some() {
+            return;
+        }
+        *exit_status = Some(status);
+        drop(exit_status);
+        // Threads blocked in the kernel have to notice.
+        signal::interrupt(self);
+    }
+
+    /// Whether the process was told to exit, and its threads should follow.
+    pub fn is_exiting(&self) -> bool {
+        self.exit_status.lock().is_some()
+    }
+
+    pub fn signals(&self) -> &ProcessSignals {
+        &self.signals
+    }
+
+    /// The kernel objects the process holds.
+    pub fn handles(&self) -> Guard<'_, HandleTable> {
+        self.handles.lock()
+    }
+
+    /// The directory relative paths start from, `None` for the root.
+    pub fn cwd(&self) -> Option<Arc<Dentry>> {
+        self.cwd.lock().clone()
+    }
+
+    pub fn set_cwd(&self, dir: Arc<Dentry>) {
+        *self.cwd.lock() = Some(dir);
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Changes the limits on `resource`, see [`Limits::set`].
+    pub fn set_limit(&self, resource: Resource, limit: Rlimit) -> SysResult<()> {
+        self.limits.set(resource, limit)?;
+        match resource {
+            RLIMIT_RSS => self.space().set_page_limit(limit.soft() / PAGE_SIZE),
+            RLIMIT_NOFILE => self.handles().set_limit(limit.soft()),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// What the process uses of each resource.
+    pub fn usage(&self) -> Usage {
+        let (pages, peak_pages) = {
+            let space = self.space();
+            (space.resident_pages(), space.peak_pages())
+        };
+        Usage {
+            pages,
+            peak_pages,
+            threads: self.threads(),
+            handles: self.handles().len(),
+            cpu_time: self.cpu_time.load(Ordering::Relaxed),
+            children_cpu_time: self.children_cpu_time.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Adds `ticks` to the time the process spent running.
+    fn charge(&self, ticks: u64) {
+        self.cpu_time.fetch_add(ticks, Ordering::Relaxed);
+    }
+
+    /// Signals the process if it ran for longer than its limits allow.
+    pub fn check_cpu_limit(&self) {
+        let limit = self.limits.get(RLIMIT_CPU).unwrap_or(Rlimit::INFINITE);
+        let seconds = self.cpu_time.load(Ordering::Relaxed) / sched::TIMEBASE_FREQ;
+        if seconds >= limit.max {
+            signal::send(self, signal::SIGKILL);
+        } else if seconds >= limit.cur && self.xcpu_second.swap(seconds, Ordering::Relaxed) != seconds {
+            signal::send(self, signal::SIGXCPU);
+        }
+    }
+
+    /// Starts a thread of this process at `pc` in U-mode,
+    /// with `sp` as its stack pointer and `arg` in `a0`.
+    pub fn spawn_thread(self: &Arc<Self>, pc: usize, sp: usize, arg: usize) -> crate::Result<thread::Tid> {
+        let mut frame = TrapFrame { pc, ..Default::default() };
+        frame.regs[TrapFrame::SP] = sp;
+        frame.set_arg(0, arg);
+        self.spawn_thread_with(frame, 0)
+    }
+
+    /// Starts a thread of this process in U-mode with the registers in `frame`,
+    /// blocking the signals in `blocked`, unless it has as many threads as
+    /// its limit allows. A process enters the process table with its first
+    /// thread.
+    fn spawn_thread_with(self: &Arc<Self>, frame: TrapFrame, blocked: SigSet) -> crate::Result<thread::Tid> {
+        let limit = self.limits.get(RLIMIT_NPROC).map_or(usize::MAX, |l| l.soft());
+        if self.threads() >= limit {
+            return Err(WalnutError::new("Over the limit of threads"));
+        }
+        let thread = Arc::new(thread::Thread::new_user(self.clone(), frame)?);
+        thread.signals().set_blocked(blocked);
+        let tid = thread.tid();
+        if self.threads.fetch_add(1, Ordering::AcqRel) == 0 {
+            PROCESSES.lock().insert(self.pid, self.clone());
+        }
+        thread::register(&thread);
+        sched::enqueue(thread);
+        Ok(tid)
+    }
+
+    /// Called by every thread of the process on its way out,
+    /// the last one turns the process into a zombie.
+    pub(super) fn thread_exited(&self) {
+        if self.threads.fetch_sub(1, Ordering::AcqRel) != 1 {
+            return;
+        }
+        if self.pid == INIT_PID {
+            panic!("Init exited with {:?}", self.exit_status());
+        }
+
+        self.set_exit_status(ExitStatus::Exited(0));
+        // Let go of pipes and channels now, not once reaped,
+        // so that whoever is on the other side knows right away.
+        let handles = core::mem::take(&mut *self.handles.lock());
+        drop(handles);
+        let mut processes = PROCESSES.lock();
+        self.state.store(ProcessState::Zombie as u8, Ordering::Release);
+
+        // Hand our children to init, or let them go if it is not around.
+        let adopter = if processes.contains_key(&INIT_PID) { INIT_PID } else { NO_PARENT };
+        let mut unwanted: Vec<Pid> = processes
+            .values()
+            .filter(|p| p.parent() == self.pid)
+            .inspect(|p| p.parent.store(adopter, Ordering::Release))
+            .filter(|p| adopter == NO_PARENT && p.state() == ProcessState::Zombie)
+            .map(|p| p.pid)
+            .collect();
+
+        if self.parent() == NO_PARENT {
+            unwanted.push(self.pid);
+        }
+        for pid in unwanted {
+            processes.remove(&pid);
+        }
+
+        let parent = processes.get(&self.parent()).cloned();
+        // Init may have just adopted zombies to reap.
+        let init = processes.get(&adopter).cloned();
+        drop(processes);
+        if let Some(parent) = parent {
+            signal::send(&parent, signal::SIGCHLD);
+            parent.child_exited.wake_all();
+        }
+        if let Some(init) = init {
+            init.child_exited.wake_all();
+        }
+    }
+}
+
+/// Maps what every program starts with: its initial stack, and the code
+/// signal handlers return through.
+fn init_space(space: &mut AddressSpace) -> crate::Result<()> {
+    let rw = space::user_flags(true, true, false);
+    let stack = Vma::anonymous(USER_STACK_TOP - USER_STACK_SIZE, USER_STACK_TOP, rw);
+    space.alloc_area(stack.growing_down().with_label("[stack]"))?;
+    signal::map_sigpage(space)
+}
+
+/// Starts the executable in `data` as init, pid 1.
+pub fn spawn_init(data: &[u8], argv: &[&str], envp: &[&str]) -> crate::Result<Arc<Process>> {
+    if PROCESSES.lock().contains_key(&INIT_PID) {
+        return Err(WalnutError::new("Init is already running"));
+    }
+    let elf = elf::Elf::parse(data)?;
+    let process = Process::with_pid(INIT_PID, "init", NO_PARENT)?;
+    let sp = elf::load_image(&process, &elf, argv, envp)?;
+    process.spawn_thread(elf.header.entry, sp, 0)?;
+    Ok(process)
+}
+
+/// The process with pid `pid`, unless it was reaped.
+pub fn find(pid: Pid) -> Option<Arc<Process>> {
+    PROCESSES.lock().get(&pid).cloned()
+}
+
+/// The process the calling thread belongs to, if any.
+pub fn current_process() -> Option<Arc<Process>> {
+    thread::current().process().cloned()
+}
+
+/// Creates a child of the calling process, with a copy of its memory and
+/// a single thread that returns from the same trap the calling thread is
+/// in, but with 0 in `a0`.
+pub fn fork() -> crate::Result<Arc<Process>> {
+    let thread = thread::current();
+    let parent = thread.process().ok_or(WalnutError::new("Only user processes can fork"))?;
+
+    let space = parent.space().fork()?;
+    table::shootdown(USER_START, USER_END - USER_START);
+    let child = Process::create(NEXT_PID.fetch_add(1, Ordering::Relaxed), &parent.name(), parent.pid, space);
+    *child.heap.lock() = parent.heap();
+    child.signals.inherit(&parent.signals);
+    child.limits.inherit(&parent.limits);
+    *child.handles.lock() = parent.handles().clone();
+    *child.cwd.lock() = parent.cwd();
+
+    let mut frame = unsafe { *thread.user_frame() };
+    frame.set_arg(0, 0);
+    child.spawn_thread_with(frame, thread.signals().blocked())?;
+    Ok(child)
+}
+
+/// Replaces the program of the calling process with the executable in
+/// `data`. The calling thread, which has to be the only one left in the
+/// process, starts it from the beginning when it returns to U-mode.
+///
+/// Failures past the point where the old program is gone end the process,
+/// and the calling thread exits on its way back to U-mode.
+pub fn exec(name: &str, data: &[u8], argv: &[&str], envp: &[&str]) -> crate::Result<()> {
+    let thread = thread::current();
+    let process = thread.process().ok_or(WalnutError::new("Only user processes can exec"))?.clone();
+    if process.threads() != 1 {
+        return Err(WalnutError::new("Can not exec with other threads running"));
+    }
+    let elf = elf::Elf::parse(data)?;
+
+    process.space().clear();
+    table::shootdown(USER_START, USER_END - USER_START);
+
+    let loaded = init_space(&mut process.space()).and_then(|_| elf::load_image(&process, &elf, argv, envp));
+    let sp = match loaded {
+        Ok(sp) => sp,
+        Err(e) => {
+            warn!("Killing process {} ({}): exec failed: {}", process.pid(), process.name(), e);
+            process.set_exit_status(ExitStatus::Signaled { signal: signal::SIGKILL, core: false });
+            return Err(e);
+        }
+    };
+    *process.name.lock() = String::from(name);
+    process.signals.reset_handlers();
+
+    let frame = unsafe { &mut *thread.user_frame() };
+    *frame = TrapFrame { pc: elf.header.entry, sstatus: frame.sstatus, ..Default::default() };
+    frame.regs[TrapFrame::SP] = sp;
+    Ok(())
+}
+
+/// What [`wait`] found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitStatus {
+    /// The child `pid` ended with `status`, and is gone now.
+    Exited { pid: Pid, status: ExitStatus },
+    /// None of the children asked for exited yet.
+    Running,
+    /// The calling process has no such children.
+    NoChild,
+}
+
+/// Reaps a child of the calling process that exited, `pid` or any of them,
+/// waiting for one to exit unless `nohang` is set. Fails with `EINTR` if
+/// the thread is interrupted while waiting.
+pub fn wait(pid: Option<Pid>, nohang: bool) -> SysResult<WaitStatus> {
+    let Some(me) = current_process() else {
+        return Ok(WaitStatus::NoChild);
+    };
+    if nohang {
+        return Ok(try_wait(me.pid, pid));
+    }
+    me.child_exited.wait_interruptible(|| match try_wait(me.pid, pid) {
+        WaitStatus::Running => None,
+        status => Some(status),
+    })
+}
+
+/// Reaps a child of process `parent` that exited, `pid` or any of them.
+fn try_wait(parent: Pid, pid: Option<Pid>) -> WaitStatus {
+    let mut processes = PROCESSES.lock();
+    let mut children = processes
+        .values()
+        .filter(|p| p.parent() == parent && pid.is_none_or(|pid| p.pid == pid))
+        .peekable();
+    if children.peek().is_none() {
+        return WaitStatus::NoChild;
+    }
+    if let Some(zombie) = children.find(|p| p.state() == ProcessState::Zombie).map(|p| p.pid) {
+        let child = processes.remove(&zombie).expect("Zombie vanished");
+        if let Some(parent) = processes.get(&parent) {
+            let ticks = child.cpu_time.load(Ordering::Relaxed) + child.children_cpu_time.load(Ordering::Relaxed);
+            parent.children_cpu_time.fetch_add(ticks, Ordering::Relaxed);
+        }
+        let status = child.exit_status().unwrap_or(ExitStatus::Exited(0));
+        return WaitStatus::Exited { pid: zombie, status };
+    }
+    WaitStatus::Running
+}
+
+/// Logs every process in the process table.
+pub fn log_table() {
+    let processes: Vec<_> = PROCESSES.lock().values().cloned().collect();
+    info!("Processes:");
+    info!("  {:>5} {:>5} {:>8} {:>7} {:>7}  NAME", "PID", "PPID", "STATE", "THREADS", "HANDLES");
+    for p in processes {
+        info!(
+            "  {:>5} {:>5} {:>8} {:>7} {:>7}  {}",
+            p.pid,
+            p.parent(),
+            format!("{:?}", p.state()),
+            p.threads(),
+            p.handles().len(),
+            p.name()
+        );
+    }
+}
+
+/// `value` out of `limit`, unless there is none. Limits are divided by `unit`.
+fn usage_of(value: u64, limit: Rlimit, unit: u64) -> String {
+    match limit.cur {
+        RLIM_INFINITY => format!("{}", value),
+        cur => format!("{}/{}", value, cur / unit),
+    }
+}
+
+/// Logs what every process uses of the resources that can be limited,
+/// and its limits, as asked for from the console with `^T`.
+pub fn log_usage() {
+    let processes: Vec<_> = PROCESSES.lock().values().cloned().collect();
+    info!("Resource usage:");
+    info!("  {:>5} {:>15} {:>9} {:>9} {:>15}  NAME", "PID", "PAGES", "THREADS", "HANDLES", "CPU (ms)");
+    for p in processes {
+        let usage = p.usage();
+        let limit = |resource| p.limits.get(resource).unwrap_or(Rlimit::INFINITE);
+        let cpu_ms = usage.cpu_time * 1000 / sched::TIMEBASE_FREQ;
+        let cpu_limit = Rlimit { cur: limit(RLIMIT_CPU).cur.saturating_mul(1000), ..limit(RLIMIT_CPU) };
+        info!(
+            "  {:>5} {:>15} {:>9} {:>9} {:>15}  {}",
+            p.pid,
+            usage_of(usage.pages as u64, limit(RLIMIT_RSS), PAGE_SIZE as u64),
+            usage_of(usage.threads as u64, limit(RLIMIT_NPROC), 1),
+            usage_of(usage.handles as u64, limit(RLIMIT_NOFILE), 1),
+            usage_of(cpu_ms, cpu_limit, 1),
+            p.name()
+        );
+    }
+}
+
+/// Tries to resolve a page fault of the calling thread on user memory,
+/// see [`AddressSpace::handle_fault`].
+pub fn handle_page_fault(va: usize, access: Access) -> bool {
+    current_process().is_some_and(|p| p.space().handle_fault(va, access))
+}
+
+/// Ends the calling thread's process after it did something it should
+/// not have, e.g. access memory it does not own.
+pub fn kill_current(reason: core::fmt::Arguments) -> ! {
+    // Nothing may be left on this stack that keeps the process alive.
+    if let Some(process) = current_process() {
+        warn!("Killing process {} ({}): {}", process.pid(), process.name(), reason);
+        debug!("Memory map of process {}:\n{}", process.pid(), process.memory_map());
+        debug!("Handles of process {}:\n{}", process.pid(), *process.handles());
+        process.set_exit_status(ExitStatus::Signaled { signal: signal::SIGKILL, core: false });
+    }
+    exit();
+}
+
+/// Ends the calling thread's process with `code`, as asked by the process
+/// itself. Its other threads follow the next time they trap.
+pub fn exit_current(code: i32) -> ! {
+    if let Some(process) = current_process() {
+        process.set_exit_status(ExitStatus::Exited(code));
+    }
+    exit();
+}
+
+/// Ends the calling thread, and with it its process if it was the last
+/// one, in which case `code` is the exit code.
+pub fn exit_thread(code: i32) -> ! {
+    if let Some(process) = current_process() {
+        if process.threads() == 1 {
+            process.set_exit_status(ExitStatus::Exited(code));
+        }
+    }
+    exit();
+}
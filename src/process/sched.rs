@@ -0,0 +1,444 @@
+//! Preemptive scheduling of kernel threads.
+//!
+//! Every hart has its own run queue, split by scheduling class (see
+//! [`super::class`]). A hart whose queue is empty steals work from the
+//! others, and when there is nothing to steal it waits in `wfi` in its
+//! idle thread. The timer interrupt gives the scheduler a chance to
+//! preempt the running thread at the end of each [`TIME_SLICE`].
+
+use alloc::{collections::BTreeMap, sync::Arc};
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use crate::{
+    cpu::{self, csr::ControlStatusRegister, hart, util::my_hart, MAX_HARTS, SSTATUS_SUM},
+    info,
+    mem::table,
+    sbi,
+    sync::spinlock::SpinLock,
+    syscall::{errno::Errno, SysResult},
+};
+
+use super::{
+    class::RunQueue,
+    signal,
+    thread::{self, Context, Thread, ThreadState, Tid},
+};
+
+/// Frequency of the `time` CSR, as set up by QEMU's virt machine.
+pub const TIMEBASE_FREQ: u64 = 10_000_000;
+
+/// How long a thread runs before it is preempted, in timer ticks.
+pub const TIME_SLICE: u64 = TIMEBASE_FREQ / 100;
+
+/// How often scheduler statistics are logged, in timer ticks.
+const STATS_INTERVAL: u64 = TIMEBASE_FREQ * 10;
+
+extern "C" {
+    fn switch_to(old: *mut Context, new: *const Context);
+}
+
+/// Scheduler state of a single hart.
+///
+/// Apart from the run queue, this is only ever touched by the hart it
+/// belongs to, with interrupts disabled.
+struct HartSched {
+    queue: SpinLock<RunQueue>,
+    current: UnsafeCell<Option<Arc<Thread>>>,
+    idle: UnsafeCell<Option<Arc<Thread>>>,
+    /// The thread we just switched away from, which can only be
+    /// queued again (or freed) once we are off its stack.
+    previous: UnsafeCell<Option<Arc<Thread>>>,
+    switches: AtomicUsize,
+    /// Set when a thread that should preempt the current one was woken up.
+    need_resched: AtomicBool,
+}
+
+unsafe impl Sync for HartSched {}
+
+static HARTS: [HartSched; MAX_HARTS] = [const {
+    HartSched {
+        queue: SpinLock::new(RunQueue::new()),
+        current: UnsafeCell::new(None),
+        idle: UnsafeCell::new(None),
+        previous: UnsafeCell::new(None),
+        switches: AtomicUsize::new(0),
+        need_resched: AtomicBool::new(false),
+    }
+}; MAX_HARTS];
+
+/// Threads sleeping until a given time, by wake up time.
+static SLEEPERS: SpinLock<BTreeMap<(u64, Tid), Arc<Thread>>> = SpinLock::new(BTreeMap::new());
+
+/// Harts that run the scheduler, one bit per hart.
+static ONLINE_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Harts waiting in `wfi` for something to run, one bit per hart.
+static IDLE_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+fn this_hart() -> &'static HartSched {
+    &HARTS[unsafe { my_hart() }]
+}
+
+pub fn now() -> u64 {
+    ControlStatusRegister::Time.read() as u64
+}
+
+/// Turns the calling hart's current context into its idle thread,
+/// and starts preempting whatever it runs from now on.
+/// `stack_bottom` is the bottom of the stack it runs on.
+pub fn init_hart(stack_bottom: usize) {
+    let idle = Arc::new(Thread::idle(stack_bottom));
+    idle.switched_in(now());
+    thread::register(&idle);
+
+    let sched = this_hart();
+    unsafe {
+        *sched.idle.get() = Some(idle.clone());
+        *sched.current.get() = Some(idle);
+    }
+    ONLINE_HARTS.fetch_or(1 << unsafe { my_hart() }, Ordering::AcqRel);
+
+    arm_timer();
+}
+
+/// The thread running on the calling hart.
+pub fn current() -> Arc<Thread> {
+    let interrupts = cpu::disable_interrupts();
+    let current = unsafe { (*this_hart().current.get()).clone() };
+    cpu::restore_interrupts(interrupts);
+    current.expect("Threads are not set up on this hart")
+}
+
+/// Makes `thread` runnable on the calling hart, other harts may steal it.
+pub fn enqueue(thread: Arc<Thread>) {
+    thread.set_state(ThreadState::Runnable);
+    let sched = this_hart();
+    let rank = thread.entity().rank();
+    sched.queue.lock().enqueue(thread, now());
+
+    let current = current();
+    if current.is_idle() || rank > current.entity().rank() {
+        sched.need_resched.store(true, Ordering::Relaxed);
+    }
+    kick_idle_hart();
+}
+
+/// Wakes up a thread that [`block`]ed.
+pub fn wake(thread: &Arc<Thread>) {
+    let _guard = thread.wake_lock.lock();
+    if thread.state() != ThreadState::Blocked {
+        return;
+    }
+    if thread.on_cpu.load(Ordering::Acquire) {
+        // Still on its way out of `schedule`, `finish_switch`
+        // will queue it once it is off its stack.
+        thread.set_state(ThreadState::Runnable);
+    } else {
+        enqueue(thread.clone());
+    }
+}
+
+/// Puts the calling thread to sleep until someone calls [`wake`] on it.
+///
+/// Whatever it waits for must be published (e.g. by adding the thread to
+/// a wait list) before calling this, under a lock `wake` is called with.
+pub fn prepare_block() {
+    let current = current();
+    assert!(!current.is_idle(), "Idle threads can not block");
+    let _guard = current.wake_lock.lock();
+    current.set_state(ThreadState::Blocked);
+}
+
+/// Undoes [`prepare_block`], for a thread that finds it has to stop
+/// waiting before it got to [`block`].
+pub fn cancel_block() {
+    let current = current();
+    let _guard = current.wake_lock.lock();
+    current.set_state(ThreadState::Running);
+}
+
+/// Gives up the hart after [`prepare_block`], returning once woken up.
+pub fn block() {
+    schedule();
+}
+
+/// Like [`prepare_block`], but also has the thread woken up at `deadline`
+/// (see [`now`]) if nobody did before, which [`block_until`] then tells.
+///
+/// The thread is registered to be woken up before it is marked as blocked,
+/// both under the lock of the sleepers, so an interrupt that switches away
+/// from it in between, or the deadline passing right then, can not leave
+/// it blocked with nobody to wake it up.
+pub fn prepare_block_until(deadline: u64) {
+    let me = current();
+    let mut sleepers = SLEEPERS.lock();
+    sleepers.insert((deadline, me.tid()), me.clone());
+    prepare_block();
+}
+
+/// Undoes [`prepare_block_until`], like [`cancel_block`].
+pub fn cancel_block_until(deadline: u64) {
+    SLEEPERS.lock().remove(&(deadline, current().tid()));
+    cancel_block();
+}
+
+/// Gives up the hart after [`prepare_block_until`], returning once woken
+/// up. Returns whether the deadline is what woke it up.
+///
+/// The wake up at the deadline may come late and hit the thread while it
+/// waits for something else, so whoever blocks has to check that what it
+/// waited for actually happened.
+pub fn block_until(deadline: u64) -> bool {
+    block();
+    SLEEPERS.lock().remove(&(deadline, current().tid())).is_none()
+}
+
+/// Queues `thread` again if it is waiting in a run queue, so that a
+/// change of its class or priority is taken into account.
+pub fn requeue(thread: &Arc<Thread>) {
+    let now = now();
+    for sched in HARTS.iter() {
+        let mut queue = sched.queue.lock();
+        if let Some(t) = queue.remove(thread.tid()) {
+            queue.enqueue(t, now);
+            return;
+        }
+    }
+}
+
+/// Puts the calling thread to sleep until `time` (see [`now`]), or until
+/// it is [`signal::interrupted`], in which case it fails with `EINTR`.
+/// Sleeps are only as precise as the timer interrupt, see [`TIME_SLICE`].
+pub fn sleep_until(time: u64) -> SysResult<()> {
+    while now() < time {
+        prepare_block_until(time);
+        if signal::interrupted() {
+            cancel_block_until(time);
+            return Err(Errno::EINTR);
+        }
+        block_until(time);
+    }
+    Ok(())
+}
+
+/// Puts the calling thread to sleep for `ticks` timer ticks, see [`sleep_until`].
+pub fn sleep(ticks: u64) -> SysResult<()> {
+    sleep_until(now() + ticks)
+}
+
+/// Wakes up the threads whose sleep is over.
+fn wake_sleepers(now: u64) {
+    let mut woken = alloc::vec::Vec::new();
+    {
+        let mut sleepers = SLEEPERS.lock();
+        while let Some(entry) = sleepers.first_entry() {
+            if entry.key().0 > now {
+                break;
+            }
+            woken.push(entry.remove());
+        }
+    }
+    for thread in woken {
+        wake(&thread);
+    }
+}
+
+/// Switches threads if something more urgent was woken up on this hart.
+pub fn resched_if_needed() {
+    if this_hart().need_resched.swap(false, Ordering::Relaxed) {
+        schedule();
+    }
+}
+
+/// Wakes up a hart waiting for work, if there is one.
+fn kick_idle_hart() {
+    let idle = IDLE_HARTS.load(Ordering::Acquire) & !(1 << unsafe { my_hart() });
+    if idle != 0 {
+        let _ = sbi::send_ipi(1 << idle.trailing_zeros(), 0);
+    }
+}
+
+/// Takes a thread from the busiest other hart.
+fn steal(now: u64) -> Option<Arc<Thread>> {
+    let me = unsafe { my_hart() };
+    let busiest = (0..MAX_HARTS)
+        .filter(|&h| h != me)
+        .max_by_key(|&h| HARTS[h].queue.lock().len())?;
+    let stolen = HARTS[busiest].queue.lock().pop(now);
+    stolen
+}
+
+fn has_work() -> bool {
+    let now = now();
+    HARTS.iter().any(|h| h.queue.lock().peek(now).is_some())
+}
+
+/// Switches to the most urgent runnable thread, unless the current one
+/// outranks it. Between equals the current thread goes last, so they
+/// take turns. A current thread that is not running anymore (it blocked
+/// or exited) is left alone, and freed once nothing refers to it.
+pub fn schedule() {
+    let interrupts = cpu::disable_interrupts();
+    let sched = this_hart();
+    sched.need_resched.store(false, Ordering::Relaxed);
+    let prev = unsafe { (*sched.current.get()).clone() }.expect("Threads are not set up on this hart");
+
+    let now = now();
+    prev.charge(now);
+
+    let runnable = matches!(prev.state(), ThreadState::Running | ThreadState::Runnable);
+    let keep_prev = runnable && !prev.is_idle() && prev.entity().eligible();
+
+    let next = {
+        let mut queue = sched.queue.lock();
+        match queue.peek(now) {
+            Some(rank) if !keep_prev || rank >= prev.entity().rank() => queue.pop(now),
+            _ => None,
+        }
+    };
+    let next = match next {
+        Some(next) => next,
+        None if keep_prev => {
+            prev.set_state(ThreadState::Running);
+            cpu::restore_interrupts(interrupts);
+            return;
+        }
+        None => match steal(now) {
+            Some(next) => next,
+            None if prev.is_idle() => {
+                cpu::restore_interrupts(interrupts);
+                return;
+            }
+            None => unsafe { (*sched.idle.get()).clone() }.expect("No idle thread on this hart"),
+        },
+    };
+
+    next.switched_in(now);
+    sched.switches.fetch_add(1, Ordering::Relaxed);
+
+    if prev.state() == ThreadState::Running {
+        prev.set_state(ThreadState::Runnable);
+    }
+    next.set_state(ThreadState::Running);
+    next.on_cpu.store(true, Ordering::Release);
+    let hart = hart::current();
+    hart.set_stack_bottom(next.stack_bottom());
+    hart.set_kernel_sp(next.kernel_stack_top());
+
+    // A thread preempted while copying to or from user memory gets
+    // `sstatus.SUM` back when it returns from the trap, nobody else should
+    // run with it.
+    ControlStatusRegister::SStatus.clear_bits(SSTATUS_SUM);
+
+    let satp = next.satp();
+    if ControlStatusRegister::Satp.read() != satp {
+        ControlStatusRegister::Satp.write(satp);
+        table::flush_tlb();
+    }
+
+    let old = prev.context();
+    let new = next.context() as *const Context;
+    unsafe {
+        *sched.current.get() = Some(next);
+        *sched.previous.get() = Some(prev);
+        switch_to(old, new);
+    }
+
+    finish_switch();
+    cpu::restore_interrupts(interrupts);
+}
+
+/// Runs on the new thread right after a switch: deals with the thread
+/// we switched away from, now that nothing runs on its stack.
+pub(super) fn finish_switch() {
+    let Some(prev) = (unsafe { (*this_hart().previous.get()).take() }) else {
+        return;
+    };
+
+    let _guard = prev.wake_lock.lock();
+    prev.on_cpu.store(false, Ordering::Release);
+    if prev.state() == ThreadState::Runnable && !prev.is_idle() {
+        this_hart().queue.lock().enqueue(prev.clone(), now());
+    }
+}
+
+/// What the idle thread of every hart does.
+pub fn idle_loop() -> ! {
+    let me = 1 << unsafe { my_hart() };
+    cpu::enable_interrupts();
+
+    loop {
+        schedule();
+
+        // Interrupts are left pending while we check for work, `wfi`
+        // still returns for them, and they are taken once re-enabled.
+        cpu::disable_interrupts();
+        IDLE_HARTS.fetch_or(me, Ordering::AcqRel);
+        if !has_work() {
+            cpu::wait_for_interrupt();
+        }
+        IDLE_HARTS.fetch_and(!me, Ordering::AcqRel);
+        cpu::enable_interrupts();
+    }
+}
+
+fn arm_timer() {
+    let _ = sbi::set_timer(now() + TIME_SLICE);
+}
+
+/// Timer interrupt: the running thread's time slice is over.
+pub fn timer_tick() {
+    arm_timer();
+    wake_sleepers(now());
+    schedule();
+}
+
+/// Software interrupt: another hart has work for us.
+pub fn software_interrupt() {
+    ControlStatusRegister::Sip.clear_bits(1 << 1);
+    schedule();
+}
+
+fn ticks_to_ms(ticks: u64) -> u64 {
+    ticks * 1000 / TIMEBASE_FREQ
+}
+
+/// Starts a kernel thread that logs scheduler statistics and the process
+/// table every [`STATS_INTERVAL`]. Logging takes locks and allocates, so
+/// it has no place in the timer interrupt.
+pub fn start_stats() -> crate::Result<()> {
+    thread::spawn("stats", || loop {
+        // Kernel threads are never interrupted.
+        let _ = sleep(STATS_INTERVAL);
+        log_stats();
+        super::log_table();
+    })?;
+    Ok(())
+}
+
+/// Logs context switches per hart, and how long every thread ran.
+pub fn log_stats() {
+    let online = ONLINE_HARTS.load(Ordering::Acquire);
+    info!("Scheduler statistics at {} ms:", ticks_to_ms(now()));
+    for (id, sched) in HARTS.iter().enumerate().filter(|(id, _)| online & (1 << id) != 0) {
+        info!(
+            "  hart {}: {} context switches, {} queued",
+            id,
+            sched.switches.load(Ordering::Relaxed),
+            sched.queue.lock().len()
+        );
+    }
+    thread::for_each(|t| {
+        info!(
+            "  thread {} ({}): {:?}, ran {} ms over {} slices",
+            t.tid(),
+            t.name(),
+            t.state(),
+            ticks_to_ms(t.run_time()),
+            t.switches()
+        );
+    });
+}
@@ -0,0 +1,586 @@
+//! POSIX signals.
+//!
+//! A signal is sent either to a whole process, for whichever of its threads
+//! does not block it to handle, or to one thread, like the ones a fault
+//! raises. Nothing happens right away (except for `SIGKILL` and `SIGCONT`
+//! waking up a stopped process): pending signals are acted on by [`deliver`]
+//! when a thread is about to return to U-mode. Threads blocked in the
+//! kernel are woken up to get there, and fail what they waited for with
+//! `EINTR` (see [`interrupted`]). Acting on a signal either runs the
+//! default action, or sets the thread up to run the handler the process
+//! registered, with a Linux compatible signal frame on its stack. The
+//! handler returns to the code in the [`USER_SIGPAGE`] page, which asks
+//! for `rt_sigreturn` to resume what was interrupted.
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::{
+    mem::{offset_of, size_of},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+use crate::{
+    cpu::trap::TrapFrame,
+    debug,
+    mem::{
+        pages::PAGE_SIZE,
+        space::{self, AddressSpace, USER_SIGPAGE},
+        vma::{Mappable, Vma},
+    },
+    sync::spinlock::SpinLock,
+    syscall::{errno::Errno, uaccess::UserPtr, SysResult},
+    warn,
+};
+
+use super::{sched, thread::{self, Thread}, ExitStatus, Process};
+
+pub type Signal = usize;
+
+/// A set of signals, signal `n` being bit `n - 1`.
+pub type SigSet = u64;
+
+/// Number of signals, they go from 1 to `NSIG`.
+pub const NSIG: usize = 64;
+
+pub const SIGHUP: Signal = 1;
+pub const SIGINT: Signal = 2;
+pub const SIGQUIT: Signal = 3;
+pub const SIGILL: Signal = 4;
+pub const SIGTRAP: Signal = 5;
+pub const SIGABRT: Signal = 6;
+pub const SIGBUS: Signal = 7;
+pub const SIGFPE: Signal = 8;
+pub const SIGKILL: Signal = 9;
+pub const SIGUSR1: Signal = 10;
+pub const SIGSEGV: Signal = 11;
+pub const SIGUSR2: Signal = 12;
+pub const SIGPIPE: Signal = 13;
+pub const SIGALRM: Signal = 14;
+pub const SIGTERM: Signal = 15;
+pub const SIGSTKFLT: Signal = 16;
+pub const SIGCHLD: Signal = 17;
+pub const SIGCONT: Signal = 18;
+pub const SIGSTOP: Signal = 19;
+pub const SIGTSTP: Signal = 20;
+pub const SIGTTIN: Signal = 21;
+pub const SIGTTOU: Signal = 22;
+pub const SIGURG: Signal = 23;
+pub const SIGXCPU: Signal = 24;
+pub const SIGXFSZ: Signal = 25;
+pub const SIGVTALRM: Signal = 26;
+pub const SIGPROF: Signal = 27;
+pub const SIGWINCH: Signal = 28;
+pub const SIGIO: Signal = 29;
+pub const SIGPWR: Signal = 30;
+pub const SIGSYS: Signal = 31;
+
+/// Handler value for the default action.
+pub const SIG_DFL: usize = 0;
+/// Handler value for ignoring the signal.
+pub const SIG_IGN: usize = 1;
+
+/// The handler takes a `siginfo_t` and a `ucontext_t` as well. We always
+/// pass them, so this makes no difference.
+pub const SA_SIGINFO: usize = 0x4;
+/// The signal is not blocked while its handler runs.
+pub const SA_NODEFER: usize = 0x4000_0000;
+/// The default action is put back once the handler is called.
+pub const SA_RESETHAND: usize = 0x8000_0000;
+
+/// `si_code` of signals sent by other processes.
+const SI_USER: i32 = 0;
+/// `si_code` of faults, e.g. `SEGV_MAPERR` or `ILL_ILLOPC`.
+const SI_FAULT: i32 = 1;
+
+/// Signals that can be neither blocked, ignored nor handled.
+const UNBLOCKABLE: SigSet = sigmask(SIGKILL) | sigmask(SIGSTOP);
+
+/// Signals that stop a process unless handled.
+const STOP_SIGNALS: SigSet = sigmask(SIGSTOP) | sigmask(SIGTSTP) | sigmask(SIGTTIN) | sigmask(SIGTTOU);
+
+/// `li a7, 139; ecall`, calling `rt_sigreturn`.
+const TRAMPOLINE_CODE: [u32; 2] = [0x08b0_0893, 0x0000_0073];
+
+/// The set holding just `sig`.
+pub const fn sigmask(sig: Signal) -> SigSet {
+    1 << (sig - 1)
+}
+
+/// Whether `sig` is a signal number.
+pub fn is_valid(sig: Signal) -> bool {
+    (1..=NSIG).contains(&sig)
+}
+
+/// What a signal does when the process did not say otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefaultAction {
+    Terminate,
+    /// Terminate, reporting a core dump (we never write one).
+    CoreDump,
+    Ignore,
+    Stop,
+    /// Resume a stopped process, which happens when sending the signal.
+    Continue,
+}
+
+pub fn default_action(sig: Signal) -> DefaultAction {
+    match sig {
+        SIGQUIT | SIGILL | SIGTRAP | SIGABRT | SIGBUS | SIGFPE | SIGSEGV | SIGXCPU | SIGXFSZ | SIGSYS => {
+            DefaultAction::CoreDump
+        }
+        SIGCHLD | SIGURG | SIGWINCH => DefaultAction::Ignore,
+        SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Stop,
+        SIGCONT => DefaultAction::Continue,
+        _ => DefaultAction::Terminate,
+    }
+}
+
+/// What a process does with a signal, laid out like Linux's `struct sigaction`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SigAction {
+    /// [`SIG_DFL`], [`SIG_IGN`] or the address of a handler.
+    pub handler: usize,
+    /// `SA_*` flags.
+    pub flags: usize,
+    /// Signals blocked while the handler runs.
+    pub mask: SigSet,
+}
+
+/// Signal state of a thread.
+pub struct ThreadSignals {
+    /// Signals sent to this thread in particular.
+    pending: AtomicU64,
+    blocked: AtomicU64,
+    /// The fault the last signal raised by [`force`] was for.
+    fault: SpinLock<Option<(Signal, usize)>>,
+}
+
+impl ThreadSignals {
+    pub const fn new() -> Self {
+        Self { pending: AtomicU64::new(0), blocked: AtomicU64::new(0), fault: SpinLock::new(None) }
+    }
+
+    pub fn pending(&self) -> SigSet {
+        self.pending.load(Ordering::Acquire)
+    }
+
+    pub fn blocked(&self) -> SigSet {
+        self.blocked.load(Ordering::Acquire)
+    }
+
+    /// Blocks the signals in `mask`, except the ones that can not be.
+    pub fn set_blocked(&self, mask: SigSet) {
+        self.blocked.store(mask & !UNBLOCKABLE, Ordering::Release);
+    }
+}
+
+impl Default for ThreadSignals {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Signal state shared by the threads of a process.
+pub struct ProcessSignals {
+    actions: SpinLock<[SigAction; NSIG]>,
+    /// Signals sent to the process as a whole.
+    pending: AtomicU64,
+    stopped: AtomicBool,
+    /// Threads waiting for the process to be continued.
+    parked: SpinLock<Vec<Arc<Thread>>>,
+}
+
+impl ProcessSignals {
+    pub const fn new() -> Self {
+        Self {
+            actions: SpinLock::new([SigAction { handler: SIG_DFL, flags: 0, mask: 0 }; NSIG]),
+            pending: AtomicU64::new(0),
+            stopped: AtomicBool::new(false),
+            parked: SpinLock::new(Vec::new()),
+        }
+    }
+
+    pub fn action(&self, sig: Signal) -> SigAction {
+        self.actions.lock()[sig - 1]
+    }
+
+    /// Changes what the process does with `sig`, which can be
+    /// neither `SIGKILL` nor `SIGSTOP`.
+    pub fn set_action(&self, sig: Signal, action: SigAction) {
+        debug_assert!(sigmask(sig) & UNBLOCKABLE == 0);
+        self.actions.lock()[sig - 1] = SigAction { mask: action.mask & !UNBLOCKABLE, ..action };
+    }
+
+    pub fn pending(&self) -> SigSet {
+        self.pending.load(Ordering::Acquire)
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Acquire)
+    }
+
+    /// Takes over the actions of `parent`, as a forked child does.
+    pub fn inherit(&self, parent: &ProcessSignals) {
+        *self.actions.lock() = *parent.actions.lock();
+    }
+
+    /// Puts back the default action for every handled signal, since the
+    /// handlers are gone with the program. Ignored signals stay ignored.
+    pub fn reset_handlers(&self) {
+        for action in self.actions.lock().iter_mut() {
+            if action.handler != SIG_IGN {
+                *action = SigAction::default();
+            }
+        }
+    }
+
+    /// Whether `sig` would be thrown away when acted on.
+    fn is_ignored(&self, sig: Signal) -> bool {
+        match self.action(sig).handler {
+            SIG_IGN => true,
+            SIG_DFL => matches!(default_action(sig), DefaultAction::Ignore | DefaultAction::Continue),
+            _ => false,
+        }
+    }
+
+    /// Lets the threads of a stopped process run again.
+    fn resume(&self) {
+        self.stopped.store(false, Ordering::Release);
+        let parked = core::mem::take(&mut *self.parked.lock());
+        for thread in parked {
+            sched::wake(&thread);
+        }
+    }
+}
+
+impl Default for ProcessSignals {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What sending `sig` to `process` does right away, before anyone acts on it.
+fn generated(process: &Process, sig: Signal) {
+    let signals = process.signals();
+    if sig == SIGCONT || sig == SIGKILL {
+        signals.pending.fetch_and(!STOP_SIGNALS, Ordering::AcqRel);
+        signals.resume();
+    } else if sigmask(sig) & STOP_SIGNALS != 0 {
+        signals.pending.fetch_and(!sigmask(SIGCONT), Ordering::AcqRel);
+    }
+}
+
+/// Sends `sig` to `process`, for whichever of its threads gets to it first.
+pub fn send(process: &Process, sig: Signal) {
+    generated(process, sig);
+    if !process.signals().is_ignored(sig) {
+        process.signals().pending.fetch_or(sigmask(sig), Ordering::AcqRel);
+        interrupt(process);
+    }
+}
+
+/// Sends `sig` to `thread` in particular.
+pub fn send_to_thread(thread: &Arc<Thread>, sig: Signal) {
+    let Some(process) = thread.process() else {
+        return;
+    };
+    generated(process, sig);
+    if !process.signals().is_ignored(sig) {
+        thread.signals().pending.fetch_or(sigmask(sig), Ordering::AcqRel);
+        sched::wake(thread);
+    }
+}
+
+/// Wakes up the threads of `process` that are blocked in the kernel, so
+/// they get to act on a signal, or to exit. Whatever they wait for has to
+/// cope with being woken up for nothing, and the ones that can give up
+/// check [`interrupted`].
+pub(super) fn interrupt(process: &Process) {
+    for thread in thread::of_process(process) {
+        sched::wake(&thread);
+    }
+}
+
+/// Whether the calling thread has to stop waiting in the kernel, and fail
+/// with `EINTR`, because there is an unblocked signal pending for it or
+/// its process is exiting. Kernel threads are never interrupted.
+///
+/// Waiters check this after [`sched::prepare_block`], so that a signal
+/// sent right before they block is not missed: it is either seen here, or
+/// the wake up that comes with it finds them blocked.
+pub fn interrupted() -> bool {
+    let thread = thread::current();
+    let Some(process) = thread.process() else {
+        return false;
+    };
+    let pending = thread.signals().pending() | process.signals().pending();
+    pending & !thread.signals().blocked() != 0 || process.is_exiting()
+}
+
+/// Sends `sig` to the calling thread for a fault at `addr`. The process
+/// gets no say in whether it is acted on: if it blocks or ignores `sig`,
+/// the default action is put back, rather than having the thread fault
+/// on the same instruction forever.
+pub fn force(sig: Signal, addr: usize) {
+    let thread = thread::current();
+    let Some(process) = thread.process() else {
+        return;
+    };
+    let signals = thread.signals();
+    {
+        let mut actions = process.signals().actions.lock();
+        if signals.blocked() & sigmask(sig) != 0 || actions[sig - 1].handler == SIG_IGN {
+            actions[sig - 1] = SigAction::default();
+            signals.blocked.fetch_and(!sigmask(sig), Ordering::AcqRel);
+        }
+    }
+    *signals.fault.lock() = Some((sig, addr));
+    signals.pending.fetch_or(sigmask(sig), Ordering::AcqRel);
+}
+
+/// Takes the lowest unblocked signal pending for `thread`,
+/// looking at the ones sent to it in particular first.
+fn dequeue(thread: &Thread, process: &Process) -> Option<Signal> {
+    let blocked = thread.signals().blocked();
+    loop {
+        let (pending, ready) = [&thread.signals().pending, &process.signals().pending]
+            .into_iter()
+            .map(|p| (p, p.load(Ordering::Acquire) & !blocked))
+            .find(|(_, ready)| *ready != 0)?;
+        let bit = ready & ready.wrapping_neg();
+        if pending.fetch_and(!bit, Ordering::AcqRel) & bit != 0 {
+            return Some(bit.trailing_zeros() as usize + 1);
+        }
+    }
+}
+
+/// Blocks the calling thread until its stopped process is continued.
+fn park(thread: &Arc<Thread>, process: &Process) {
+    let signals = process.signals();
+    let mut parked = signals.parked.lock();
+    if !signals.is_stopped() {
+        return;
+    }
+    parked.push(thread.clone());
+    sched::prepare_block();
+    drop(parked);
+    sched::block();
+}
+
+/// Acts on the signals pending for the calling thread, which is about to
+/// return to U-mode with `frame`, after sending those for running too long.
+/// The thread exits if its process is done, sleeps while it is stopped,
+/// and otherwise goes on to run the handler of the first signal that has one.
+pub fn deliver(frame: &mut TrapFrame) {
+    let thread = thread::current();
+    let Some(process) = thread.process().cloned() else {
+        return;
+    };
+    process.check_cpu_limit();
+
+    loop {
+        if process.is_exiting() {
+            break;
+        }
+        if process.signals().is_stopped() {
+            park(&thread, &process);
+            continue;
+        }
+        let Some(sig) = dequeue(&thread, &process) else {
+            return;
+        };
+
+        let action = process.signals().action(sig);
+        let core = match action.handler {
+            SIG_IGN => continue,
+            SIG_DFL => match default_action(sig) {
+                DefaultAction::Ignore | DefaultAction::Continue => continue,
+                DefaultAction::Stop => {
+                    debug!("Process {} stopped by signal {}", process.pid(), sig);
+                    process.signals().stopped.store(true, Ordering::Release);
+                    continue;
+                }
+                DefaultAction::Terminate => false,
+                DefaultAction::CoreDump => true,
+            },
+            _ => match setup_frame(frame, &thread, sig, &action) {
+                Ok(()) => {
+                    if action.flags & SA_RESETHAND != 0 {
+                        process.signals().set_action(sig, SigAction::default());
+                    }
+                    return;
+                }
+                // No room for the frame, there is no way to run the handler.
+                Err(_) => {
+                    process.set_exit_status(ExitStatus::Signaled { signal: SIGSEGV, core: true });
+                    break;
+                }
+            },
+        };
+
+        if core {
+            warn!("Process {} ({}) killed by signal {} at {:#0x}", process.pid(), process.name(), sig, frame.pc);
+            debug!("Memory map of process {}:\n{}", process.pid(), process.memory_map());
+        }
+        process.set_exit_status(ExitStatus::Signaled { signal: sig, core });
+        break;
+    }
+
+    // Nothing may be left on this stack that keeps the process alive.
+    drop(process);
+    drop(thread);
+    thread::exit();
+}
+
+/// `siginfo_t`, 128 bytes.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SigInfo {
+    signo: i32,
+    errno: i32,
+    code: i32,
+    _pad: i32,
+    /// `si_addr` of faults.
+    addr: usize,
+    _rest: [u64; 13],
+}
+
+/// `stack_t`, for `sigaltstack`, which we do not support.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SigStack {
+    sp: usize,
+    flags: i32,
+    size: usize,
+}
+
+/// Room for the floating point registers, which we leave alone.
+#[repr(C, align(16))]
+#[derive(Clone, Copy)]
+struct FpState([u8; 528]);
+
+/// `struct sigcontext`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct MContext {
+    /// `pc` followed by `x1` to `x31`.
+    gregs: [usize; 32],
+    fp: FpState,
+}
+
+/// `ucontext_t`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UContext {
+    flags: usize,
+    link: usize,
+    stack: SigStack,
+    sigmask: SigSet,
+    _unused: [u8; 120],
+    mcontext: MContext,
+}
+
+/// What a handler finds on its stack.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SigFrame {
+    info: SigInfo,
+    uc: UContext,
+}
+
+// User code reads these, so they have to match Linux to the byte.
+const _: () = assert!(size_of::<SigInfo>() == 128);
+const _: () = assert!(offset_of!(UContext, mcontext) == 176);
+const _: () = assert!(offset_of!(SigFrame, uc) == 128);
+
+/// Pushes a signal frame for `sig` onto the user stack in `frame`, and
+/// points `frame` at the handler of `action`.
+fn setup_frame(frame: &mut TrapFrame, thread: &Thread, sig: Signal, action: &SigAction) -> SysResult<()> {
+    let signals = thread.signals();
+    let (code, addr) = {
+        let mut fault = signals.fault.lock();
+        match *fault {
+            Some((s, addr)) if s == sig => {
+                *fault = None;
+                (SI_FAULT, addr)
+            }
+            _ => (SI_USER, 0),
+        }
+    };
+
+    let mut gregs = frame.regs;
+    gregs[0] = frame.pc;
+    let sigframe = SigFrame {
+        info: SigInfo { signo: sig as i32, errno: 0, code, _pad: 0, addr, _rest: [0; 13] },
+        uc: UContext {
+            flags: 0,
+            link: 0,
+            stack: SigStack { sp: 0, flags: 0, size: 0 },
+            sigmask: signals.blocked(),
+            _unused: [0; 120],
+            mcontext: MContext { gregs, fp: FpState([0; 528]) },
+        },
+    };
+
+    let sp = frame.regs[TrapFrame::SP].checked_sub(size_of::<SigFrame>()).ok_or(Errno::EFAULT)? & !0xf;
+    UserPtr::<SigFrame>::new(sp).write(&sigframe)?;
+
+    frame.pc = action.handler;
+    frame.regs[TrapFrame::RA] = USER_SIGPAGE;
+    frame.regs[TrapFrame::SP] = sp;
+    frame.set_arg(0, sig);
+    frame.set_arg(1, sp + offset_of!(SigFrame, info));
+    frame.set_arg(2, sp + offset_of!(SigFrame, uc));
+
+    let mut blocked = signals.blocked() | action.mask;
+    if action.flags & SA_NODEFER == 0 {
+        blocked |= sigmask(sig);
+    }
+    signals.set_blocked(blocked);
+    Ok(())
+}
+
+/// Resumes what a signal handler interrupted, from the signal frame the
+/// handler returned with on top of its stack. Returns what goes back in `a0`.
+pub fn sigreturn() -> SysResult {
+    let thread = thread::current();
+    let frame = unsafe { &mut *thread.user_frame() };
+    let sigframe = match UserPtr::<SigFrame>::new(frame.regs[TrapFrame::SP]).read() {
+        Ok(sigframe) => sigframe,
+        Err(e) => {
+            force(SIGSEGV, frame.regs[TrapFrame::SP]);
+            return Err(e);
+        }
+    };
+
+    let gregs = sigframe.uc.mcontext.gregs;
+    frame.pc = gregs[0];
+    frame.regs[1..].copy_from_slice(&gregs[1..]);
+    thread.signals().set_blocked(sigframe.uc.sigmask);
+    Ok(frame.regs[TrapFrame::A0])
+}
+
+/// The code in the [`USER_SIGPAGE`] page.
+struct Trampoline;
+
+impl Mappable for Trampoline {
+    fn read_page(&self, offset: usize, buf: &mut [u8]) -> crate::Result<()> {
+        buf.fill(0);
+        if offset == 0 {
+            for (dst, insn) in buf.chunks_exact_mut(4).zip(TRAMPOLINE_CODE) {
+                dst.copy_from_slice(&insn.to_le_bytes());
+            }
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> String {
+        String::from("[sigpage]")
+    }
+}
+
+/// Maps the code signal handlers return through into `space`.
+pub fn map_sigpage(space: &mut AddressSpace) -> crate::Result<()> {
+    let rx = space::user_flags(true, false, true);
+    space.map_area(Vma::file(USER_SIGPAGE, USER_SIGPAGE + PAGE_SIZE, rx, Arc::new(Trampoline), 0))
+}
@@ -0,0 +1,417 @@
+//! Kernel threads.
+//!
+//! Every thread has its own guarded kernel stack (see [`crate::mem::stack`])
+//! and a saved [`Context`], which is all `switch_to` (see `asm/switch.s`)
+//! needs to move a hart from one thread to another. Each hart also has an
+//! idle thread, which is the context the hart booted on, and which runs
+//! whenever there is nothing else to do. Picking what runs where is up to
+//! [`super::sched`].
+
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+};
+use core::{
+    cell::UnsafeCell,
+    mem::size_of,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering},
+};
+
+use crate::{
+    cpu::{self, mode::Mode, trap::TrapFrame, util::my_hart},
+    mem::{stack::KernelStack, table},
+    sync::{spinlock::SpinLock, WaitQueue},
+};
+
+use super::{
+    class::{SchedClass, SchedEntity},
+    sched,
+    signal::ThreadSignals,
+    Process,
+};
+
+pub type Tid = usize;
+
+/// Callee-saved registers of a thread that is not running,
+/// laid out the way `switch_to` expects.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Context {
+    pub ra: usize,
+    pub sp: usize,
+    pub s: [usize; 12],
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadState {
+    /// Waiting in a run queue.
+    Runnable,
+    /// Running on some hart.
+    Running,
+    /// Waiting for something else to wake it up.
+    Blocked,
+    /// Done, waiting for its resources to be reclaimed.
+    Exited,
+}
+
+impl ThreadState {
+    fn from_bits(bits: u8) -> Self {
+        match bits {
+            0 => Self::Runnable,
+            1 => Self::Running,
+            2 => Self::Blocked,
+            _ => Self::Exited,
+        }
+    }
+}
+
+type Entry = Box<dyn FnOnce() + Send>;
+
+pub struct Thread {
+    tid: Tid,
+    name: String,
+    state: AtomicU8,
+    /// Only touched by the hart switching to or away from this thread.
+    context: UnsafeCell<Context>,
+    /// `None` for idle threads, which run on their hart's boot stack.
+    stack: Option<KernelStack>,
+    stack_bottom: usize,
+    /// The process this thread runs code of in U-mode, if any.
+    process: Option<Arc<Process>>,
+    entry: SpinLock<Option<Entry>>,
+    /// Time spent running, in timer ticks.
+    run_time: AtomicU64,
+    /// When the thread last started running.
+    last_start: AtomicU64,
+    /// How many times the thread was switched to.
+    switches: AtomicUsize,
+    entity: SchedEntity,
+    signals: ThreadSignals,
+    /// The mutex this thread is waiting for, by id, and its owner.
+    blocked_on: SpinLock<Option<(usize, Arc<Thread>)>>,
+    /// Whether a hart is still running on this thread's stack.
+    pub(super) on_cpu: AtomicBool,
+    /// Serializes waking the thread up with switching away from it.
+    pub(super) wake_lock: SpinLock<()>,
+}
+
+unsafe impl Send for Thread {}
+unsafe impl Sync for Thread {}
+
+impl Thread {
+    fn new(name: String, class: SchedClass, entry: Entry) -> crate::Result<Self> {
+        Self::with_stack(name, class, thread_start, Some(entry), None)
+    }
+
+    /// Creates a thread of `process` that starts in U-mode
+    /// with the registers in `frame`.
+    pub(super) fn new_user(process: Arc<Process>, frame: TrapFrame) -> crate::Result<Self> {
+        let name = process.name();
+        let thread = Self::with_stack(name, SchedClass::default(), user_thread_start, None, Some(process))?;
+        unsafe { *thread.user_frame() = frame };
+        Ok(thread)
+    }
+
+    fn with_stack(
+        name: String,
+        class: SchedClass,
+        start: extern "C" fn() -> !,
+        entry: Option<Entry>,
+        process: Option<Arc<Process>>,
+    ) -> crate::Result<Self> {
+        let stack = KernelStack::new()?;
+        let context = Context {
+            ra: start as *const () as usize,
+            // The top of the stack is where the registers
+            // of the U-mode side of the thread go.
+            sp: stack.top() - size_of::<TrapFrame>(),
+            ..Default::default()
+        };
+
+        Ok(Self {
+            tid: next_tid(),
+            name,
+            state: AtomicU8::new(ThreadState::Runnable as u8),
+            context: UnsafeCell::new(context),
+            stack_bottom: stack.bottom(),
+            stack: Some(stack),
+            process,
+            entry: SpinLock::new(entry),
+            run_time: AtomicU64::new(0),
+            last_start: AtomicU64::new(0),
+            switches: AtomicUsize::new(0),
+            entity: SchedEntity::new(class),
+            signals: ThreadSignals::new(),
+            blocked_on: SpinLock::new(None),
+            on_cpu: AtomicBool::new(false),
+            wake_lock: SpinLock::new(()),
+        })
+    }
+
+    pub(super) fn idle(stack_bottom: usize) -> Self {
+        Self {
+            tid: next_tid(),
+            name: format!("idle/{}", unsafe { my_hart() }),
+            state: AtomicU8::new(ThreadState::Running as u8),
+            context: UnsafeCell::new(Context::default()),
+            stack: None,
+            stack_bottom,
+            process: None,
+            entry: SpinLock::new(None),
+            run_time: AtomicU64::new(0),
+            last_start: AtomicU64::new(0),
+            switches: AtomicUsize::new(0),
+            entity: SchedEntity::new(SchedClass::default()),
+            signals: ThreadSignals::new(),
+            blocked_on: SpinLock::new(None),
+            on_cpu: AtomicBool::new(true),
+            wake_lock: SpinLock::new(()),
+        }
+    }
+
+    pub fn tid(&self) -> Tid {
+        self.tid
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn state(&self) -> ThreadState {
+        ThreadState::from_bits(self.state.load(Ordering::Acquire))
+    }
+
+    pub fn set_state(&self, state: ThreadState) {
+        self.state.store(state as u8, Ordering::Release);
+    }
+
+    pub(super) fn is_idle(&self) -> bool {
+        self.stack.is_none()
+    }
+
+    pub(super) fn context(&self) -> *mut Context {
+        self.context.get()
+    }
+
+    pub fn process(&self) -> Option<&Arc<Process>> {
+        self.process.as_ref()
+    }
+
+    /// Top of the thread's kernel stack, where traps from U-mode go.
+    pub fn kernel_stack_top(&self) -> usize {
+        self.stack.as_ref().map_or(0, |s| s.top())
+    }
+
+    /// The saved U-mode registers of the thread, at the top of its kernel stack.
+    pub fn user_frame(&self) -> *mut TrapFrame {
+        (self.kernel_stack_top() - size_of::<TrapFrame>()) as *mut TrapFrame
+    }
+
+    /// The value of `satp` the thread runs with.
+    pub(super) fn satp(&self) -> usize {
+        self.process.as_ref().map_or_else(table::kernel_satp, |p| p.satp())
+    }
+
+    /// Bottom of the stack the thread runs on.
+    pub fn stack_bottom(&self) -> usize {
+        self.stack_bottom
+    }
+
+    /// Time spent running, in timer ticks.
+    pub fn run_time(&self) -> u64 {
+        self.run_time.load(Ordering::Relaxed)
+    }
+
+    /// How many times the thread was switched to.
+    pub fn switches(&self) -> usize {
+        self.switches.load(Ordering::Relaxed)
+    }
+
+    pub(super) fn switched_in(&self, now: u64) {
+        self.last_start.store(now, Ordering::Relaxed);
+        self.switches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Accounts for the time run since the thread was switched
+    /// to, or since it was last charged.
+    pub(super) fn charge(&self, now: u64) {
+        let ran = now.saturating_sub(self.last_start.swap(now, Ordering::Relaxed));
+        self.run_time.fetch_add(ran, Ordering::Relaxed);
+        if let Some(process) = &self.process {
+            process.charge(ran);
+        }
+        if !self.is_idle() {
+            self.entity.charge(ran);
+        }
+    }
+
+    pub fn entity(&self) -> &SchedEntity {
+        &self.entity
+    }
+
+    /// See [`crate::sync::Mutex`], which keeps this up to date.
+    pub fn blocked_on(&self) -> &SpinLock<Option<(usize, Arc<Thread>)>> {
+        &self.blocked_on
+    }
+
+    pub fn signals(&self) -> &ThreadSignals {
+        &self.signals
+    }
+
+    pub fn class(&self) -> SchedClass {
+        self.entity.class()
+    }
+
+    /// Moves the thread to another scheduling class.
+    pub fn set_class(self: &Arc<Self>, class: SchedClass) {
+        self.entity.set_class(class);
+        sched::requeue(self);
+    }
+}
+
+impl Drop for Thread {
+    fn drop(&mut self) {
+        THREADS.lock().remove(&self.tid);
+    }
+}
+
+/// Every thread alive, for statistics.
+static THREADS: SpinLock<BTreeMap<Tid, Weak<Thread>>> = SpinLock::new(BTreeMap::new());
+
+pub(super) fn register(thread: &Arc<Thread>) {
+    THREADS.lock().insert(thread.tid, Arc::downgrade(thread));
+}
+
+/// The thread with id `tid`, if it is still alive.
+pub fn find(tid: Tid) -> Option<Arc<Thread>> {
+    THREADS.lock().get(&tid).and_then(Weak::upgrade)
+}
+
+/// Calls `f` on every thread that is still alive.
+pub fn for_each(mut f: impl FnMut(&Thread)) {
+    let threads: alloc::vec::Vec<_> = THREADS.lock().values().filter_map(Weak::upgrade).collect();
+    for t in threads {
+        f(&t);
+    }
+}
+
+/// The threads of `process` that are still alive.
+pub(super) fn of_process(process: &Process) -> alloc::vec::Vec<Arc<Thread>> {
+    // Threads are dropped with the list unlocked, as dropping one takes it.
+    let threads: alloc::vec::Vec<_> = THREADS.lock().values().filter_map(Weak::upgrade).collect();
+    threads.into_iter().filter(|t| t.process().is_some_and(|p| core::ptr::eq(&**p, process))).collect()
+}
+
+static NEXT_TID: AtomicUsize = AtomicUsize::new(0);
+
+fn next_tid() -> Tid {
+    NEXT_TID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// The thread running on the calling hart.
+pub fn current() -> Arc<Thread> {
+    sched::current()
+}
+
+/// Where a thread spawned with [`spawn`] leaves its result.
+struct Packet<T> {
+    result: SpinLock<Option<T>>,
+    done: WaitQueue,
+}
+
+/// Handle to wait for a thread spawned with [`spawn`] and get its result.
+pub struct JoinHandle<T> {
+    tid: Tid,
+    packet: Arc<Packet<T>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn tid(&self) -> Tid {
+        self.tid
+    }
+
+    /// Waits for the thread to finish, returning what it returned.
+    pub fn join(self) -> T {
+        self.packet.done.wait_until(|| self.packet.result.lock().take())
+    }
+}
+
+/// Starts a kernel thread running `f`, in the default fair class.
+pub fn spawn<F, T>(name: &str, f: F) -> crate::Result<JoinHandle<T>>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    spawn_with_class(name, SchedClass::default(), f)
+}
+
+/// Starts a kernel thread running `f`, scheduled with `class`.
+pub fn spawn_with_class<F, T>(name: &str, class: SchedClass, f: F) -> crate::Result<JoinHandle<T>>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let packet = Arc::new(Packet { result: SpinLock::new(None), done: WaitQueue::new() });
+    let their_packet = packet.clone();
+
+    let thread = Arc::new(Thread::new(
+        String::from(name),
+        class,
+        Box::new(move || {
+            let v = f();
+            *their_packet.result.lock() = Some(v);
+            their_packet.done.wake_all();
+        }),
+    )?);
+
+    let tid = thread.tid;
+    register(&thread);
+    sched::enqueue(thread);
+    Ok(JoinHandle { tid, packet })
+}
+
+/// Gives up the hart to another runnable thread, if there is one.
+pub fn yield_now() {
+    sched::schedule();
+}
+
+/// Ends the calling thread.
+pub fn exit() -> ! {
+    let thread = current();
+    assert!(!thread.is_idle(), "Idle threads can not exit");
+    if let Some(process) = thread.process() {
+        process.thread_exited();
+    }
+    thread.set_state(ThreadState::Exited);
+    drop(thread);
+
+    sched::schedule();
+    unreachable!("Exited thread was scheduled again");
+}
+
+/// Where new threads start, see [`Thread::new`].
+extern "C" fn thread_start() -> ! {
+    sched::finish_switch();
+    // We got here from `schedule`, which disabled interrupts.
+    cpu::enable_interrupts();
+
+    let entry = current().entry.lock().take();
+    if let Some(entry) = entry {
+        entry();
+    }
+    exit();
+}
+
+/// Where threads created with [`Thread::new_user`] start.
+extern "C" fn user_thread_start() -> ! {
+    sched::finish_switch();
+
+    let frame = current().user_frame();
+    unsafe {
+        let _ = cpu::transition(Mode::User, &mut *frame);
+    }
+    unreachable!("Failed to enter U-mode");
+}
@@ -0,0 +1,108 @@
+//! Timers that threads can wait on.
+//!
+//! A timer expires at a deadline, and then every interval if it has one.
+//! Nothing happens when it does: expirations are counted up whenever
+//! someone looks, so an armed timer costs nothing but the threads waiting.
+
+use crate::{
+    sync::{spinlock::SpinLock, WaitQueue},
+    syscall::SysResult,
+};
+
+use super::sched;
+
+#[derive(Default)]
+struct State {
+    /// When the timer expires next, in ticks (see [`sched::now`]), if armed.
+    deadline: Option<u64>,
+    /// Ticks between expirations, 0 for a timer that expires once.
+    interval: u64,
+    /// Expirations nobody took yet.
+    expirations: u64,
+}
+
+impl State {
+    /// Counts the expirations up to `now`, and moves the deadline past it.
+    fn expire(&mut self, now: u64) {
+        match self.deadline {
+            Some(deadline) if now >= deadline => {
+                // Intervals that passed since the deadline, unless there are none.
+                match (now - deadline).checked_div(self.interval) {
+                    Some(periods) => {
+                        self.expirations += periods + 1;
+                        self.deadline = Some(deadline + (periods + 1) * self.interval);
+                    }
+                    None => {
+                        self.expirations += 1;
+                        self.deadline = None;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+pub struct Timer {
+    state: SpinLock<State>,
+    /// Threads waiting for the timer to expire.
+    expired: WaitQueue,
+}
+
+impl Timer {
+    /// A timer that is not armed.
+    pub fn new() -> Self {
+        Self { state: SpinLock::new(State::default()), expired: WaitQueue::new() }
+    }
+
+    /// Arms the timer to expire at `deadline`, and then every `interval`
+    /// ticks unless it is 0, or disarms it if there is no deadline.
+    /// Expirations not taken yet are dropped.
+    pub fn set(&self, deadline: Option<u64>, interval: u64) {
+        *self.state.lock() = State { deadline, interval, expirations: 0 };
+        // Waiters went to sleep until the old deadline.
+        self.expired.wake_all();
+    }
+
+    /// When the timer expires next, if it is armed, and its interval.
+    pub fn get(&self) -> (Option<u64>, u64) {
+        let mut state = self.state.lock();
+        state.expire(sched::now());
+        (state.deadline, state.interval)
+    }
+
+    /// Takes the expirations so far, if there were any.
+    pub fn try_take(&self) -> Option<u64> {
+        let mut state = self.state.lock();
+        state.expire(sched::now());
+        (state.expirations > 0).then(|| core::mem::take(&mut state.expirations))
+    }
+
+    /// Waits until the timer expired, and takes the expirations so far.
+    /// Waits forever on a timer that is not armed, until someone arms it,
+    /// or until the thread is interrupted, failing with `EINTR`.
+    pub fn wait(&self) -> SysResult<u64> {
+        loop {
+            let deadline = self.state.lock().deadline;
+            // Gives up on `deadline` once the timer was set again.
+            let check = || match self.try_take() {
+                Some(n) => Some(Some(n)),
+                None if self.state.lock().deadline != deadline => Some(None),
+                None => None,
+            };
+            let taken = match deadline {
+                Some(deadline) => self.expired.wait_interruptible_deadline(check, deadline)?.flatten(),
+                None => self.expired.wait_interruptible(check)?,
+            };
+            if let Some(n) = taken {
+                return Ok(n);
+            }
+        }
+    }
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,264 @@
+//! Supervisor Binary Interface (SBI).
+//!
+//! This is the S-mode side of the SBI: the kernel never touches
+//! machine-level hardware (CLINT, `mtimecmp`, the test finisher, ...)
+//! directly, it asks whatever firmware sits below it via `ecall`.
+//! Under `-bios none` that firmware is our own [`crate::firmware`],
+//! under OpenSBI it is OpenSBI, either way this module does not care.
+//!
+//! The calling convention is the standard one: the extension ID goes in
+//! `a7`, the function ID in `a6`, arguments in `a0`-`a5`, and the
+//! firmware returns an error code in `a0` and a value in `a1`.
+
+use core::arch::asm;
+
+/// Base extension, present in every SBI implementation.
+pub const EID_BASE: usize = 0x10;
+/// Timer extension ("TIME").
+pub const EID_TIME: usize = 0x5449_4D45;
+/// Inter-processor interrupt extension ("sPI").
+pub const EID_IPI: usize = 0x0073_5049;
+/// Remote fence extension ("RFNC").
+pub const EID_RFENCE: usize = 0x5246_4E43;
+/// Hart state management extension ("HSM").
+pub const EID_HSM: usize = 0x0048_534D;
+/// System reset extension ("SRST").
+pub const EID_SRST: usize = 0x5352_5354;
+/// Legacy `sbi_console_putchar`.
+pub const EID_LEGACY_CONSOLE_PUTCHAR: usize = 0x01;
+/// Legacy `sbi_console_getchar`.
+pub const EID_LEGACY_CONSOLE_GETCHAR: usize = 0x02;
+
+/// The SBI specification version we implement and expect, v2.0.
+pub const SPEC_VERSION: usize = 2 << 24;
+
+/// Hart states as reported by `sbi_hart_get_status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum HartState {
+    Started = 0,
+    Stopped = 1,
+    StartPending = 2,
+    StopPending = 3,
+    Suspended = 4,
+    SuspendPending = 5,
+    ResumePending = 6,
+}
+
+impl TryFrom<usize> for HartState {
+    type Error = SbiError;
+
+    fn try_from(value: usize) -> core::result::Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Started),
+            1 => Ok(Self::Stopped),
+            2 => Ok(Self::StartPending),
+            3 => Ok(Self::StopPending),
+            4 => Ok(Self::Suspended),
+            5 => Ok(Self::SuspendPending),
+            6 => Ok(Self::ResumePending),
+            _ => Err(SbiError::Failed),
+        }
+    }
+}
+
+/// `sbi_system_reset` reset types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum ResetType {
+    Shutdown = 0,
+    ColdReboot = 1,
+    WarmReboot = 2,
+}
+
+/// `sbi_system_reset` reset reasons.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum ResetReason {
+    NoReason = 0,
+    SystemFailure = 1,
+}
+
+/// Standard SBI error codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SbiError {
+    Failed,
+    NotSupported,
+    InvalidParam,
+    Denied,
+    InvalidAddress,
+    AlreadyAvailable,
+    AlreadyStarted,
+    AlreadyStopped,
+}
+
+impl SbiError {
+    /// The (negative) value placed in `a0` for this error.
+    pub fn code(&self) -> isize {
+        match self {
+            Self::Failed => -1,
+            Self::NotSupported => -2,
+            Self::InvalidParam => -3,
+            Self::Denied => -4,
+            Self::InvalidAddress => -5,
+            Self::AlreadyAvailable => -6,
+            Self::AlreadyStarted => -7,
+            Self::AlreadyStopped => -8,
+        }
+    }
+
+    fn from_code(code: isize) -> Self {
+        match code {
+            -2 => Self::NotSupported,
+            -3 => Self::InvalidParam,
+            -4 => Self::Denied,
+            -5 => Self::InvalidAddress,
+            -6 => Self::AlreadyAvailable,
+            -7 => Self::AlreadyStarted,
+            -8 => Self::AlreadyStopped,
+            _ => Self::Failed,
+        }
+    }
+}
+
+impl core::fmt::Display for SbiError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "SBI error {:?} ({})", self, self.code())
+    }
+}
+
+impl core::error::Error for SbiError {}
+
+pub type SbiResult<T> = core::result::Result<T, SbiError>;
+
+/// Performs a raw SBI call.
+///
+/// # Safety
+///
+/// Some SBI calls (e.g. `sbi_hart_start`) hand addresses to the
+/// firmware which will later be jumped to, the caller must make sure
+/// those are valid.
+#[inline]
+pub unsafe fn call(eid: usize, fid: usize, args: [usize; 4]) -> SbiResult<usize> {
+    let error: isize;
+    let value: usize;
+    asm!(
+        "ecall",
+        inlateout("a0") args[0] => error,
+        inlateout("a1") args[1] => value,
+        in("a2") args[2],
+        in("a3") args[3],
+        in("a6") fid,
+        in("a7") eid,
+    );
+    match error {
+        0 => Ok(value),
+        e => Err(SbiError::from_code(e)),
+    }
+}
+
+/// Performs a legacy (v0.1) SBI call, which only returns a single value in `a0`.
+#[inline]
+fn legacy_call(eid: usize, arg0: usize) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            inlateout("a0") arg0 => ret,
+            in("a7") eid,
+        );
+    }
+    ret
+}
+
+pub fn spec_version() -> SbiResult<usize> {
+    unsafe { call(EID_BASE, 0, [0; 4]) }
+}
+
+pub fn impl_id() -> SbiResult<usize> {
+    unsafe { call(EID_BASE, 1, [0; 4]) }
+}
+
+pub fn impl_version() -> SbiResult<usize> {
+    unsafe { call(EID_BASE, 2, [0; 4]) }
+}
+
+/// Returns whether the firmware implements the extension `eid`.
+pub fn probe_extension(eid: usize) -> bool {
+    matches!(unsafe { call(EID_BASE, 3, [eid, 0, 0, 0]) }, Ok(v) if v != 0)
+}
+
+/// Programs the next timer event for this hart, in `time` ticks.
+///
+/// This also clears any pending supervisor timer interrupt.
+pub fn set_timer(stime_value: u64) -> SbiResult<()> {
+    unsafe { call(EID_TIME, 0, [stime_value as usize, 0, 0, 0]).map(|_| ()) }
+}
+
+/// Sends a supervisor software interrupt to every hart
+/// in `hart_mask`, relative to `hart_mask_base`.
+pub fn send_ipi(hart_mask: usize, hart_mask_base: usize) -> SbiResult<()> {
+    unsafe { call(EID_IPI, 0, [hart_mask, hart_mask_base, 0, 0]).map(|_| ()) }
+}
+
+/// Executes `fence.i` on every hart in the mask.
+pub fn remote_fence_i(hart_mask: usize, hart_mask_base: usize) -> SbiResult<()> {
+    unsafe { call(EID_RFENCE, 0, [hart_mask, hart_mask_base, 0, 0]).map(|_| ()) }
+}
+
+/// Executes `sfence.vma` for the given range on every hart in the mask.
+pub fn remote_sfence_vma(
+    hart_mask: usize,
+    hart_mask_base: usize,
+    start: usize,
+    size: usize,
+) -> SbiResult<()> {
+    unsafe { call(EID_RFENCE, 1, [hart_mask, hart_mask_base, start, size]).map(|_| ()) }
+}
+
+/// Starts `hartid` in S-mode at `start_addr`, with `a0 = hartid`
+/// and `a1 = opaque`.
+///
+/// # Safety
+///
+/// `start_addr` must be the physical address of code that is
+/// able to run with no stack and paging disabled.
+pub unsafe fn hart_start(hartid: usize, start_addr: usize, opaque: usize) -> SbiResult<()> {
+    call(EID_HSM, 0, [hartid, start_addr, opaque, 0]).map(|_| ())
+}
+
+/// Stops the calling hart, handing it back to the firmware.
+pub fn hart_stop() -> SbiResult<()> {
+    unsafe { call(EID_HSM, 1, [0; 4]).map(|_| ()) }
+}
+
+pub fn hart_get_status(hartid: usize) -> SbiResult<HartState> {
+    unsafe { call(EID_HSM, 2, [hartid, 0, 0, 0]).and_then(HartState::try_from) }
+}
+
+/// Resets or shuts down the whole system. Only returns on failure.
+pub fn system_reset(ty: ResetType, reason: ResetReason) -> SbiError {
+    match unsafe { call(EID_SRST, 0, [ty as usize, reason as usize, 0, 0]) } {
+        Ok(_) => SbiError::Failed,
+        Err(e) => e,
+    }
+}
+
+/// Shuts the machine down.
+pub fn shutdown() -> ! {
+    let e = system_reset(ResetType::Shutdown, ResetReason::NoReason);
+    panic!("Unable to shut down: {}", e);
+}
+
+/// Writes a byte to the debug console.
+pub fn console_putchar(c: u8) {
+    legacy_call(EID_LEGACY_CONSOLE_PUTCHAR, c as usize);
+}
+
+/// Reads a byte from the debug console, if one is available.
+pub fn console_getchar() -> Option<u8> {
+    match legacy_call(EID_LEGACY_CONSOLE_GETCHAR, 0) {
+        -1 => None,
+        c => Some(c as u8),
+    }
+}
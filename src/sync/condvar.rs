@@ -0,0 +1,62 @@
+//! Condition variables, to wait for the data behind a [`Mutex`] to change.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{
+    mutex::{Mutex, MutexGuard},
+    waitqueue::WaitQueue,
+};
+
+pub struct Condvar {
+    /// Bumped by every notification, so a waiter can tell whether
+    /// one came after it let go of the mutex.
+    generation: AtomicUsize,
+    queue: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self { generation: AtomicUsize::new(0), queue: WaitQueue::new() }
+    }
+
+    /// Unlocks the mutex behind `guard` and sleeps until notified, locking
+    /// the mutex again before returning. Like on any condition variable,
+    /// the thread may also wake up without having been notified.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let generation = self.generation.load(Ordering::Acquire);
+        let mutex: &'a Mutex<T> = guard.unlock();
+        self.queue
+            .wait_until(|| (self.generation.load(Ordering::Acquire) != generation).then_some(()));
+        mutex.lock()
+    }
+
+    /// Waits for as long as `condition` holds for the data behind the mutex.
+    pub fn wait_while<'a, T>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while condition(&mut guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    /// Wakes up one waiting thread.
+    pub fn notify_one(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        self.queue.wake_one();
+    }
+
+    /// Wakes up every waiting thread.
+    pub fn notify_all(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        self.queue.wake_all();
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
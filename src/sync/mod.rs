@@ -1 +1,10 @@
+pub mod condvar;
+pub mod mutex;
+pub mod semaphore;
 pub mod spinlock;
+pub mod waitqueue;
+
+pub use condvar::Condvar;
+pub use mutex::{Mutex, MutexGuard};
+pub use semaphore::Semaphore;
+pub use waitqueue::WaitQueue;
@@ -0,0 +1,202 @@
+//! A mutex that puts contending threads to sleep.
+//!
+//! Unlike a [`SpinLock`], a [`Mutex`] may be held for a long time, and
+//! across blocking operations. Its owner inherits the scheduling class of
+//! the most urgent thread waiting for it, so that a low priority thread
+//! holding a lock can not keep a high priority one waiting indefinitely
+//! while medium priority work runs.
+//!
+//! If the owner is itself waiting for another mutex, the boost is passed
+//! on to the owner of that one, and so on, up to [`MAX_BOOST_DEPTH`]
+//! owners down the chain.
+
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+};
+
+use crate::process::{sched, thread::{self, Thread}};
+
+use super::spinlock::SpinLock;
+
+/// How many owners down a chain of mutexes a boost is passed on to.
+pub const MAX_BOOST_DEPTH: usize = 8;
+
+struct MutexState {
+    owner: Option<Arc<Thread>>,
+    waiters: Vec<Arc<Thread>>,
+}
+
+pub struct Mutex<T> {
+    state: SpinLock<MutexState>,
+    val: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for Mutex<T> where T: Send {}
+unsafe impl<T> Send for Mutex<T> where T: Send {}
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(val: T) -> Self {
+        Self {
+            state: SpinLock::new(MutexState { owner: None, waiters: Vec::new() }),
+            val: UnsafeCell::new(val),
+        }
+    }
+
+    fn id(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    /// Locks the mutex, sleeping until it is available.
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        let me = thread::current();
+
+        let mut state = self.state.lock();
+        match &state.owner {
+            None => {
+                state.owner = Some(me);
+                return MutexGuard { mutex: self };
+            }
+            Some(owner) => {
+                assert!(!Arc::ptr_eq(owner, &me), "Mutex locked twice by thread {}", me.tid());
+                // Lend our class to the owner, so it gets out of our way quickly.
+                if owner.entity().boost(self.id(), me.entity().effective_class()) {
+                    sched::requeue(owner);
+                    propagate_boost(owner);
+                }
+                *me.blocked_on().lock() = Some((self.id(), owner.clone()));
+            }
+        }
+
+        state.waiters.push(me.clone());
+        sched::prepare_block();
+        drop(state);
+
+        // `unlock` hands the mutex over to us before waking us up.
+        loop {
+            sched::block();
+            let state = self.state.lock();
+            if state.owner.as_ref().is_some_and(|o| Arc::ptr_eq(o, &me)) {
+                return MutexGuard { mutex: self };
+            }
+            // Woken up for something else, go back to sleep.
+            sched::prepare_block();
+        }
+    }
+
+    /// Locks the mutex if it is available.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let mut state = self.state.lock();
+        if state.owner.is_some() {
+            return None;
+        }
+        state.owner = Some(thread::current());
+        Some(MutexGuard { mutex: self })
+    }
+
+    /// Hands the mutex to the most urgent waiter, if there is one.
+    fn unlock(&self) {
+        let mut state = self.state.lock();
+        let Some(owner) = state.owner.take() else {
+            panic!("Unlocking a mutex that is not locked");
+        };
+        let id = self.id();
+
+        let best = (0..state.waiters.len()).max_by_key(|&i| state.waiters[i].entity().rank());
+        let next = best.map(|idx| state.waiters.remove(idx));
+
+        // Point the waiters at their new owner before dropping our boost,
+        // so `propagate_boost` can't boost us for a mutex we gave away.
+        if let Some(next) = &next {
+            *next.blocked_on().lock() = None;
+            for waiter in &state.waiters {
+                *waiter.blocked_on().lock() = Some((id, next.clone()));
+            }
+        }
+        owner.entity().unboost(id);
+
+        if let Some(next) = next {
+            // The new owner is now the one in the way of the others.
+            if let Some(class) = state
+                .waiters
+                .iter()
+                .map(|w| w.entity().effective_class())
+                .reduce(|a, b| if b.outranks(&a) { b } else { a })
+            {
+                next.entity().boost(id, class);
+            }
+
+            state.owner = Some(next.clone());
+            drop(state);
+            sched::wake(&next);
+        } else {
+            drop(state);
+        }
+
+        // We may have woken up someone more urgent than us.
+        sched::resched_if_needed();
+    }
+}
+
+/// Passes the class `owner` was boosted to on to the owner of the mutex
+/// `owner` is waiting for, if any, and so on down the chain.
+fn propagate_boost(owner: &Arc<Thread>) {
+    let mut owner = owner.clone();
+    for _ in 0..MAX_BOOST_DEPTH {
+        // `unlock` only changes this before dropping the boosts of the
+        // owner it names, so that owner keeps the mutex while we hold it.
+        let blocked_on = owner.blocked_on().lock();
+        let Some((id, next)) = blocked_on.as_ref() else {
+            return;
+        };
+        let next = next.clone();
+        let boosted = next.entity().boost(*id, owner.entity().effective_class());
+        if boosted {
+            sched::requeue(&next);
+        }
+        drop(blocked_on);
+
+        // Past a thread that already had this class, the chain has it too.
+        if !boosted {
+            return;
+        }
+        owner = next;
+    }
+}
+
+impl<'a, T> MutexGuard<'a, T> {
+    /// Unlocks the mutex, handing back the mutex itself to lock again later.
+    pub(super) fn unlock(self) -> &'a Mutex<T> {
+        let mutex = self.mutex;
+        drop(self);
+        mutex
+    }
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Safety: The very existence of this Guard
+        // guarantees we own the mutex.
+        unsafe { &*self.mutex.val.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: The very existence of this Guard
+        // guarantees we own the mutex.
+        unsafe { &mut *self.mutex.val.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
@@ -0,0 +1,41 @@
+//! A counting semaphore.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::waitqueue::WaitQueue;
+
+/// Hands out up to a given number of permits at once,
+/// putting threads that ask for more to sleep.
+pub struct Semaphore {
+    permits: AtomicUsize,
+    queue: WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Self { permits: AtomicUsize::new(permits), queue: WaitQueue::new() }
+    }
+
+    /// Takes a permit, sleeping until one is available.
+    pub fn acquire(&self) {
+        self.queue.wait_until(|| self.try_acquire().then_some(()));
+    }
+
+    /// Takes a permit if one is available, returning whether it did.
+    pub fn try_acquire(&self) -> bool {
+        self.permits
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1))
+            .is_ok()
+    }
+
+    /// Gives back a permit, waking up a thread waiting for one.
+    pub fn release(&self) {
+        self.permits.fetch_add(1, Ordering::AcqRel);
+        self.queue.wake_one();
+    }
+
+    /// How many permits are available right now.
+    pub fn available(&self) -> usize {
+        self.permits.load(Ordering::Acquire)
+    }
+}
@@ -5,6 +5,8 @@ use core::{
 
 pub struct Guard<'a, T> {
     lock: &'a SpinLock<T>,
+    /// Whether interrupts were enabled before we took the lock.
+    interrupts: bool,
 }
 
 /// we dont need to require that `T` is `Sync` because our `Guard<T>`
@@ -30,8 +32,12 @@ impl<T> SpinLock<T> {
 
     /// Retreive a reference to the `T` value,
     /// locking the `SpinLock`
+    ///
+    /// Interrupts are disabled for as long as the lock is held, an
+    /// interrupt handler taking the same lock would otherwise deadlock.
     #[inline]
     pub fn lock(&self) -> Guard<T> {
+        let interrupts = crate::cpu::disable_interrupts();
         while self
             .locked
             .swap(true, core::sync::atomic::Ordering::Acquire)
@@ -39,7 +45,7 @@ impl<T> SpinLock<T> {
             core::hint::spin_loop();
         }
 
-        Guard { lock: self }
+        Guard { lock: self, interrupts }
     }
 }
 
@@ -72,6 +78,7 @@ impl<T> Drop for Guard<'_, T> {
         // this to create unforeseen consequences down the line
         // only creates worse and harder-to-debug errors/bugs.
         assert!(prev_val);
+        crate::cpu::restore_interrupts(self.interrupts);
     }
 }
 
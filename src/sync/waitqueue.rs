@@ -0,0 +1,130 @@
+//! Queues of threads sleeping until something happens.
+//!
+//! A waiter passes a condition, which is checked with the queue locked,
+//! and sleeps until it holds. Whoever makes it hold does so first, and
+//! then wakes the queue up, so a wake up can never slip in between a
+//! waiter checking its condition and going to sleep.
+//!
+//! Waits on behalf of user space are interruptible: they give up with
+//! `EINTR` when a signal is sent to the waiting thread, or its process exits.
+
+use alloc::{collections::VecDeque, sync::Arc};
+
+use crate::{
+    process::{sched, signal, thread::{self, Thread}},
+    syscall::{errno::Errno, SysResult},
+};
+
+use super::spinlock::SpinLock;
+
+pub struct WaitQueue {
+    waiters: SpinLock<VecDeque<Arc<Thread>>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self { waiters: SpinLock::new(VecDeque::new()) }
+    }
+
+    /// Sleeps until `cond` returns something, and returns that.
+    ///
+    /// `cond` runs with the queue locked, and interrupts disabled, so it
+    /// must not block. It may take other locks, as long as nobody holding
+    /// those wakes up this queue.
+    pub fn wait_until<T>(&self, cond: impl FnMut() -> Option<T>) -> T {
+        match self.wait(cond, None, false) {
+            Ok(Some(v)) => v,
+            _ => unreachable!("Uninterruptible wait without a deadline gave up"),
+        }
+    }
+
+    /// Like [`Self::wait_until`], but gives up at `deadline` (see
+    /// [`sched::now`]), in which case it returns `None`.
+    pub fn wait_until_deadline<T>(&self, cond: impl FnMut() -> Option<T>, deadline: u64) -> Option<T> {
+        self.wait(cond, Some(deadline), false).ok().flatten()
+    }
+
+    /// Like [`Self::wait_until`], but fails with `EINTR` once the calling
+    /// thread is [`signal::interrupted`]. Waits on behalf of user space,
+    /// which may take forever, use this so signals still get through.
+    pub fn wait_interruptible<T>(&self, cond: impl FnMut() -> Option<T>) -> SysResult<T> {
+        self.wait(cond, None, true).map(|v| v.expect("Wait without a deadline timed out"))
+    }
+
+    /// Like [`Self::wait_until_deadline`], but fails with `EINTR` once the
+    /// calling thread is [`signal::interrupted`].
+    pub fn wait_interruptible_deadline<T>(&self, cond: impl FnMut() -> Option<T>, deadline: u64) -> SysResult<Option<T>> {
+        self.wait(cond, Some(deadline), true)
+    }
+
+    fn wait<T>(&self, mut cond: impl FnMut() -> Option<T>, deadline: Option<u64>, interruptible: bool) -> SysResult<Option<T>> {
+        let me = thread::current();
+        loop {
+            {
+                let mut waiters = self.waiters.lock();
+                if let Some(v) = cond() {
+                    return Ok(Some(v));
+                }
+                if deadline.is_some_and(|deadline| sched::now() >= deadline) {
+                    waiters.retain(|t| !Arc::ptr_eq(t, &me));
+                    return Ok(None);
+                }
+                // We may still be queued if something else woke us up.
+                if !waiters.iter().any(|t| Arc::ptr_eq(t, &me)) {
+                    waiters.push_back(me.clone());
+                }
+                match deadline {
+                    Some(deadline) => sched::prepare_block_until(deadline),
+                    None => sched::prepare_block(),
+                }
+                if interruptible && signal::interrupted() {
+                    match deadline {
+                        Some(deadline) => sched::cancel_block_until(deadline),
+                        None => sched::cancel_block(),
+                    }
+                    waiters.retain(|t| !Arc::ptr_eq(t, &me));
+                    return Err(Errno::EINTR);
+                }
+            }
+            match deadline {
+                Some(deadline) => {
+                    sched::block_until(deadline);
+                }
+                None => sched::block(),
+            }
+        }
+    }
+
+    /// Wakes up the thread that waited longest. Returns whether there was one.
+    pub fn wake_one(&self) -> bool {
+        let thread = self.waiters.lock().pop_front();
+        match thread {
+            Some(thread) => {
+                sched::wake(&thread);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Wakes up every waiting thread, returning how many there were.
+    pub fn wake_all(&self) -> usize {
+        let threads = core::mem::take(&mut *self.waiters.lock());
+        let count = threads.len();
+        for thread in threads {
+            sched::wake(&thread);
+        }
+        count
+    }
+
+    /// Whether no thread is waiting.
+    pub fn is_empty(&self) -> bool {
+        self.waiters.lock().is_empty()
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,391 @@
+//! Files and directories.
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+
+use crate::{
+    drivers::Device,
+    fs::{self, Dentry, File, FileType, Metadata, OpenOptions, Whence, PATH_MAX},
+    process::{
+        self,
+        handle::{Capability, Object, Rights},
+        Process,
+    },
+};
+
+use super::{
+    errno::Errno,
+    ipc::{capability, install, object},
+    proc::Timespec,
+    uaccess::{read_user_str, UserPtr, UserSlice},
+    SysResult,
+};
+
+/// Passed for a directory handle, meaning the working directory.
+const AT_FDCWD: isize = -100;
+const AT_SYMLINK_NOFOLLOW: usize = 0x100;
+/// For `unlinkat`, remove a directory rather than anything else.
+const AT_REMOVEDIR: usize = 0x200;
+const AT_SYMLINK_FOLLOW: usize = 0x400;
+/// An empty path means the file the handle is of.
+const AT_EMPTY_PATH: usize = 0x1000;
+
+const O_ACCMODE: usize = 0o3;
+const O_RDONLY: usize = 0o0;
+const O_WRONLY: usize = 0o1;
+const O_RDWR: usize = 0o2;
+const O_CREAT: usize = 0o100;
+const O_EXCL: usize = 0o200;
+const O_TRUNC: usize = 0o1000;
+const O_APPEND: usize = 0o2000;
+const O_DIRECTORY: usize = 0o200000;
+const O_NOFOLLOW: usize = 0o400000;
+/// Handles have no flags of their own to close them on `execve` with, so
+/// this is accepted and ignored, and files stay open across it.
+const O_CLOEXEC: usize = 0o2000000;
+
+const SEEK_SET: usize = 0;
+const SEEK_CUR: usize = 1;
+const SEEK_END: usize = 2;
+
+/// Major and minor device number of the console, as Linux has `/dev/console`.
+const CONSOLE_RDEV: u64 = (5 << 8) | 1;
+
+/// Linux's `struct stat` on RISC-V.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Stat {
+    dev: u64,
+    ino: u64,
+    mode: u32,
+    nlink: u32,
+    uid: u32,
+    gid: u32,
+    rdev: u64,
+    _pad1: u64,
+    size: i64,
+    blksize: i32,
+    _pad2: i32,
+    blocks: i64,
+    atime: Timespec,
+    mtime: Timespec,
+    ctime: Timespec,
+    _unused: [u32; 2],
+}
+
+impl From<Metadata> for Stat {
+    fn from(m: Metadata) -> Self {
+        Self {
+            dev: m.dev,
+            ino: m.ino,
+            mode: m.kind as u32 | m.mode,
+            nlink: m.nlink,
+            uid: m.uid,
+            gid: m.gid,
+            rdev: m.rdev,
+            _pad1: 0,
+            size: m.size as i64,
+            blksize: 4096,
+            _pad2: 0,
+            blocks: m.blocks as i64,
+            atime: Timespec::from_ticks(m.atime),
+            mtime: Timespec::from_ticks(m.mtime),
+            ctime: Timespec::from_ticks(m.ctime),
+            _unused: [0; 2],
+        }
+    }
+}
+
+/// Header of an entry in the buffer `getdents64` fills, followed by the
+/// NUL terminated name and padding to 8 bytes.
+#[repr(C)]
+struct Dirent64 {
+    ino: u64,
+    off: i64,
+    reclen: u16,
+    kind: u8,
+}
+
+const DIRENT_NAME_OFFSET: usize = 19;
+
+fn current() -> SysResult<Arc<Process>> {
+    process::current_process().ok_or(Errno::ESRCH)
+}
+
+fn path_arg(addr: usize) -> SysResult<String> {
+    read_user_str(addr, PATH_MAX - 1)
+}
+
+/// The open file `fd` is a handle with `rights` to.
+fn file(fd: usize, rights: Rights) -> SysResult<Arc<File>> {
+    match object(fd, rights)? {
+        Object::File(file) => Ok(file),
+        _ => Err(Errno::EBADF),
+    }
+}
+
+/// The directory paths are relative to for `dirfd`: the working directory
+/// for `AT_FDCWD`, or else the directory `dirfd` is a handle of.
+fn dir_arg(dirfd: usize) -> SysResult<Option<Arc<Dentry>>> {
+    if dirfd as isize == AT_FDCWD {
+        return Ok(current()?.cwd());
+    }
+    let dentry = file(dirfd, Rights::NONE)?.dentry().clone();
+    if !dentry.is_dir() {
+        return Err(Errno::ENOTDIR);
+    }
+    Ok(Some(dentry))
+}
+
+/// `openat(dirfd, path, flags, mode)`. Files open for reading give
+/// handles with the right to read, and those open for writing with the
+/// right to write.
+pub fn sys_openat(args: &[usize; 6]) -> SysResult {
+    let (dir, path, flags, mode) = (dir_arg(args[0])?, path_arg(args[1])?, args[2], args[3]);
+    let access = match flags & O_ACCMODE {
+        O_RDONLY => Rights::READ,
+        O_WRONLY => Rights::WRITE,
+        O_RDWR => Rights::READ | Rights::WRITE,
+        _ => return Err(Errno::EINVAL),
+    };
+    let options = OpenOptions {
+        write: access.contains(Rights::WRITE),
+        create: (flags & O_CREAT != 0).then_some(mode as u32),
+        exclusive: flags & O_EXCL != 0,
+        truncate: flags & O_TRUNC != 0,
+        append: flags & O_APPEND != 0,
+        directory: flags & O_DIRECTORY != 0,
+        nofollow: flags & O_NOFOLLOW != 0,
+    };
+    let file = fs::open(dir, &path, &options)?;
+    let rights = access | Rights::DUPLICATE | Rights::TRANSFER;
+    let handles = install([Capability { object: Object::File(file), rights }])?;
+    Ok(handles[0])
+}
+
+/// `lseek(fd, offset, whence)`
+pub fn sys_lseek(args: &[usize; 6]) -> SysResult {
+    let (offset, whence) = (args[1] as i64, args[2]);
+    let whence = match whence {
+        SEEK_SET => Whence::Start,
+        SEEK_CUR => Whence::Current,
+        SEEK_END => Whence::End,
+        _ => return Err(Errno::EINVAL),
+    };
+    let file = match object(args[0], Rights::NONE)? {
+        Object::File(file) => file,
+        _ => return Err(Errno::ESPIPE),
+    };
+    file.seek(offset, whence).map(|offset| offset as usize)
+}
+
+/// What `stat` tells about `object`, made up for those that are not files.
+fn metadata(object: &Object) -> Metadata {
+    let made_up = |kind, rdev| Metadata {
+        dev: 0,
+        ino: 0,
+        kind,
+        mode: 0o600,
+        nlink: 1,
+        uid: 0,
+        gid: 0,
+        rdev,
+        size: 0,
+        blocks: 0,
+        atime: 0,
+        mtime: 0,
+        ctime: 0,
+    };
+    match object {
+        Object::File(file) => file.metadata(),
+        Object::Device(Device::Console) => made_up(FileType::CharDevice, CONSOLE_RDEV),
+        Object::PipeReader(_) | Object::PipeWriter(_) => made_up(FileType::Fifo, 0),
+        // Like Linux's anonymous inodes.
+        _ => made_up(FileType::Regular, 0),
+    }
+}
+
+/// `fstat(fd, statbuf)`
+pub fn sys_fstat(args: &[usize; 6]) -> SysResult {
+    let object = object(args[0], Rights::NONE)?;
+    UserPtr::<Stat>::new(args[1]).write(&metadata(&object).into())?;
+    Ok(0)
+}
+
+/// `newfstatat(dirfd, path, statbuf, flags)`
+pub fn sys_newfstatat(args: &[usize; 6]) -> SysResult {
+    let (path, out, flags) = (path_arg(args[1])?, UserPtr::<Stat>::new(args[2]), args[3]);
+    if flags & !(AT_SYMLINK_NOFOLLOW | AT_EMPTY_PATH) != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let metadata = if path.is_empty() && flags & AT_EMPTY_PATH != 0 {
+        match args[0] as isize {
+            AT_FDCWD => current()?.cwd().map_or_else(fs::root, Ok)?.inode().metadata(),
+            _ => metadata(&object(args[0], Rights::NONE)?),
+        }
+    } else {
+        let dentry = fs::lookup(dir_arg(args[0])?, &path, flags & AT_SYMLINK_NOFOLLOW == 0)?;
+        dentry.inode().metadata()
+    };
+    out.write(&metadata.into())?;
+    Ok(0)
+}
+
+/// `getdents64(fd, dirp, count)`, fills `dirp` with as many entries of the
+/// directory as fit, returning how many bytes they take, 0 at the end.
+pub fn sys_getdents64(args: &[usize; 6]) -> SysResult {
+    let (file, out) = (file(args[0], Rights::READ)?, UserSlice::new(args[1], args[2])?);
+    let mut buf = Vec::new();
+    let mut full = false;
+    while !full {
+        let entry = file.next_entry(|entry| {
+            let reclen = (DIRENT_NAME_OFFSET + entry.name.len() + 1).next_multiple_of(8);
+            full = buf.len() + reclen > out.len();
+            if full {
+                return false;
+            }
+            let header = Dirent64 { ino: entry.ino, off: 0, reclen: reclen as u16, kind: entry.kind.dirent_type() };
+            let start = buf.len();
+            buf.extend_from_slice(&header.ino.to_ne_bytes());
+            buf.extend_from_slice(&header.off.to_ne_bytes());
+            buf.extend_from_slice(&header.reclen.to_ne_bytes());
+            buf.push(header.kind);
+            buf.extend_from_slice(entry.name.as_bytes());
+            buf.resize(start + reclen, 0);
+            true
+        })?;
+        if entry.is_none() {
+            break;
+        }
+    }
+    if full && buf.is_empty() {
+        return Err(Errno::EINVAL);
+    }
+    out.write(&buf)?;
+    Ok(buf.len())
+}
+
+/// `mkdirat(dirfd, path, mode)`
+pub fn sys_mkdirat(args: &[usize; 6]) -> SysResult {
+    let (dir, path, mode) = (dir_arg(args[0])?, path_arg(args[1])?, args[2]);
+    fs::create(dir, &path, FileType::Directory, mode as u32)?;
+    Ok(0)
+}
+
+/// `unlinkat(dirfd, path, flags)`, which removes an empty directory with
+/// `AT_REMOVEDIR` and anything else without.
+pub fn sys_unlinkat(args: &[usize; 6]) -> SysResult {
+    let (dir, path, flags) = (dir_arg(args[0])?, path_arg(args[1])?, args[2]);
+    if flags & !AT_REMOVEDIR != 0 {
+        return Err(Errno::EINVAL);
+    }
+    fs::unlink(dir, &path, flags & AT_REMOVEDIR != 0)?;
+    Ok(0)
+}
+
+/// `symlinkat(target, dirfd, path)`
+pub fn sys_symlinkat(args: &[usize; 6]) -> SysResult {
+    let (target, dir, path) = (path_arg(args[0])?, dir_arg(args[1])?, path_arg(args[2])?);
+    fs::symlink(dir, &path, &target)?;
+    Ok(0)
+}
+
+/// `linkat(olddirfd, oldpath, newdirfd, newpath, flags)`. A symbolic link
+/// at `oldpath` is linked itself unless `AT_SYMLINK_FOLLOW` is set.
+pub fn sys_linkat(args: &[usize; 6]) -> SysResult {
+    let (old_path, new_dir, new_path, flags) = (path_arg(args[1])?, dir_arg(args[2])?, path_arg(args[3])?, args[4]);
+    if flags & !(AT_SYMLINK_FOLLOW | AT_EMPTY_PATH) != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let old = if old_path.is_empty() && flags & AT_EMPTY_PATH != 0 {
+        file(args[0], Rights::NONE)?.dentry().clone()
+    } else {
+        fs::lookup(dir_arg(args[0])?, &old_path, flags & AT_SYMLINK_FOLLOW != 0)?
+    };
+    fs::link(&old, new_dir, &new_path)?;
+    Ok(0)
+}
+
+/// `readlinkat(dirfd, path, buf, size)`, fills `buf` with where the
+/// symbolic link points, cut short to `size` and without a NUL, returning
+/// how much it filled.
+pub fn sys_readlinkat(args: &[usize; 6]) -> SysResult {
+    let (dir, path, size) = (dir_arg(args[0])?, path_arg(args[1])?, args[3]);
+    if size == 0 {
+        return Err(Errno::EINVAL);
+    }
+    let target = fs::lookup(dir, &path, false)?.inode().read_link()?;
+    let n = target.len().min(size);
+    UserSlice::new(args[2], n)?.write(&target.as_bytes()[..n])?;
+    Ok(n)
+}
+
+/// `fchmod(fd, mode)`
+pub fn sys_fchmod(args: &[usize; 6]) -> SysResult {
+    let file = file(args[0], Rights::NONE)?;
+    file.dentry().inode().set_mode(args[1] as u32 & 0o7777)?;
+    Ok(0)
+}
+
+/// `fchmodat(dirfd, path, mode)`, following a symbolic link at `path`.
+pub fn sys_fchmodat(args: &[usize; 6]) -> SysResult {
+    let (dir, path, mode) = (dir_arg(args[0])?, path_arg(args[1])?, args[2]);
+    fs::lookup(dir, &path, true)?.inode().set_mode(mode as u32 & 0o7777)?;
+    Ok(0)
+}
+
+/// `dup(fd)`, another handle to the same object with the same rights,
+/// which needs the right to duplicate it.
+pub fn sys_dup(args: &[usize; 6]) -> SysResult {
+    let capability = capability(args[0], Rights::DUPLICATE)?;
+    let handles = install([capability])?;
+    Ok(handles[0])
+}
+
+/// `dup3(oldfd, newfd, flags)`, like `dup` but to `newfd`, closing what was there.
+pub fn sys_dup3(args: &[usize; 6]) -> SysResult {
+    let (old, new, flags) = (args[0], args[1], args[2]);
+    if flags & !O_CLOEXEC != 0 || old == new {
+        return Err(Errno::EINVAL);
+    }
+    let capability = capability(old, Rights::DUPLICATE)?;
+    let replaced = current()?.handles().insert_at(new, capability);
+    // Whatever was there is dropped outside the lock.
+    match replaced {
+        Ok(_) => Ok(new),
+        Err(_) => Err(Errno::EMFILE),
+    }
+}
+
+/// `chdir(path)`
+pub fn sys_chdir(args: &[usize; 6]) -> SysResult {
+    let process = current()?;
+    let dir = fs::lookup(process.cwd(), &path_arg(args[0])?, true)?;
+    if !dir.is_dir() {
+        return Err(Errno::ENOTDIR);
+    }
+    process.set_cwd(dir);
+    Ok(0)
+}
+
+/// `fchdir(fd)`
+pub fn sys_fchdir(args: &[usize; 6]) -> SysResult {
+    let dir = file(args[0], Rights::NONE)?.dentry().clone();
+    if !dir.is_dir() {
+        return Err(Errno::ENOTDIR);
+    }
+    current()?.set_cwd(dir);
+    Ok(0)
+}
+
+/// `getcwd(buf, size)`, returns the length of the path with its NUL.
+pub fn sys_getcwd(args: &[usize; 6]) -> SysResult {
+    let (buf, size) = (args[0], args[1]);
+    let cwd = current()?.cwd().map_or_else(|| String::from("/"), |d| d.path());
+    let mut path = cwd.into_bytes();
+    path.push(0);
+    if path.len() > size {
+        return Err(Errno::ERANGE);
+    }
+    UserSlice::new(buf, path.len())?.write(&path)?;
+    Ok(path.len())
+}
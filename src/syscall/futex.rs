@@ -0,0 +1,54 @@
+//! Futexes.
+
+use crate::process::{futex, sched};
+
+use super::{errno::Errno, proc::Timespec, uaccess::UserPtr, SysResult};
+
+const FUTEX_WAIT: usize = 0;
+const FUTEX_WAKE: usize = 1;
+const FUTEX_REQUEUE: usize = 3;
+const FUTEX_CMP_REQUEUE: usize = 4;
+const FUTEX_WAIT_BITSET: usize = 9;
+const FUTEX_WAKE_BITSET: usize = 10;
+
+/// Says the futex is not shared with other processes, which we do not
+/// need to know since every futex is keyed by its physical address.
+const FUTEX_PRIVATE_FLAG: usize = 128;
+/// Says the timeout is on the real time clock. We only have the one clock.
+const FUTEX_CLOCK_REALTIME: usize = 256;
+
+/// When a wait with the timeout at `addr` should end, if ever.
+/// Timeouts are `relative` to now, or else time since boot.
+fn deadline(addr: usize, relative: bool) -> SysResult<Option<u64>> {
+    let timeout = UserPtr::<Timespec>::new(addr);
+    if timeout.is_null() {
+        return Ok(None);
+    }
+    let ticks = timeout.read()?.ticks()?;
+    Ok(Some(if relative { sched::now() + ticks } else { ticks }))
+}
+
+/// A count passed as an `int`, negative ones counting for none.
+fn count(arg: usize) -> usize {
+    (arg as i32).max(0) as usize
+}
+
+/// `futex(uaddr, op, val, timeout or val2, uaddr2, val3)`
+pub fn sys_futex(args: &[usize; 6]) -> SysResult {
+    let (uaddr, val) = (args[0], args[2]);
+    match args[1] & !(FUTEX_PRIVATE_FLAG | FUTEX_CLOCK_REALTIME) {
+        FUTEX_WAIT => {
+            futex::wait(uaddr, val as u32, futex::BITSET_MATCH_ANY, deadline(args[3], true)?)?;
+            Ok(0)
+        }
+        FUTEX_WAIT_BITSET => {
+            futex::wait(uaddr, val as u32, args[5] as u32, deadline(args[3], false)?)?;
+            Ok(0)
+        }
+        FUTEX_WAKE => futex::wake(uaddr, count(val), futex::BITSET_MATCH_ANY),
+        FUTEX_WAKE_BITSET => futex::wake(uaddr, count(val), args[5] as u32),
+        FUTEX_REQUEUE => futex::requeue(uaddr, args[4], count(val), count(args[3]), None),
+        FUTEX_CMP_REQUEUE => futex::requeue(uaddr, args[4], count(val), count(args[3]), Some(args[5] as u32)),
+        _ => Err(Errno::ENOSYS),
+    }
+}
@@ -0,0 +1,121 @@
+//! Reading and writing.
+
+use alloc::vec;
+
+use crate::{
+    drivers::{self, Device},
+    fs::File,
+    ipc::{PipeReader, PipeWriter},
+    process::{
+        handle::{Handle, Object, Rights},
+        signal, thread,
+        timer::Timer,
+    },
+};
+
+use super::{errno::Errno, ipc::object, uaccess::UserSlice, SysResult};
+
+/// Largest chunk we copy out of user memory at once.
+const CHUNK: usize = 4096;
+
+/// The object `fd` is a handle with `rights` to. Like for files not open
+/// for reading or writing, a handle without them is a bad one.
+fn file(fd: Handle, rights: Rights) -> SysResult<Object> {
+    object(fd, rights).map_err(|_| Errno::EBADF)
+}
+
+/// `read(fd, buf, len)`, from a file, the console, a pipe or a timer.
+pub fn sys_read(args: &[usize; 6]) -> SysResult {
+    let (fd, buf, len) = (args[0], UserSlice::new(args[1], args[2])?, args[2]);
+    match file(fd, Rights::READ)? {
+        Object::File(file) => read_file(&file, buf, len),
+        Object::Device(Device::Console) => read_console(buf, len),
+        Object::PipeReader(reader) => read_pipe(&reader, buf, len),
+        Object::Timer(timer) => read_timer(&timer, buf, len),
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+fn read_file(file: &File, buf: UserSlice, len: usize) -> SysResult {
+    let mut data = vec![0; len.min(CHUNK)];
+    let n = file.read(&mut data)?;
+    buf.write(&data[..n])?;
+    Ok(n)
+}
+
+fn read_console(buf: UserSlice, len: usize) -> SysResult {
+    if len == 0 {
+        return Ok(0);
+    }
+
+    // Wait for something to show up, then take what is there.
+    let mut data = vec![drivers::console_read_wait()?];
+    while data.len() < len.min(CHUNK) {
+        match drivers::console_read() {
+            Some(b) => data.push(b),
+            None => break,
+        }
+    }
+    buf.write(&data)?;
+    Ok(data.len())
+}
+
+fn read_pipe(reader: &PipeReader, buf: UserSlice, len: usize) -> SysResult {
+    let mut data = vec![0; len.min(CHUNK)];
+    let n = reader.read(&mut data)?;
+    buf.write(&data[..n])?;
+    Ok(n)
+}
+
+/// The number of times `timer` expired since the last read, as a `u64`,
+/// waiting for it to expire at least once.
+fn read_timer(timer: &Timer, buf: UserSlice, len: usize) -> SysResult {
+    if len < size_of::<u64>() {
+        return Err(Errno::EINVAL);
+    }
+    buf.write(&timer.wait()?.to_ne_bytes())?;
+    Ok(size_of::<u64>())
+}
+
+/// `write(fd, buf, len)`, to a file, the console or a pipe.
+pub fn sys_write(args: &[usize; 6]) -> SysResult {
+    let (fd, buf, len) = (args[0], UserSlice::new(args[1], args[2])?, args[2]);
+    match file(fd, Rights::WRITE)? {
+        Object::File(file) => write_chunks(buf, len, |chunk| file.write(chunk)),
+        Object::Device(Device::Console) => write_chunks(buf, len, |chunk| {
+            drivers::console_write(chunk);
+            Ok(chunk.len())
+        }),
+        Object::PipeWriter(writer) => write_pipe(&writer, buf, len),
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+fn write_pipe(writer: &PipeWriter, buf: UserSlice, len: usize) -> SysResult {
+    let written = write_chunks(buf, len, |chunk| writer.write(chunk));
+    if written == Err(Errno::EPIPE) {
+        signal::send_to_thread(&thread::current(), signal::SIGPIPE);
+    }
+    written
+}
+
+/// Copies `buf` out of user memory a chunk at a time, handing each to
+/// `write`, until all of it is written or `write` comes up short.
+fn write_chunks(buf: UserSlice, len: usize, mut write: impl FnMut(&[u8]) -> SysResult<usize>) -> SysResult {
+    let mut chunk = vec![0; len.min(CHUNK)];
+    let mut done = 0;
+    while done < len {
+        let n = (len - done).min(CHUNK);
+        buf.subslice(done, n)?.read(&mut chunk[..n])?;
+        let written = match write(&chunk[..n]) {
+            Ok(written) => written,
+            Err(_) if done > 0 => break,
+            Err(e) => return Err(e),
+        };
+        done += written;
+        if written < n {
+            break;
+        }
+    }
+    Ok(done)
+}
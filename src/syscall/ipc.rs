@@ -0,0 +1,207 @@
+//! Pipes, channels, and the handles processes hold them by.
+//!
+//! A handle without the rights a call needs fails it with `EACCES`.
+
+use alloc::vec::Vec;
+
+use crate::{
+    ipc::{self, channel::{MAX_MESSAGE_HANDLES, MAX_MESSAGE_SIZE}, Message},
+    process::{
+        self,
+        handle::{Capability, Handle, Object, Rights},
+    },
+};
+
+use super::{
+    errno::Errno,
+    uaccess::{UserPtr, UserSlice},
+    SysResult,
+};
+
+/// Don't wait for a message, or for room to send one.
+const CHANNEL_NONBLOCK: usize = 1;
+
+/// Passed to `handle_duplicate` for a handle with the same rights.
+const SAME_RIGHTS: usize = usize::MAX;
+
+/// A message in user memory, as passed to `channel_send` and `channel_recv`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct MessageHeader {
+    data: usize,
+    data_len: usize,
+    /// Array of `u32` handles.
+    handles: usize,
+    handles_len: usize,
+}
+
+/// What the calling process holds as `handle`, if it has `rights` to it.
+pub(super) fn capability(handle: Handle, rights: Rights) -> SysResult<Capability> {
+    let process = process::current_process().ok_or(Errno::EBADF)?;
+    let capability = process.handles().get(handle).cloned().ok_or(Errno::EBADF)?;
+    if !capability.rights.contains(rights) {
+        return Err(Errno::EACCES);
+    }
+    Ok(capability)
+}
+
+/// The object the calling process holds as `handle`, if it has `rights` to it.
+pub(super) fn object(handle: Handle, rights: Rights) -> SysResult<Object> {
+    capability(handle, rights).map(|c| c.object)
+}
+
+/// Adds `capabilities` to the calling process' handle table, returning their
+/// handles, or fails with `EMFILE` if they would take it past its limit.
+pub(super) fn install(capabilities: impl IntoIterator<Item = Capability>) -> SysResult<Vec<Handle>> {
+    let process = process::current_process().ok_or(Errno::EBADF)?;
+    let capabilities = capabilities.into_iter().collect();
+    let installed = process.handles().insert_all(capabilities);
+    // Those that did not fit are dropped outside the lock.
+    installed.map_err(|_| Errno::EMFILE)
+}
+
+/// Adds `objects` to the calling process' handle table and tells it their
+/// handles with `report`, taking them back out if that fails.
+fn install_reported(objects: [Object; 2], report: impl FnOnce([Handle; 2]) -> SysResult<()>) -> SysResult {
+    let handles = install(objects.map(Capability::new))?;
+    let handles = [handles[0], handles[1]];
+    if let Err(e) = report(handles) {
+        let process = process::current_process().ok_or(e)?;
+        let removed: Vec<_> = {
+            let mut table = process.handles();
+            handles.iter().map(|&h| table.remove(h)).collect()
+        };
+        drop(removed);
+        return Err(e);
+    }
+    Ok(0)
+}
+
+/// `pipe2(fds, flags)`, the read end goes in `fds[0]` and the write end in `fds[1]`.
+pub fn sys_pipe2(args: &[usize; 6]) -> SysResult {
+    let (fds, flags) = (UserPtr::<[i32; 2]>::new(args[0]), args[1]);
+    if flags != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let (reader, writer) = ipc::pipe();
+    install_reported([Object::PipeReader(reader), Object::PipeWriter(writer)], |[r, w]| {
+        fds.write(&[r as i32, w as i32])
+    })
+}
+
+/// `close(fd)`
+pub fn sys_close(args: &[usize; 6]) -> SysResult {
+    let process = process::current_process().ok_or(Errno::EBADF)?;
+    let object = process.handles().remove(args[0]);
+    // Dropped outside the lock, as closing may wake others up.
+    object.map(|_| 0).ok_or(Errno::EBADF)
+}
+
+/// `channel_create(handles)`, puts the handles of both ends in `handles[0..2]`.
+pub fn sys_channel_create(args: &[usize; 6]) -> SysResult {
+    let out = UserPtr::<[u32; 2]>::new(args[0]);
+    let (a, b) = ipc::channel();
+    install_reported([Object::Channel(a), Object::Channel(b)], |[a, b]| out.write(&[a as u32, b as u32]))
+}
+
+fn endpoint(handle: Handle, rights: Rights) -> SysResult<ipc::Endpoint> {
+    match object(handle, rights)? {
+        Object::Channel(endpoint) => Ok(endpoint),
+        _ => Err(Errno::EBADF),
+    }
+}
+
+/// `channel_send(handle, msg, flags)`, sends the message `msg` describes.
+/// The handles it carries, which need the right to be transferred, are
+/// gone from the caller's table once it is sent.
+pub fn sys_channel_send(args: &[usize; 6]) -> SysResult {
+    let (endpoint, header, flags) = (endpoint(args[0], Rights::WRITE)?, UserPtr::<MessageHeader>::new(args[1]).read()?, args[2]);
+    if flags & !CHANNEL_NONBLOCK != 0 {
+        return Err(Errno::EINVAL);
+    }
+    if header.data_len > MAX_MESSAGE_SIZE || header.handles_len > MAX_MESSAGE_HANDLES {
+        return Err(Errno::EMSGSIZE);
+    }
+
+    let data = UserSlice::new(header.data, header.data_len)?.read_to_vec()?;
+    let mut handles = Vec::with_capacity(header.handles_len);
+    for i in 0..header.handles_len {
+        let handle = UserPtr::<u32>::new(header.handles).add(i)?.read()? as Handle;
+        if handles.iter().any(|&(h, _)| h == handle) {
+            return Err(Errno::EINVAL);
+        }
+        handles.push((handle, capability(handle, Rights::TRANSFER)?));
+    }
+
+    // The message carries copies, so the handles stay valid if sending fails.
+    let msg = Message { data, handles: handles.iter().map(|(_, o)| o.clone()).collect() };
+    if flags & CHANNEL_NONBLOCK != 0 {
+        endpoint.try_send(msg)?;
+    } else {
+        endpoint.send(msg)?;
+    }
+
+    let process = process::current_process().ok_or(Errno::EBADF)?;
+    let removed: Vec<_> = {
+        let mut table = process.handles();
+        handles.iter().map(|&(h, _)| table.remove(h)).collect()
+    };
+    drop(removed);
+    Ok(0)
+}
+
+/// `channel_recv(handle, msg, flags)`, receives a message into the buffers
+/// `msg` describes, updating it with the size of the message. Fails with
+/// `EMSGSIZE` if the buffers are too small, still updating the sizes.
+pub fn sys_channel_recv(args: &[usize; 6]) -> SysResult {
+    let endpoint = endpoint(args[0], Rights::READ)?;
+    let (header_ptr, flags) = (UserPtr::<MessageHeader>::new(args[1]), args[2]);
+    if flags & !CHANNEL_NONBLOCK != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let mut header = header_ptr.read()?;
+    let data = UserSlice::new(header.data, header.data_len)?;
+
+    let msg = match endpoint.recv_max(header.data_len, header.handles_len, flags & CHANNEL_NONBLOCK == 0) {
+        Ok(msg) => msg,
+        Err(Errno::EMSGSIZE) => {
+            if let Some((data_len, handles_len)) = endpoint.peek_size() {
+                header_ptr.write(&MessageHeader { data_len, handles_len, ..header })?;
+            }
+            return Err(Errno::EMSGSIZE);
+        }
+        Err(e) => return Err(e),
+    };
+
+    // The message is ours now, so bad buffers, or no room for its handles, lose it.
+    data.write(&msg.data)?;
+    header.data_len = msg.data.len();
+    header.handles_len = msg.handles.len();
+    let handles = install(msg.handles)?;
+    for (i, handle) in handles.into_iter().enumerate() {
+        UserPtr::<u32>::new(header.handles).add(i)?.write(&(handle as u32))?;
+    }
+    header_ptr.write(&header)?;
+    Ok(0)
+}
+
+/// `handle_duplicate(handle, rights)`, makes another handle to the same
+/// object with `rights`, which can not be more than those of `handle`, or
+/// the same ones if `rights` is all ones.
+pub fn sys_handle_duplicate(args: &[usize; 6]) -> SysResult {
+    let capability = capability(args[0], Rights::DUPLICATE)?;
+    let rights = match args[1] {
+        SAME_RIGHTS => capability.rights,
+        bits => u32::try_from(bits).ok().and_then(Rights::from_bits).ok_or(Errno::EINVAL)?,
+    };
+    if !capability.rights.contains(rights) {
+        return Err(Errno::EINVAL);
+    }
+    let handles = install([Capability { rights, ..capability }])?;
+    Ok(handles[0])
+}
+
+/// `handle_rights(handle)`, what the caller may do with `handle`.
+pub fn sys_handle_rights(args: &[usize; 6]) -> SysResult {
+    capability(args[0], Rights::NONE).map(|c| c.rights.bits() as usize)
+}
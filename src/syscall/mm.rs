@@ -0,0 +1,163 @@
+//! Memory management.
+
+use alloc::sync::Arc;
+
+use crate::{
+    mem::{
+        pages::PAGE_SIZE,
+        shm::SharedMemory,
+        space::{self, user_flags},
+        table,
+        vma::Vma,
+    },
+    process::{
+        current_process,
+        handle::{Capability, Object, Rights},
+    },
+};
+
+use super::{
+    errno::Errno,
+    ipc::{install, object},
+    SysResult,
+};
+
+const PROT_READ: usize = 0x1;
+const PROT_WRITE: usize = 0x2;
+const PROT_EXEC: usize = 0x4;
+
+const MAP_SHARED: usize = 0x01;
+const MAP_PRIVATE: usize = 0x02;
+const MAP_FIXED: usize = 0x10;
+const MAP_ANONYMOUS: usize = 0x20;
+
+fn prot_flags(prot: usize) -> SysResult {
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return Err(Errno::EINVAL);
+    }
+    Ok(user_flags(prot & PROT_READ != 0, prot & PROT_WRITE != 0, prot & PROT_EXEC != 0))
+}
+
+/// Checks `addr` and `len` describe whole pages of user memory,
+/// returning `len` rounded up to pages.
+fn page_range(addr: usize, len: usize) -> SysResult {
+    if !addr.is_multiple_of(PAGE_SIZE) || len == 0 {
+        return Err(Errno::EINVAL);
+    }
+    let len = len.checked_next_multiple_of(PAGE_SIZE).ok_or(Errno::EINVAL)?;
+    if !space::is_user_range(addr, len) {
+        return Err(Errno::EINVAL);
+    }
+    Ok(len)
+}
+
+/// `brk(addr)`, returns the current break when `addr` is 0 or can't be used.
+pub fn sys_brk(args: &[usize; 6]) -> SysResult {
+    let process = current_process().ok_or(Errno::ESRCH)?;
+    let current = process.heap().end;
+    if args[0] == 0 {
+        return Ok(current);
+    }
+    Ok(process.set_break(args[0]).unwrap_or(current))
+}
+
+/// The shared memory object `fd` is a handle with `rights` to, checking
+/// that `len` bytes from `offset` on are within it.
+fn shm_arg(fd: usize, rights: Rights, offset: usize, len: usize) -> SysResult<Arc<SharedMemory>> {
+    let Object::SharedMemory(shm) = object(fd, rights)? else {
+        return Err(Errno::ENODEV);
+    };
+    if !offset.is_multiple_of(PAGE_SIZE) || offset.checked_add(len).is_none_or(|end| end > shm.size()) {
+        return Err(Errno::EINVAL);
+    }
+    Ok(shm)
+}
+
+/// `mmap(addr, len, prot, flags, fd, offset)`, of anonymous memory,
+/// or of the shared memory object `fd` is a handle of. Shared writable
+/// mappings need the right to write to it, any other the right to read.
+pub fn sys_mmap(args: &[usize; 6]) -> SysResult {
+    let (addr, len, prot, flags, fd, offset) = (args[0], args[1], args[2], args[3], args[4], args[5]);
+    let shared = match flags & (MAP_SHARED | MAP_PRIVATE) {
+        MAP_SHARED => true,
+        MAP_PRIVATE => false,
+        _ => return Err(Errno::EINVAL),
+    };
+    if len == 0 {
+        return Err(Errno::EINVAL);
+    }
+    let len = len.checked_next_multiple_of(PAGE_SIZE).ok_or(Errno::ENOMEM)?;
+    let perms = prot_flags(prot)?;
+    let shm = match flags & MAP_ANONYMOUS {
+        0 => {
+            let rights = match shared && prot & PROT_WRITE != 0 {
+                true => Rights::READ | Rights::WRITE,
+                false => Rights::READ,
+            };
+            Some(shm_arg(fd, rights, offset, len)?)
+        }
+        _ => None,
+    };
+    let process = current_process().ok_or(Errno::ESRCH)?;
+
+    let mut space = process.space();
+    let fixed = flags & MAP_FIXED != 0;
+    let start = if fixed {
+        page_range(addr, len)?;
+        // Whatever was there before is replaced.
+        space.free(addr, len);
+        addr
+    } else {
+        space.find_free(len).ok_or(Errno::ENOMEM)?
+    };
+
+    let end = start + len;
+    let result = match (shm, shared) {
+        (Some(shm), true) => space.map_area(Vma::shared_memory(start, end, perms, shm, offset)),
+        (Some(shm), false) => space.map_area(Vma::file(start, end, perms, shm, offset)),
+        // Forked children share the pages that are there when they fork,
+        // so they all have to be.
+        (None, true) => space.alloc_area(Vma::anonymous(start, end, perms).shared()),
+        (None, false) => space.map_area(Vma::anonymous(start, end, perms)),
+    };
+    drop(space);
+
+    if fixed {
+        table::shootdown(start, len);
+    }
+    result.map(|_| start).map_err(|_| Errno::ENOMEM)
+}
+
+/// `munmap(addr, len)`, which may cover parts of mappings.
+pub fn sys_munmap(args: &[usize; 6]) -> SysResult {
+    let (addr, len) = (args[0], args[1]);
+    let len = page_range(addr, len)?;
+
+    let process = current_process().ok_or(Errno::ESRCH)?;
+    process.space().free(addr, len);
+    table::shootdown(addr, len);
+    Ok(0)
+}
+
+/// `mprotect(addr, len, prot)`
+pub fn sys_mprotect(args: &[usize; 6]) -> SysResult {
+    let (addr, len, prot) = (args[0], args[1], args[2]);
+    let len = page_range(addr, len)?;
+    let perms = prot_flags(prot)?;
+
+    let process = current_process().ok_or(Errno::ESRCH)?;
+    let result = process.space().protect(addr, len, perms);
+    table::shootdown(addr, len);
+    result.map(|_| 0).map_err(|_| Errno::ENOMEM)
+}
+
+/// `shm_create(size)`, creates a shared memory object of `size` bytes,
+/// rounded up to pages, returning a handle to map it with `mmap`.
+pub fn sys_shm_create(args: &[usize; 6]) -> SysResult {
+    if args[0] == 0 {
+        return Err(Errno::EINVAL);
+    }
+    let shm = SharedMemory::new(args[0]).map_err(|_| Errno::ENOMEM)?;
+    let handles = install([Capability::new(Object::SharedMemory(shm))])?;
+    Ok(handles[0])
+}
@@ -0,0 +1,165 @@
+//! System calls.
+//!
+//! User code asks for a system call with `ecall`, passing its number in
+//! `a7` and up to six arguments in `a0`-`a5`. The result comes back in
+//! `a0`: either a value, or a negated [`Errno`]. Numbers and errors follow
+//! Linux on RISC-V, so existing toolchains and libc ports work as-is.
+
+use crate::{cpu, cpu::trap::TrapFrame};
+
+use self::errno::Errno;
+
+pub mod errno;
+mod fs;
+mod futex;
+mod io;
+mod ipc;
+mod mm;
+mod proc;
+mod resource;
+mod signal;
+mod timer;
+pub mod uaccess;
+
+pub type SysResult<T = usize> = core::result::Result<T, Errno>;
+
+pub const SYS_GETCWD: usize = 17;
+pub const SYS_DUP: usize = 23;
+pub const SYS_DUP3: usize = 24;
+pub const SYS_MKDIRAT: usize = 34;
+pub const SYS_UNLINKAT: usize = 35;
+pub const SYS_SYMLINKAT: usize = 36;
+pub const SYS_LINKAT: usize = 37;
+pub const SYS_CHDIR: usize = 49;
+pub const SYS_FCHDIR: usize = 50;
+pub const SYS_FCHMOD: usize = 52;
+pub const SYS_FCHMODAT: usize = 53;
+pub const SYS_OPENAT: usize = 56;
+pub const SYS_CLOSE: usize = 57;
+pub const SYS_PIPE2: usize = 59;
+pub const SYS_GETDENTS64: usize = 61;
+pub const SYS_LSEEK: usize = 62;
+pub const SYS_READ: usize = 63;
+pub const SYS_WRITE: usize = 64;
+pub const SYS_READLINKAT: usize = 78;
+pub const SYS_NEWFSTATAT: usize = 79;
+pub const SYS_FSTAT: usize = 80;
+pub const SYS_TIMERFD_CREATE: usize = 85;
+pub const SYS_TIMERFD_SETTIME: usize = 86;
+pub const SYS_TIMERFD_GETTIME: usize = 87;
+pub const SYS_EXIT: usize = 93;
+pub const SYS_EXIT_GROUP: usize = 94;
+pub const SYS_FUTEX: usize = 98;
+pub const SYS_NANOSLEEP: usize = 101;
+pub const SYS_SCHED_YIELD: usize = 124;
+pub const SYS_KILL: usize = 129;
+pub const SYS_TKILL: usize = 130;
+pub const SYS_TGKILL: usize = 131;
+pub const SYS_RT_SIGACTION: usize = 134;
+pub const SYS_RT_SIGPROCMASK: usize = 135;
+pub const SYS_RT_SIGPENDING: usize = 136;
+pub const SYS_RT_SIGRETURN: usize = 139;
+pub const SYS_GETRUSAGE: usize = 165;
+pub const SYS_GETPID: usize = 172;
+pub const SYS_GETPPID: usize = 173;
+pub const SYS_BRK: usize = 214;
+pub const SYS_MUNMAP: usize = 215;
+pub const SYS_CLONE: usize = 220;
+pub const SYS_EXECVE: usize = 221;
+pub const SYS_MMAP: usize = 222;
+pub const SYS_MPROTECT: usize = 226;
+pub const SYS_WAIT4: usize = 260;
+pub const SYS_PRLIMIT64: usize = 261;
+pub const SYS_PIDFD_SEND_SIGNAL: usize = 424;
+pub const SYS_PIDFD_OPEN: usize = 434;
+
+// Walnut's own system calls, numbered past Linux's.
+pub const SYS_CHANNEL_CREATE: usize = 512;
+pub const SYS_CHANNEL_SEND: usize = 513;
+pub const SYS_CHANNEL_RECV: usize = 514;
+pub const SYS_SHM_CREATE: usize = 515;
+pub const SYS_HANDLE_DUPLICATE: usize = 516;
+pub const SYS_HANDLE_RIGHTS: usize = 517;
+
+type Handler = fn(&[usize; 6]) -> SysResult;
+
+const SYSCALL_COUNT: usize = 1024;
+
+static SYSCALLS: [Option<Handler>; SYSCALL_COUNT] = {
+    let mut table: [Option<Handler>; SYSCALL_COUNT] = [None; SYSCALL_COUNT];
+    table[SYS_GETCWD] = Some(fs::sys_getcwd);
+    table[SYS_DUP] = Some(fs::sys_dup);
+    table[SYS_DUP3] = Some(fs::sys_dup3);
+    table[SYS_MKDIRAT] = Some(fs::sys_mkdirat);
+    table[SYS_UNLINKAT] = Some(fs::sys_unlinkat);
+    table[SYS_SYMLINKAT] = Some(fs::sys_symlinkat);
+    table[SYS_LINKAT] = Some(fs::sys_linkat);
+    table[SYS_CHDIR] = Some(fs::sys_chdir);
+    table[SYS_FCHDIR] = Some(fs::sys_fchdir);
+    table[SYS_FCHMOD] = Some(fs::sys_fchmod);
+    table[SYS_FCHMODAT] = Some(fs::sys_fchmodat);
+    table[SYS_OPENAT] = Some(fs::sys_openat);
+    table[SYS_CLOSE] = Some(ipc::sys_close);
+    table[SYS_PIPE2] = Some(ipc::sys_pipe2);
+    table[SYS_GETDENTS64] = Some(fs::sys_getdents64);
+    table[SYS_LSEEK] = Some(fs::sys_lseek);
+    table[SYS_READ] = Some(io::sys_read);
+    table[SYS_WRITE] = Some(io::sys_write);
+    table[SYS_READLINKAT] = Some(fs::sys_readlinkat);
+    table[SYS_NEWFSTATAT] = Some(fs::sys_newfstatat);
+    table[SYS_FSTAT] = Some(fs::sys_fstat);
+    table[SYS_TIMERFD_CREATE] = Some(timer::sys_timerfd_create);
+    table[SYS_TIMERFD_SETTIME] = Some(timer::sys_timerfd_settime);
+    table[SYS_TIMERFD_GETTIME] = Some(timer::sys_timerfd_gettime);
+    table[SYS_EXIT] = Some(proc::sys_exit);
+    table[SYS_EXIT_GROUP] = Some(proc::sys_exit_group);
+    table[SYS_FUTEX] = Some(futex::sys_futex);
+    table[SYS_NANOSLEEP] = Some(proc::sys_nanosleep);
+    table[SYS_SCHED_YIELD] = Some(proc::sys_sched_yield);
+    table[SYS_KILL] = Some(signal::sys_kill);
+    table[SYS_TKILL] = Some(signal::sys_tkill);
+    table[SYS_TGKILL] = Some(signal::sys_tgkill);
+    table[SYS_RT_SIGACTION] = Some(signal::sys_rt_sigaction);
+    table[SYS_RT_SIGPROCMASK] = Some(signal::sys_rt_sigprocmask);
+    table[SYS_RT_SIGPENDING] = Some(signal::sys_rt_sigpending);
+    table[SYS_RT_SIGRETURN] = Some(signal::sys_rt_sigreturn);
+    table[SYS_GETRUSAGE] = Some(resource::sys_getrusage);
+    table[SYS_GETPID] = Some(proc::sys_getpid);
+    table[SYS_GETPPID] = Some(proc::sys_getppid);
+    table[SYS_BRK] = Some(mm::sys_brk);
+    table[SYS_MUNMAP] = Some(mm::sys_munmap);
+    table[SYS_CLONE] = Some(proc::sys_clone);
+    table[SYS_EXECVE] = Some(proc::sys_execve);
+    table[SYS_MMAP] = Some(mm::sys_mmap);
+    table[SYS_MPROTECT] = Some(mm::sys_mprotect);
+    table[SYS_WAIT4] = Some(proc::sys_wait4);
+    table[SYS_PRLIMIT64] = Some(resource::sys_prlimit64);
+    table[SYS_PIDFD_SEND_SIGNAL] = Some(signal::sys_pidfd_send_signal);
+    table[SYS_PIDFD_OPEN] = Some(proc::sys_pidfd_open);
+    table[SYS_CHANNEL_CREATE] = Some(ipc::sys_channel_create);
+    table[SYS_CHANNEL_SEND] = Some(ipc::sys_channel_send);
+    table[SYS_CHANNEL_RECV] = Some(ipc::sys_channel_recv);
+    table[SYS_SHM_CREATE] = Some(mm::sys_shm_create);
+    table[SYS_HANDLE_DUPLICATE] = Some(ipc::sys_handle_duplicate);
+    table[SYS_HANDLE_RIGHTS] = Some(ipc::sys_handle_rights);
+    table
+};
+
+/// Runs the system call the user thread that trapped with `frame` asked for.
+pub fn dispatch(frame: &mut TrapFrame) {
+    let number = frame.regs[TrapFrame::A7];
+    let args = core::array::from_fn(|i| frame.arg(i));
+
+    // System calls may take a while, let the timer preempt them.
+    cpu::enable_interrupts();
+    let result = match SYSCALLS.get(number).copied().flatten() {
+        Some(handler) => handler(&args),
+        None => Err(Errno::ENOSYS),
+    };
+    cpu::disable_interrupts();
+
+    frame.set_arg(0, match result {
+        Ok(v) => v,
+        Err(e) => e.as_return(),
+    });
+}
@@ -0,0 +1,204 @@
+//! Process and thread management.
+
+use alloc::{string::String, vec::Vec};
+
+use crate::{
+    fs::{self, FileType, OpenOptions, PATH_MAX},
+    mem::space::USER_STACK_SIZE,
+    process::{
+        self,
+        handle::{Capability, Object},
+        limits::RLIMIT_NPROC,
+        sched,
+        signal::SIGCHLD,
+        Process,
+        WaitStatus,
+    },
+};
+
+use super::{
+    errno::Errno,
+    ipc::install,
+    uaccess::{read_user_str, UserPtr},
+    SysResult,
+};
+
+const WNOHANG: usize = 1;
+
+/// How much of the new program's stack the arguments and environment
+/// of `execve` may take, strings and pointers to them, leaving it the rest.
+const ARG_MAX: usize = USER_STACK_SIZE / 2;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Timespec {
+    sec: i64,
+    nsec: i64,
+}
+
+impl Timespec {
+    /// The time in timer ticks, failing for negative or malformed times.
+    pub(super) fn ticks(&self) -> SysResult<u64> {
+        if self.sec < 0 || !(0..1_000_000_000).contains(&self.nsec) {
+            return Err(Errno::EINVAL);
+        }
+        Ok(self.sec as u64 * sched::TIMEBASE_FREQ + self.nsec as u64 * sched::TIMEBASE_FREQ / 1_000_000_000)
+    }
+
+    /// The time `ticks` timer ticks make.
+    pub(super) fn from_ticks(ticks: u64) -> Self {
+        let sec = ticks / sched::TIMEBASE_FREQ;
+        let nsec = ticks % sched::TIMEBASE_FREQ * 1_000_000_000 / sched::TIMEBASE_FREQ;
+        Self { sec: sec as i64, nsec: nsec as i64 }
+    }
+}
+
+/// `exit(code)`, ends the calling thread.
+pub fn sys_exit(args: &[usize; 6]) -> SysResult {
+    process::exit_thread(args[0] as i32);
+}
+
+/// `exit_group(code)`, ends the whole process.
+pub fn sys_exit_group(args: &[usize; 6]) -> SysResult {
+    process::exit_current(args[0] as i32);
+}
+
+/// `getpid()`
+pub fn sys_getpid(_args: &[usize; 6]) -> SysResult {
+    process::current_process().map(|p| p.pid()).ok_or(Errno::ESRCH)
+}
+
+/// `getppid()`
+pub fn sys_getppid(_args: &[usize; 6]) -> SysResult {
+    process::current_process().map(|p| p.parent()).ok_or(Errno::ESRCH)
+}
+
+/// `clone(flags, stack, ...)`, only as `fork`, which is
+/// just `SIGCHLD` as the signal to get when the child exits.
+pub fn sys_clone(args: &[usize; 6]) -> SysResult {
+    let (flags, stack) = (args[0], args[1]);
+    if flags != SIGCHLD || stack != 0 {
+        return Err(Errno::EINVAL);
+    }
+    // The child inherits our limits, so its thread would be over them.
+    let process = process::current_process().ok_or(Errno::ESRCH)?;
+    if process.limits().get(RLIMIT_NPROC)?.cur == 0 {
+        return Err(Errno::EAGAIN);
+    }
+    process::fork().map(|child| child.pid()).map_err(|_| Errno::ENOMEM)
+}
+
+/// `execve(path, argv, envp)`, runs the program at `path` in place of the
+/// calling one, which has to be down to the calling thread. Handles stay
+/// open, and signals that were handled go back to their default action.
+pub fn sys_execve(args: &[usize; 6]) -> SysResult {
+    let path = read_user_str(args[0], PATH_MAX - 1)?;
+    let mut budget = ARG_MAX;
+    let argv = string_array(args[1], &mut budget)?;
+    let envp = string_array(args[2], &mut budget)?;
+
+    let data = {
+        let process = process::current_process().ok_or(Errno::ESRCH)?;
+        if process.threads() != 1 {
+            return Err(Errno::EBUSY);
+        }
+        read_program(&process, &path)?
+    };
+    let name = path.rsplit('/').next().unwrap_or(&path);
+    let argv: Vec<&str> = argv.iter().map(String::as_str).collect();
+    let envp: Vec<&str> = envp.iter().map(String::as_str).collect();
+    process::exec(name, &data, &argv, &envp).map_err(|_| Errno::ENOEXEC)?;
+    Ok(0)
+}
+
+/// Reads the program at `path` for `process` to run, which has to be an
+/// executable regular file.
+fn read_program(process: &Process, path: &str) -> SysResult<Vec<u8>> {
+    let file = fs::open(process.cwd(), path, &OpenOptions::default())?;
+    let metadata = file.metadata();
+    if metadata.kind != FileType::Regular || metadata.mode & 0o111 == 0 {
+        return Err(Errno::EACCES);
+    }
+    file.read_to_end()
+}
+
+/// Reads the null terminated array of strings at `addr`, which may itself
+/// be null for an empty one, taking what they need out of `budget`.
+fn string_array(addr: usize, budget: &mut usize) -> SysResult<Vec<String>> {
+    let mut strings = Vec::new();
+    if addr == 0 {
+        return Ok(strings);
+    }
+    let mut ptr = UserPtr::<usize>::new(addr);
+    loop {
+        let s = ptr.read()?;
+        if s == 0 {
+            return Ok(strings);
+        }
+        // The pointer and the terminating NUL.
+        *budget = budget.checked_sub(size_of::<usize>() + 1).ok_or(Errno::E2BIG)?;
+        let s = match read_user_str(s, *budget) {
+            Err(Errno::ENAMETOOLONG) => return Err(Errno::E2BIG),
+            s => s?,
+        };
+        *budget -= s.len();
+        strings.push(s);
+        ptr = ptr.add(1)?;
+    }
+}
+
+/// `wait4(pid, wstatus, options, rusage)`, `pid` is -1 for any child.
+pub fn sys_wait4(args: &[usize; 6]) -> SysResult {
+    let (pid, wstatus, options) = (args[0] as isize, UserPtr::<i32>::new(args[1]), args[2]);
+    let pid = match pid {
+        -1 => None,
+        pid if pid > 0 => Some(pid as usize),
+        _ => return Err(Errno::EINVAL),
+    };
+    if options & !WNOHANG != 0 {
+        return Err(Errno::EINVAL);
+    }
+
+    match process::wait(pid, options & WNOHANG != 0)? {
+        WaitStatus::Exited { pid, status } => {
+            if !wstatus.is_null() {
+                wstatus.write(&status.wait_status())?;
+            }
+            Ok(pid)
+        }
+        WaitStatus::Running => Ok(0),
+        WaitStatus::NoChild => Err(Errno::ECHILD),
+    }
+}
+
+/// `sched_yield()`
+pub fn sys_sched_yield(_args: &[usize; 6]) -> SysResult {
+    process::yield_now();
+    Ok(0)
+}
+
+/// `nanosleep(req, rem)`, if a signal interrupts the sleep what was left
+/// of it goes in `rem`, unless that is null.
+pub fn sys_nanosleep(args: &[usize; 6]) -> SysResult {
+    let ticks = UserPtr::<Timespec>::new(args[0]).read()?.ticks()?;
+    let rem = UserPtr::<Timespec>::new(args[1]);
+    let end = sched::now() + ticks;
+    if let Err(e) = sched::sleep_until(end) {
+        if !rem.is_null() {
+            rem.write(&Timespec::from_ticks(end.saturating_sub(sched::now())))?;
+        }
+        return Err(e);
+    }
+    Ok(0)
+}
+
+/// `pidfd_open(pid, flags)`, a handle to process `pid`, to signal it with.
+pub fn sys_pidfd_open(args: &[usize; 6]) -> SysResult {
+    let (pid, flags) = (args[0], args[1]);
+    if flags != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let process = process::find(pid).ok_or(Errno::ESRCH)?;
+    let handles = install([Capability::new(Object::Process(process))])?;
+    Ok(handles[0])
+}
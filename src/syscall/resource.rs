@@ -0,0 +1,80 @@
+//! Resource limits and usage.
+
+use crate::{
+    mem::pages::PAGE_SIZE,
+    process::{
+        self,
+        limits::{Resource, Rlimit},
+        sched, thread,
+    },
+};
+
+use super::{errno::Errno, uaccess::UserPtr, SysResult};
+
+const RUSAGE_SELF: isize = 0;
+const RUSAGE_CHILDREN: isize = -1;
+const RUSAGE_THREAD: isize = 1;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct Timeval {
+    sec: i64,
+    usec: i64,
+}
+
+impl Timeval {
+    fn from_ticks(ticks: u64) -> Self {
+        let sec = ticks / sched::TIMEBASE_FREQ;
+        let usec = ticks % sched::TIMEBASE_FREQ * 1_000_000 / sched::TIMEBASE_FREQ;
+        Self { sec: sec as i64, usec: usec as i64 }
+    }
+}
+
+/// Linux's `struct rusage`. There is no telling user from system time, so
+/// all of it is user time, and only the fields set here are kept track of.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct Rusage {
+    utime: Timeval,
+    stime: Timeval,
+    /// In kilobytes.
+    maxrss: i64,
+    rest: [i64; 13],
+}
+
+/// `prlimit64(pid, resource, new_limit, old_limit)`, of the calling process
+/// if `pid` is 0.
+pub fn sys_prlimit64(args: &[usize; 6]) -> SysResult {
+    let (pid, resource) = (args[0], args[1] as Resource);
+    let (new, old) = (UserPtr::<Rlimit>::new(args[2]), UserPtr::<Rlimit>::new(args[3]));
+    let target = match pid {
+        0 => process::current_process(),
+        pid => process::find(pid),
+    };
+    let target = target.ok_or(Errno::ESRCH)?;
+
+    let current = target.limits().get(resource)?;
+    if !new.is_null() {
+        target.set_limit(resource, new.read()?)?;
+    }
+    if !old.is_null() {
+        old.write(&current)?;
+    }
+    Ok(0)
+}
+
+/// `getrusage(who, usage)`
+pub fn sys_getrusage(args: &[usize; 6]) -> SysResult {
+    let (who, out) = (args[0] as isize, UserPtr::<Rusage>::new(args[1]));
+    let process = process::current_process().ok_or(Errno::ESRCH)?;
+    let usage = process.usage();
+    let (ticks, pages) = match who {
+        RUSAGE_SELF => (usage.cpu_time, usage.peak_pages),
+        RUSAGE_CHILDREN => (usage.children_cpu_time, 0),
+        RUSAGE_THREAD => (thread::current().run_time(), usage.peak_pages),
+        _ => return Err(Errno::EINVAL),
+    };
+    let maxrss = (pages * PAGE_SIZE / 1024) as i64;
+    out.write(&Rusage { utime: Timeval::from_ticks(ticks), maxrss, ..Default::default() })?;
+    Ok(0)
+}
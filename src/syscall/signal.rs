@@ -0,0 +1,148 @@
+//! Signals.
+
+use crate::process::{
+    self,
+    handle::{Object, Rights},
+    signal::{self, SigAction, SigSet, Signal, SIGKILL, SIGSTOP},
+    thread, ProcessState,
+};
+
+use super::{errno::Errno, ipc::object, uaccess::UserPtr, SysResult};
+
+const SIG_BLOCK: usize = 0;
+const SIG_UNBLOCK: usize = 1;
+const SIG_SETMASK: usize = 2;
+
+/// Checks a signal number, 0 meaning none, which only checks the target exists.
+fn signal_arg(sig: usize) -> SysResult<Option<Signal>> {
+    match sig {
+        0 => Ok(None),
+        sig if signal::is_valid(sig) => Ok(Some(sig)),
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+/// Sigsets are 64 bits, which callers have to agree with.
+fn check_sigset_size(size: usize) -> SysResult<()> {
+    if size != size_of::<SigSet>() {
+        return Err(Errno::EINVAL);
+    }
+    Ok(())
+}
+
+/// `kill(pid, sig)`, there are no process groups so `pid` has to be a process.
+pub fn sys_kill(args: &[usize; 6]) -> SysResult {
+    let (pid, sig) = (args[0] as isize, signal_arg(args[1])?);
+    if pid <= 0 {
+        return Err(Errno::EINVAL);
+    }
+    let target = process::find(pid as usize).ok_or(Errno::ESRCH)?;
+    if let Some(sig) = sig {
+        signal::send(&target, sig);
+    }
+    Ok(0)
+}
+
+/// `pidfd_send_signal(pidfd, sig, info, flags)`, like `kill` to the process
+/// `pidfd` is a handle with the right to write to. There is no `info` to
+/// pass, nor any flags.
+pub fn sys_pidfd_send_signal(args: &[usize; 6]) -> SysResult {
+    let (sig, info, flags) = (signal_arg(args[1])?, args[2], args[3]);
+    if info != 0 || flags != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let Object::Process(target) = object(args[0], Rights::WRITE)? else {
+        return Err(Errno::EBADF);
+    };
+    if target.state() == ProcessState::Zombie {
+        return Err(Errno::ESRCH);
+    }
+    if let Some(sig) = sig {
+        signal::send(&target, sig);
+    }
+    Ok(0)
+}
+
+/// Sends `sig` to thread `tid`, which has to be in process `tgid` if given.
+fn kill_thread(tgid: Option<usize>, tid: usize, sig: usize) -> SysResult {
+    let sig = signal_arg(sig)?;
+    let target = thread::find(tid).ok_or(Errno::ESRCH)?;
+    match target.process() {
+        Some(p) if tgid.is_none_or(|tgid| p.pid() == tgid) => {}
+        _ => return Err(Errno::ESRCH),
+    }
+    if let Some(sig) = sig {
+        signal::send_to_thread(&target, sig);
+    }
+    Ok(0)
+}
+
+/// `tkill(tid, sig)`
+pub fn sys_tkill(args: &[usize; 6]) -> SysResult {
+    kill_thread(None, args[0], args[1])
+}
+
+/// `tgkill(tgid, tid, sig)`
+pub fn sys_tgkill(args: &[usize; 6]) -> SysResult {
+    kill_thread(Some(args[0]), args[1], args[2])
+}
+
+/// `rt_sigaction(sig, act, oldact, sigsetsize)`
+pub fn sys_rt_sigaction(args: &[usize; 6]) -> SysResult {
+    let (act, oldact) = (UserPtr::<SigAction>::new(args[1]), UserPtr::<SigAction>::new(args[2]));
+    check_sigset_size(args[3])?;
+    let sig = signal_arg(args[0])?.ok_or(Errno::EINVAL)?;
+    let process = process::current_process().ok_or(Errno::ESRCH)?;
+
+    let old = process.signals().action(sig);
+    if !act.is_null() {
+        if sig == SIGKILL || sig == SIGSTOP {
+            return Err(Errno::EINVAL);
+        }
+        process.signals().set_action(sig, act.read()?);
+    }
+    if !oldact.is_null() {
+        oldact.write(&old)?;
+    }
+    Ok(0)
+}
+
+/// `rt_sigprocmask(how, set, oldset, sigsetsize)`
+pub fn sys_rt_sigprocmask(args: &[usize; 6]) -> SysResult {
+    let (how, set, oldset) = (args[0], UserPtr::<SigSet>::new(args[1]), UserPtr::<SigSet>::new(args[2]));
+    check_sigset_size(args[3])?;
+    let thread = thread::current();
+    let signals = thread.signals();
+
+    let old = signals.blocked();
+    if !set.is_null() {
+        let set = set.read()?;
+        let blocked = match how {
+            SIG_BLOCK => old | set,
+            SIG_UNBLOCK => old & !set,
+            SIG_SETMASK => set,
+            _ => return Err(Errno::EINVAL),
+        };
+        signals.set_blocked(blocked);
+    }
+    if !oldset.is_null() {
+        oldset.write(&old)?;
+    }
+    Ok(0)
+}
+
+/// `rt_sigpending(set, sigsetsize)`, the blocked signals waiting to be delivered.
+pub fn sys_rt_sigpending(args: &[usize; 6]) -> SysResult {
+    let set = UserPtr::<SigSet>::new(args[0]);
+    check_sigset_size(args[1])?;
+    let thread = thread::current();
+    let process = thread.process().ok_or(Errno::ESRCH)?;
+    let pending = (thread.signals().pending() | process.signals().pending()) & thread.signals().blocked();
+    set.write(&pending)?;
+    Ok(0)
+}
+
+/// `rt_sigreturn()`, called by the signal trampoline when a handler returns.
+pub fn sys_rt_sigreturn(_args: &[usize; 6]) -> SysResult {
+    signal::sigreturn()
+}
@@ -0,0 +1,83 @@
+//! Timers processes hold handles to, which expire on the monotonic clock.
+
+use alloc::sync::Arc;
+
+use crate::process::{
+    handle::{Capability, Object, Rights},
+    sched,
+    timer::Timer,
+};
+
+use super::{
+    errno::Errno,
+    ipc::{install, object},
+    proc::Timespec,
+    uaccess::UserPtr,
+    SysResult,
+};
+
+const CLOCK_MONOTONIC: usize = 1;
+
+/// `new_value` of `timerfd_settime` is a point in time rather than from now.
+const TFD_TIMER_ABSTIME: usize = 1;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Itimerspec {
+    interval: Timespec,
+    value: Timespec,
+}
+
+fn timer(handle: usize, rights: Rights) -> SysResult<Arc<Timer>> {
+    match object(handle, rights)? {
+        Object::Timer(timer) => Ok(timer),
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+/// The setting of `timer`, with the time left until it expires.
+fn setting(timer: &Timer) -> Itimerspec {
+    let (deadline, interval) = timer.get();
+    let left = deadline.map_or(0, |deadline| deadline.saturating_sub(sched::now()).max(1));
+    Itimerspec { interval: Timespec::from_ticks(interval), value: Timespec::from_ticks(left) }
+}
+
+/// `timerfd_create(clockid, flags)`, a handle to a timer that is not armed.
+/// Reading it waits for it to expire.
+pub fn sys_timerfd_create(args: &[usize; 6]) -> SysResult {
+    let (clock, flags) = (args[0], args[1]);
+    if clock != CLOCK_MONOTONIC || flags != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let handles = install([Capability::new(Object::Timer(Arc::new(Timer::new())))])?;
+    Ok(handles[0])
+}
+
+/// `timerfd_settime(fd, flags, new_value, old_value)`, arms the timer, or
+/// disarms it if `new_value` is 0.
+pub fn sys_timerfd_settime(args: &[usize; 6]) -> SysResult {
+    let (timer, flags) = (timer(args[0], Rights::WRITE)?, args[1]);
+    let (new, old) = (UserPtr::<Itimerspec>::new(args[2]).read()?, UserPtr::<Itimerspec>::new(args[3]));
+    if flags & !TFD_TIMER_ABSTIME != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let (value, interval) = (new.value.ticks()?, new.interval.ticks()?);
+    let deadline = match value {
+        0 => None,
+        _ if flags & TFD_TIMER_ABSTIME != 0 => Some(value),
+        _ => Some(sched::now() + value),
+    };
+
+    if !old.is_null() {
+        old.write(&setting(&timer))?;
+    }
+    timer.set(deadline, interval);
+    Ok(0)
+}
+
+/// `timerfd_gettime(fd, curr_value)`
+pub fn sys_timerfd_gettime(args: &[usize; 6]) -> SysResult {
+    let timer = timer(args[0], Rights::READ)?;
+    UserPtr::<Itimerspec>::new(args[1]).write(&setting(&timer))?;
+    Ok(0)
+}
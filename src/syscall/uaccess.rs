@@ -0,0 +1,203 @@
+//! Copying data between the kernel and the calling process.
+//!
+//! System calls never dereference user pointers themselves. They wrap them
+//! in a [`UserPtr`] or [`UserSlice`], which check that the range lies within
+//! user memory, and copy through `copy_user` (see `asm/uaccess.s`) with
+//! `sstatus.SUM` set for just as long as the copy takes. A page fault while
+//! copying is not a kernel bug but a bad pointer: the trap handler finds the
+//! faulting instruction in the exception table and resumes at its fixup,
+//! which makes the copy come up short and the system call fail with `EFAULT`.
+
+use alloc::{string::String, vec, vec::Vec};
+use core::marker::PhantomData;
+
+use crate::{
+    cpu::{csr::ControlStatusRegister, SSTATUS_SUM},
+    mem::space,
+};
+
+use super::{errno::Errno, SysResult};
+
+extern "C" {
+    fn copy_user(dst: *mut u8, src: *const u8, len: usize) -> usize;
+
+    static __ex_table_start: [FixupEntry; 0];
+    static __ex_table_end: [FixupEntry; 0];
+}
+
+/// Entry of the exception table: where to resume when `insn` faults.
+#[repr(C)]
+struct FixupEntry {
+    insn: usize,
+    fixup: usize,
+}
+
+/// Where to resume after an instruction at `pc` faulted on user memory,
+/// if it is one that is allowed to.
+pub fn search_fixup(pc: usize) -> Option<usize> {
+    let table = unsafe {
+        let start = __ex_table_start.as_ptr();
+        let len = __ex_table_end.as_ptr().offset_from(start) as usize;
+        core::slice::from_raw_parts(start, len)
+    };
+    table.iter().find(|e| e.insn == pc).map(|e| e.fixup)
+}
+
+/// Runs `copy_user` with access to user memory, failing if any of it faulted.
+fn user_copy(dst: *mut u8, src: *const u8, len: usize) -> SysResult<()> {
+    let had_sum = ControlStatusRegister::SStatus.read() & SSTATUS_SUM != 0;
+    ControlStatusRegister::SStatus.set_bits(SSTATUS_SUM);
+    let left = unsafe { copy_user(dst, src, len) };
+    if !had_sum {
+        ControlStatusRegister::SStatus.clear_bits(SSTATUS_SUM);
+    }
+
+    match left {
+        0 => Ok(()),
+        _ => Err(Errno::EFAULT),
+    }
+}
+
+/// Copies `buf.len()` bytes from user memory at `addr`.
+pub fn copy_from_user(addr: usize, buf: &mut [u8]) -> SysResult<()> {
+    UserSlice::new(addr, buf.len())?.read(buf)
+}
+
+/// Copies `data` to user memory at `addr`.
+pub fn copy_to_user(addr: usize, data: &[u8]) -> SysResult<()> {
+    UserSlice::new(addr, data.len())?.write(data)
+}
+
+/// A range of bytes in the calling process' memory.
+#[derive(Debug, Clone, Copy)]
+pub struct UserSlice {
+    addr: usize,
+    len: usize,
+}
+
+impl UserSlice {
+    /// Fails with `EFAULT` unless the whole range is user memory.
+    /// Whether it is mapped is only found out when copying.
+    pub fn new(addr: usize, len: usize) -> SysResult<Self> {
+        if len != 0 && !space::is_user_range(addr, len) {
+            return Err(Errno::EFAULT);
+        }
+        Ok(Self { addr, len })
+    }
+
+    pub fn addr(&self) -> usize {
+        self.addr
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The part of the slice `len` bytes long, `offset` bytes in.
+    pub fn subslice(&self, offset: usize, len: usize) -> SysResult<Self> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.len => Ok(Self { addr: self.addr + offset, len }),
+            _ => Err(Errno::EFAULT),
+        }
+    }
+
+    /// Copies the start of the slice into `buf`, which may not be longer.
+    pub fn read(&self, buf: &mut [u8]) -> SysResult<()> {
+        if buf.len() > self.len {
+            return Err(Errno::EFAULT);
+        }
+        user_copy(buf.as_mut_ptr(), self.addr as *const u8, buf.len())
+    }
+
+    /// Copies the whole slice into a new buffer.
+    pub fn read_to_vec(&self) -> SysResult<Vec<u8>> {
+        let mut buf = vec![0; self.len];
+        self.read(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Copies `data` to the start of the slice, which may not be shorter.
+    pub fn write(&self, data: &[u8]) -> SysResult<()> {
+        if data.len() > self.len {
+            return Err(Errno::EFAULT);
+        }
+        user_copy(self.addr as *mut u8, data.as_ptr(), data.len())
+    }
+}
+
+/// A pointer to a `T` in the calling process' memory. `T` has to be plain
+/// data, any bit pattern user code leaves there must be a valid `T`.
+#[derive(Debug)]
+pub struct UserPtr<T> {
+    addr: usize,
+    _marker: PhantomData<*mut T>,
+}
+
+impl<T> Clone for UserPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for UserPtr<T> {}
+
+impl<T: Copy> UserPtr<T> {
+    pub const fn new(addr: usize) -> Self {
+        Self { addr, _marker: PhantomData }
+    }
+
+    pub fn addr(&self) -> usize {
+        self.addr
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.addr == 0
+    }
+
+    /// The pointer `n` elements further along.
+    pub fn add(&self, n: usize) -> SysResult<Self> {
+        n.checked_mul(size_of::<T>())
+            .and_then(|off| self.addr.checked_add(off))
+            .map(Self::new)
+            .ok_or(Errno::EFAULT)
+    }
+
+    fn slice(&self) -> SysResult<UserSlice> {
+        UserSlice::new(self.addr, size_of::<T>())
+    }
+
+    pub fn read(&self) -> SysResult<T> {
+        let mut val = core::mem::MaybeUninit::<T>::uninit();
+        let slice = self.slice()?;
+        user_copy(val.as_mut_ptr() as *mut u8, slice.addr as *const u8, slice.len)?;
+        Ok(unsafe { val.assume_init() })
+    }
+
+    pub fn write(&self, val: &T) -> SysResult<()> {
+        let slice = self.slice()?;
+        user_copy(slice.addr as *mut u8, val as *const T as *const u8, slice.len)
+    }
+}
+
+/// Reads a NUL terminated string of at most `max` bytes from user memory.
+pub fn read_user_str(addr: usize, max: usize) -> SysResult<String> {
+    let mut bytes = Vec::new();
+    let mut ptr = UserPtr::<u8>::new(addr);
+    loop {
+        let b = ptr.read()?;
+        if b == 0 {
+            break;
+        }
+        if bytes.len() == max {
+            return Err(Errno::ENAMETOOLONG);
+        }
+        bytes.push(b);
+        ptr = ptr.add(1)?;
+    }
+    String::from_utf8(bytes).map_err(|_| Errno::EINVAL)
+}
+
@@ -30,9 +30,7 @@ impl Error for WalnutError {
 
 impl From<AllocationError> for WalnutError {
     fn from(value: AllocationError) -> Self {
-        // this is likely violating the geneva convention
-        // TODO Make this cleaner!
-        Self::new(format_args!("Allocation Error: {}", value.description()).as_str().unwrap())
+        Self::new(value.details())
     }
 }
 
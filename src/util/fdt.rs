@@ -0,0 +1,134 @@
+//! Reading the flattened device tree the firmware hands us.
+//!
+//! This only looks properties up by path, which is all the kernel and the
+//! firmware need so far, and allocates nothing so it works before the heap
+//! is set up, or in M-mode.
+
+use core::ffi::CStr;
+
+use super::error::WalnutError;
+
+const MAGIC: u32 = 0xd00dfeed;
+
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+
+pub struct DeviceTree {
+    data: &'static [u8],
+    structs: usize,
+    strings: usize,
+}
+
+impl DeviceTree {
+    /// The device tree at physical address `addr`.
+    ///
+    /// # Safety
+    /// `addr` has to point at memory that is readable for as long as the
+    /// tree is used, like where the firmware left it before it is reused.
+    pub unsafe fn from_addr(addr: usize) -> crate::Result<Self> {
+        if addr == 0 || !addr.is_multiple_of(8) {
+            return Err(WalnutError::new("Device tree address is not valid"));
+        }
+        let header = core::slice::from_raw_parts(addr as *const u8, 40);
+        if read_u32(header, 0) != Some(MAGIC) {
+            return Err(WalnutError::new("Device tree has a bad magic number"));
+        }
+        let size = read_u32(header, 4).unwrap_or(0) as usize;
+        let data = core::slice::from_raw_parts(addr as *const u8, size);
+        Ok(Self {
+            data,
+            structs: read_u32(header, 8).unwrap_or(0) as usize,
+            strings: read_u32(header, 12).unwrap_or(0) as usize,
+        })
+    }
+
+    /// The value of the property `name` of the node at `path`, like
+    /// `/chosen`. Nodes match with or without their unit address.
+    pub fn property(&self, path: &str, name: &str) -> Option<&'static [u8]> {
+        let mut found = None;
+        self.walk(path, 1, name, |value| {
+            found = Some(value);
+            false
+        });
+        found
+    }
+
+    /// Calls `f` with the value of the property `name` of every child of
+    /// the node at `path` that has one, like the `reg` of each of `/cpus`.
+    pub fn for_each_child(&self, path: &str, name: &str, mut f: impl FnMut(&'static [u8])) {
+        self.walk(path, 2, name, |value| {
+            f(value);
+            true
+        });
+    }
+
+    /// Calls `f` with the value of every property `name` of nodes `level`
+    /// levels down from the one at `path` (1 for that node itself), for as
+    /// long as it returns `true`.
+    fn walk(&self, path: &str, level: usize, name: &str, mut f: impl FnMut(&'static [u8]) -> bool) -> Option<()> {
+        let components = || path.split('/').filter(|c| !c.is_empty());
+        let wanted = components().count();
+        let mut offset = self.structs;
+        // How many nodes are open, the root included, and how many of
+        // those under the root are the ones on the path.
+        let mut depth = 0;
+        let mut matched = 0;
+        loop {
+            let token = read_u32(self.data, offset)?;
+            offset += 4;
+            match token {
+                FDT_BEGIN_NODE => {
+                    let node = self.str_at(offset)?;
+                    offset += align4(node.len() + 1);
+                    if depth > 0 && matched == depth - 1 && components().nth(depth - 1).is_some_and(|c| node_is(node, c)) {
+                        matched = depth;
+                    }
+                    depth += 1;
+                }
+                FDT_END_NODE => {
+                    depth = depth.checked_sub(1)?;
+                    matched = matched.min(depth.saturating_sub(1));
+                }
+                FDT_PROP => {
+                    let len = read_u32(self.data, offset)? as usize;
+                    let name_offset = read_u32(self.data, offset + 4)? as usize;
+                    let value = self.data.get(offset + 8..offset + 8 + len)?;
+                    offset += 8 + align4(len);
+                    if matched == wanted && depth == wanted + level && self.str_at(self.strings + name_offset)? == name && !f(value) {
+                        return Some(());
+                    }
+                }
+                FDT_NOP => {}
+                // The end of the tree, or something we can not make sense of.
+                _ => return None,
+            }
+        }
+    }
+
+    fn str_at(&self, offset: usize) -> Option<&'static str> {
+        CStr::from_bytes_until_nul(self.data.get(offset..)?).ok()?.to_str().ok()
+    }
+}
+
+/// A property value of one or two cells, as addresses and sizes are.
+pub fn read_cells(value: &[u8]) -> Option<u64> {
+    match value.len() {
+        4 => read_u32(value, 0).map(u64::from),
+        8 => Some(u64::from_be_bytes(value.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn align4(n: usize) -> usize {
+    n.next_multiple_of(4)
+}
+
+fn node_is(node: &str, name: &str) -> bool {
+    node == name || node.split('@').next() == Some(name)
+}
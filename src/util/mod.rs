@@ -1,13 +1,14 @@
 mod panic;
 pub mod error;
+pub mod fdt;
 
 pub type Result<T> = core::result::Result<T, error::WalnutError>;
 
-/// Runs a given code block ONLY in hardware thread 0
+/// Runs a given code block ONLY in the boot hart (see `cpu::boot_hart`)
 #[macro_export]
 macro_rules! main_thread_only {
     ($block:block) => {
-        if unsafe { $crate::cpu::util::my_hart() } == 0 {
+        if unsafe { $crate::cpu::util::my_hart() } == $crate::cpu::boot_hart() {
             $block
         }
     };